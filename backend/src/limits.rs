@@ -0,0 +1,37 @@
+use super::*;
+
+#[get("/limits")]
+async fn limits(event_sender: web::Data<Sender<RsbtCommand>>, _user: User) -> impl Responder {
+    let result = torrent_command_result(event_sender, (), RsbtCommand::BandwidthLimits).await;
+    match result {
+        Ok(limits) => HttpResponse::Ok().json(limits),
+        Err(err) => HttpResponse::InternalServerError().json(Failure {
+            error: format!("{}", err),
+        }),
+    }
+}
+
+#[put("/limits")]
+async fn limits_update(
+    event_sender: web::Data<Sender<RsbtCommand>>,
+    body: web::Json<Limits>,
+    _user: User,
+) -> impl Responder {
+    let result = torrent_command_result(
+        event_sender,
+        body.into_inner(),
+        RsbtCommand::SetBandwidthLimits,
+    )
+    .await;
+    match result {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err @ RsbtError::InvalidTimeOfDay(_)) => {
+            HttpResponse::UnprocessableEntity().json(Failure {
+                error: format!("{}", err),
+            })
+        }
+        Err(err) => HttpResponse::InternalServerError().json(Failure {
+            error: format!("{}", err),
+        }),
+    }
+}
@@ -8,8 +8,10 @@ use actix::prelude::*;
 use actix_identity::{CookieIdentityPolicy, Identity, IdentityService};
 use actix_multipart::Multipart;
 use actix_web::{
-    dev::Payload, error::ErrorUnauthorized, http, middleware, web, App, Error, FromRequest,
-    HttpRequest, HttpResponse, HttpServer, Responder,
+    dev::Payload,
+    error::{BlockingError, ErrorUnauthorized},
+    http, middleware, web, App, Error, FromRequest, HttpRequest, HttpResponse, HttpServer,
+    Responder,
 };
 use bytes::Bytes;
 use dotenv::dotenv;
@@ -20,6 +22,7 @@ use openid::{DiscoveredClient, Options, Token, Userinfo};
 use reqwest;
 use rsbt_service::{
     app::{events::TorrentEvent, *},
+    creator::{create_torrent, shared_path, TorrentCreateOptions},
     types::*,
     RsbtError,
 };
@@ -44,19 +47,23 @@ use url::Url;
 
 mod cli;
 mod event_stream;
+mod limits;
 mod login;
 mod model;
 #[cfg(feature = "sandbox")]
 mod sandbox;
 mod session;
+mod statistics;
 mod torrents;
 mod uploads;
 
 use event_stream::*;
+use limits::*;
 use login::*;
 #[cfg(feature = "sandbox")]
 use sandbox::*;
 use session::*;
+use statistics::*;
 use torrents::*;
 use uploads::*;
 
@@ -118,6 +125,7 @@ async fn main() -> Result<(), ExitFailure> {
     let sessions = web::Data::new(Sessions::new(&properties, local).await?);
 
     let storage_path = properties.storage.clone();
+    let share_roots = web::Data::new(ShareRoots(properties.share_roots()));
 
     let rsbt_app = RsbtApp::new(properties);
 
@@ -137,6 +145,12 @@ async fn main() -> Result<(), ExitFailure> {
                 data,
                 filename: torrent.file,
                 state: torrent.state,
+                priorities: torrent.priorities,
+                sequential: torrent.sequential,
+                backend: torrent.backend,
+                preallocation: torrent.preallocation,
+                category: torrent.category,
+                save_to: torrent.save_to,
             });
 
             download_events_sender
@@ -170,10 +184,12 @@ async fn main() -> Result<(), ExitFailure> {
             .app_data(sessions.clone())
             .app_data(sender.clone())
             .app_data(broadcaster_sender.clone())
+            .app_data(share_roots.clone())
             .service(authorize)
             .service(login_get)
             .service(
                 web::scope("/api")
+                    .service(torrent_create)
                     .service(torrent_list)
                     .service(torrent_detail)
                     .service(torrent_delete)
@@ -181,8 +197,14 @@ async fn main() -> Result<(), ExitFailure> {
                     .service(torrent_peer_list)
                     .service(torrent_announce_list)
                     .service(torrent_file_list)
+                    .service(torrent_file_priority)
+                    .service(torrent_sequential)
+                    .service(torrent_move)
                     .service(torrent_piece_list)
                     .service(torrent_file_download)
+                    .service(limits)
+                    .service(limits_update)
+                    .service(statistics)
                     .service(upload)
                     .service(account)
                     .service(logout)
@@ -278,6 +300,8 @@ fn init_broadcaster() -> (web::Data<Broadcaster>, Sender<BroadcasterMessage>) {
                                     read: x.bytes_read,
                                     write: x.bytes_write,
                                     left: x.pieces_left,
+                                    checking: x.checking,
+                                    moving: x.moving,
                                 })
                                 .boxed(),
                             torrent_download
@@ -288,6 +312,14 @@ fn init_broadcaster() -> (web::Data<Broadcaster>, Sender<BroadcasterMessage>) {
                                     rx: x.downloaded,
                                 })
                                 .boxed(),
+                            torrent_download
+                                .status_watch
+                                .map(move |x| TorrentEvent::Status {
+                                    id,
+                                    active: x == TorrentDownloadStatus::Enabled,
+                                    error: x.error(),
+                                })
+                                .boxed(),
                         ]);
                         while let Some(message) = messages.next().await {
                             if let Err(err) = task_broadcaster_sender
@@ -0,0 +1,12 @@
+use super::*;
+
+#[get("/statistics")]
+async fn statistics(event_sender: web::Data<Sender<RsbtCommand>>, _user: User) -> impl Responder {
+    let result = torrent_command_result(event_sender, (), RsbtCommand::Statistics).await;
+    match result {
+        Ok(statistics) => HttpResponse::Ok().json(statistics),
+        Err(err) => HttpResponse::InternalServerError().json(Failure {
+            error: format!("{}", err),
+        }),
+    }
+}
@@ -229,7 +229,7 @@ async fn torrent_delete(
     }
 }
 
-async fn torrent_command_result<T, F, R>(
+pub(crate) async fn torrent_command_result<T, F, R>(
     event_sender: web::Data<Sender<RsbtCommand>>,
     data: T,
     cmd: F,
@@ -326,6 +326,104 @@ async fn torrent_file_list(
     .await
 }
 
+#[derive(Serialize, Deserialize)]
+struct SequentialUpdate {
+    pub sequential: bool,
+}
+
+#[put("/torrent/{id}/sequential")]
+async fn torrent_sequential(
+    event_sender: web::Data<Sender<RsbtCommand>>,
+    id: web::Path<usize>,
+    body: web::Json<SequentialUpdate>,
+    _user: User,
+) -> impl Responder {
+    torrent_command(
+        event_sender,
+        RsbtCommandTorrentSequential {
+            id: *id,
+            sequential: body.sequential,
+        },
+        RsbtCommand::TorrentSequential,
+    )
+    .await
+}
+
+#[derive(Serialize, Deserialize)]
+struct MoveRequest {
+    pub save_to: PathBuf,
+}
+
+#[post("/torrent/{id}/move")]
+async fn torrent_move(
+    event_sender: web::Data<Sender<RsbtCommand>>,
+    share_roots: web::Data<ShareRoots>,
+    id: web::Path<usize>,
+    body: web::Json<MoveRequest>,
+    _user: User,
+) -> impl Responder {
+    let save_to = match shared_save_to(&share_roots, body.into_inner().save_to).await {
+        Ok(save_to) => save_to,
+        Err(response) => return response,
+    };
+    let result = torrent_command_result(
+        event_sender,
+        RsbtCommandTorrentMove { id: *id, save_to },
+        RsbtCommand::TorrentMove,
+    )
+    .await;
+
+    match result {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err @ RsbtError::TorrentNotFound(_)) => HttpResponse::NotFound().json(Failure {
+            error: format!("{}", err),
+        }),
+        Err(err @ RsbtError::StorageMoveInProgress) => HttpResponse::Conflict().json(Failure {
+            error: format!("{}", err),
+        }),
+        Err(err) => HttpResponse::InternalServerError().json(Failure {
+            error: format!("{}", err),
+        }),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FilePriorityUpdate {
+    pub priority: FilePriority,
+}
+
+#[put("/torrent/{id}/file/{file_id}")]
+async fn torrent_file_priority(
+    event_sender: web::Data<Sender<RsbtCommand>>,
+    ids: web::Path<(usize, usize)>,
+    body: web::Json<FilePriorityUpdate>,
+    _user: User,
+) -> impl Responder {
+    let (id, file_id) = *ids;
+    let result = torrent_command_result(
+        event_sender,
+        RsbtCommandTorrentFilePriority {
+            id,
+            file_id,
+            priority: body.priority,
+        },
+        RsbtCommand::TorrentFilePriority,
+    )
+    .await;
+
+    match result {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err @ RsbtError::TorrentNotFound(_)) | Err(err @ RsbtError::TorrentFileNotFound(_)) => {
+            HttpResponse::NotFound().json(Failure {
+                error: format!("{}", err),
+            })
+        }
+        Err(err) => HttpResponse::InternalServerError().json(Failure {
+            error: format!("{}", err),
+        }),
+    }
+}
+
 #[get("/torrent/{id}/piece")]
 async fn torrent_piece_list(
     event_sender: web::Data<Sender<RsbtCommand>>,
@@ -349,8 +447,69 @@ async fn torrent_piece_list(
     }
 }
 
+const BYTES_UNIT: &str = "bytes=";
+
+/// Byte range requested with `Range` header.
+#[derive(Debug, PartialEq)]
+enum RequestedRange {
+    /// No range or range which is ignored (malformed or multiple ranges)
+    Whole,
+    /// Bytes `start..end`
+    Bytes {
+        start: usize,
+        end: usize,
+    },
+    NotSatisfiable,
+}
+
+impl RequestedRange {
+    fn parse(value: Option<&str>, size: usize) -> Self {
+        let value = match value {
+            Some(value) if value.trim().starts_with(BYTES_UNIT) => value.trim(),
+            _ => return RequestedRange::Whole,
+        };
+        let range = &value[BYTES_UNIT.len()..];
+        if range.contains(',') {
+            return RequestedRange::Whole;
+        }
+
+        let mut parts = range.splitn(2, '-');
+        let (start, end) = match (parts.next(), parts.next()) {
+            (Some(start), Some(end)) => (start.trim(), end.trim()),
+            _ => return RequestedRange::Whole,
+        };
+
+        // range with last byte before first one is invalid and ignored as malformed one
+        let bounds = if start.is_empty() {
+            end.parse::<usize>()
+                .ok()
+                .map(|suffix| (size.saturating_sub(suffix), size))
+        } else {
+            let last = if end.is_empty() {
+                Ok(None)
+            } else {
+                end.parse::<usize>().map(Some)
+            };
+            match (start.parse::<usize>(), last) {
+                (Ok(start), Ok(None)) => Some((start, size)),
+                (Ok(start), Ok(Some(last))) if start <= last => {
+                    Some((start, last.saturating_add(1).min(size)))
+                }
+                _ => None,
+            }
+        };
+
+        match bounds {
+            Some((start, end)) if start < end => RequestedRange::Bytes { start, end },
+            Some(_) => RequestedRange::NotSatisfiable,
+            None => RequestedRange::Whole,
+        }
+    }
+}
+
 #[get("/torrent/{id}/file/{file_id}/download")]
 async fn torrent_file_download(
+    req: HttpRequest,
     event_sender: web::Data<Sender<RsbtCommand>>,
     ids: web::Path<(usize, usize)>,
     _user: User,
@@ -364,19 +523,47 @@ async fn torrent_file_download(
     .await;
 
     match download_stream {
-        Ok(download_stream) => HttpResponse::Ok()
-            .keep_alive()
-            .no_chunking()
-            .set_header(
-                http::header::CONTENT_DISPOSITION,
-                format!("attachment; filename=\"{}\"", download_stream.name),
-            )
-            .content_length(download_stream.size as u64)
-            .streaming(download_stream.map_err(|x| {
-                actix_web::Error::from(HttpResponse::InternalServerError().json(Failure {
-                    error: format!("{}", x),
+        Ok(download_stream) => {
+            let size = download_stream.size;
+            let range = RequestedRange::parse(
+                req.headers()
+                    .get(http::header::RANGE)
+                    .and_then(|x| x.to_str().ok()),
+                size,
+            );
+
+            let (mut response, download_stream) = match range {
+                RequestedRange::Whole => (HttpResponse::Ok(), download_stream),
+                RequestedRange::Bytes { start, end } => {
+                    let mut response = HttpResponse::PartialContent();
+                    response.set_header(
+                        http::header::CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", start, end - 1, size),
+                    );
+                    (response, download_stream.with_range(start, end))
+                }
+                RequestedRange::NotSatisfiable => {
+                    return HttpResponse::RangeNotSatisfiable()
+                        .set_header(http::header::CONTENT_RANGE, format!("bytes */{}", size))
+                        .finish();
+                }
+            };
+
+            response
+                .keep_alive()
+                .no_chunking()
+                .set_header(http::header::ACCEPT_RANGES, "bytes")
+                .set_header(
+                    http::header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}\"", download_stream.name),
+                )
+                .content_length(download_stream.left as u64)
+                .streaming(download_stream.map_err(|x| {
+                    actix_web::Error::from(HttpResponse::InternalServerError().json(Failure {
+                        error: format!("{}", x),
+                    }))
                 }))
-            })),
+        }
         Err(err @ RsbtError::TorrentNotFound(_)) | Err(err @ RsbtError::TorrentFileNotFound(_)) => {
             HttpResponse::NotFound().json(Failure {
                 error: format!("{}", err),
@@ -387,3 +574,69 @@ async fn torrent_file_download(
         }),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_requested_range() {
+        assert_eq!(RequestedRange::parse(None, 10), RequestedRange::Whole);
+        assert_eq!(
+            RequestedRange::parse(Some("bytes=2-5"), 10),
+            RequestedRange::Bytes { start: 2, end: 6 }
+        );
+        assert_eq!(
+            RequestedRange::parse(Some("bytes=2-"), 10),
+            RequestedRange::Bytes { start: 2, end: 10 }
+        );
+        assert_eq!(
+            RequestedRange::parse(Some("bytes=-3"), 10),
+            RequestedRange::Bytes { start: 7, end: 10 }
+        );
+        assert_eq!(
+            RequestedRange::parse(Some("bytes=5-20"), 10),
+            RequestedRange::Bytes { start: 5, end: 10 }
+        );
+    }
+
+    #[test]
+    fn invalid_range_is_ignored() {
+        assert_eq!(
+            RequestedRange::parse(Some("bytes=5-3"), 10),
+            RequestedRange::Whole
+        );
+        assert_eq!(
+            RequestedRange::parse(Some("bytes=20-3"), 10),
+            RequestedRange::Whole
+        );
+        assert_eq!(
+            RequestedRange::parse(Some("bytes=a-3"), 10),
+            RequestedRange::Whole
+        );
+        assert_eq!(
+            RequestedRange::parse(Some("bytes=1-2,4-5"), 10),
+            RequestedRange::Whole
+        );
+        assert_eq!(
+            RequestedRange::parse(Some("items=1-2"), 10),
+            RequestedRange::Whole
+        );
+    }
+
+    #[test]
+    fn range_past_end_is_not_satisfiable() {
+        assert_eq!(
+            RequestedRange::parse(Some("bytes=10-"), 10),
+            RequestedRange::NotSatisfiable
+        );
+        assert_eq!(
+            RequestedRange::parse(Some("bytes=12-15"), 10),
+            RequestedRange::NotSatisfiable
+        );
+        assert_eq!(
+            RequestedRange::parse(Some("bytes=-0"), 10),
+            RequestedRange::NotSatisfiable
+        );
+    }
+}
@@ -1,12 +1,28 @@
 use super::*;
 
+#[derive(Deserialize)]
+struct UploadQuery {
+    preallocation: Option<Preallocation>,
+    category: Option<String>,
+    save_to: Option<PathBuf>,
+}
+
 #[post("/upload")]
 async fn upload(
     _user: User,
     event_sender: web::Data<Sender<RsbtCommand>>,
     broadcaster_sender: web::Data<Sender<BroadcasterMessage>>,
+    share_roots: web::Data<ShareRoots>,
+    query: web::Query<UploadQuery>,
     mut payload: Multipart,
 ) -> Result<HttpResponse, Error> {
+    let save_to = match query.save_to.clone() {
+        Some(save_to) => match shared_save_to(&share_roots, save_to).await {
+            Ok(save_to) => Some(save_to),
+            Err(response) => return Ok(response),
+        },
+        None => None,
+    };
     if let Some(item) = payload.next().await {
         let mut field = item?;
         let content_type = field.content_disposition().unwrap();
@@ -18,50 +34,181 @@ async fn upload(
             torrent.extend(&data);
         }
 
-        let (request_response, receiver) = RequestResponse::new(RsbtCommandAddTorrent {
+        let command = RsbtCommandAddTorrent {
             data: torrent,
             filename: filename.to_string(),
             state: TorrentDownloadStatus::Enabled,
-        });
-        {
-            let mut event_sender = event_sender.as_ref().clone();
-            if let Err(err) = event_sender
-                .send(RsbtCommand::AddTorrent(request_response))
+            priorities: vec![],
+            sequential: false,
+            backend: None,
+            preallocation: query.preallocation,
+            category: query.category.clone(),
+            save_to,
+        };
+        return Ok(
+            match add_torrent(&event_sender, &broadcaster_sender, command).await {
+                Ok(torrent_view) => HttpResponse::Ok().json(torrent_view),
+                Err(response) => response,
+            },
+        );
+    }
+    Ok(HttpResponse::UnprocessableEntity().into())
+}
+
+/// Adds torrent and subscribes to its events, failure is returned as response.
+async fn add_torrent(
+    event_sender: &Sender<RsbtCommand>,
+    broadcaster_sender: &Sender<BroadcasterMessage>,
+    command: RsbtCommandAddTorrent,
+) -> Result<TorrentDownloadView, HttpResponse> {
+    let (request_response, receiver) = RequestResponse::new(command);
+    if let Err(err) = event_sender
+        .clone()
+        .send(RsbtCommand::AddTorrent(request_response))
+        .await
+    {
+        error!("cannot send to torrent process: {}", err);
+        return Err(HttpResponse::InternalServerError().json(Failure {
+            error: format!("cannot send to torrent process: {}", err),
+        }));
+    }
+
+    match receiver.await {
+        Ok(Ok(ref torrent)) => {
+            let torrent_view: TorrentDownloadView = torrent.into();
+            if let Err(err) = broadcaster_sender
+                .clone()
+                .send(BroadcasterMessage::Subscribe(torrent.clone()))
                 .await
             {
-                error!("cannot send to torrent process: {}", err);
-                return Ok(HttpResponse::InternalServerError().json(Failure {
-                    error: format!("cannot send to torrent process: {}", err),
-                }));
+                error!("cannot send subscribe message: {}", err);
             }
+            Ok(torrent_view)
+        }
+        Ok(Err(err @ RsbtError::NotEnoughSpace { .. })) => Err(HttpResponse::InsufficientStorage()
+            .json(Failure {
+                error: format!("cannot add torrent process: {}", err),
+            })),
+        Ok(Err(err @ RsbtError::InvalidCategory(_)))
+        | Ok(Err(err @ RsbtError::InvalidTorrentInfo(_))) => {
+            Err(HttpResponse::BadRequest().json(Failure {
+                error: format!("cannot add torrent process: {}", err),
+            }))
+        }
+        Ok(Err(err)) => {
+            error!("error in update call: {}", err);
+            Err(HttpResponse::InternalServerError().json(Failure {
+                error: format!("cannot add torrent process: {}", err),
+            }))
         }
+        Err(err) => {
+            error!("error in receiver: {}", err);
+            Err(HttpResponse::InternalServerError().json(Failure {
+                error: format!("cannot receive from add torrent process: {}", err),
+            }))
+        }
+    }
+}
 
-        return Ok(match receiver.await {
-            Ok(Ok(ref torrent)) => {
-                let torrent_view: TorrentDownloadView = torrent.into();
-                if let Err(err) = broadcaster_sender
-                    .as_ref()
-                    .clone()
-                    .send(BroadcasterMessage::Subscribe(torrent.clone()))
-                    .await
-                {
-                    error!("cannot send subscribe message: {}", err);
-                }
-                HttpResponse::Ok().json(torrent_view)
-            }
-            Ok(Err(err)) => {
-                error!("error in update call: {}", err);
-                HttpResponse::InternalServerError().json(Failure {
-                    error: format!("cannot add torrent process: {}", err),
-                })
-            }
-            Err(err) => {
-                error!("error in receiver: {}", err);
-                HttpResponse::InternalServerError().json(Failure {
-                    error: format!("cannot receive from add torrent process: {}", err),
-                })
-            }
-        });
+/// Directories, which files may be shared by creating torrents, or downloaded into.
+pub(crate) struct ShareRoots(pub(crate) Vec<PathBuf>);
+
+/// Resolves download path requested by user, failure is returned as response.
+///
+/// Path must be inside of shared directories, as path of created torrent.
+pub(crate) async fn shared_save_to(
+    share_roots: &ShareRoots,
+    save_to: PathBuf,
+) -> Result<PathBuf, HttpResponse> {
+    let roots = share_roots.0.clone();
+    match web::block(move || shared_path(&save_to, &roots)).await {
+        Ok(save_to) => Ok(save_to),
+        Err(BlockingError::Error(err @ RsbtError::PathNotShared(_))) => {
+            Err(HttpResponse::Forbidden().json(Failure {
+                error: format!("{}", err),
+            }))
+        }
+        Err(BlockingError::Error(err @ RsbtError::IO(_))) => {
+            Err(HttpResponse::BadRequest().json(Failure {
+                error: format!("{}", err),
+            }))
+        }
+        Err(err) => {
+            error!("cannot resolve download path: {}", err);
+            Err(HttpResponse::InternalServerError().json(Failure {
+                error: format!("{}", err),
+            }))
+        }
     }
-    Ok(HttpResponse::UnprocessableEntity().into())
+}
+
+#[derive(Deserialize)]
+struct CreateQuery {
+    #[serde(default)]
+    seed: bool,
+    preallocation: Option<Preallocation>,
+}
+
+#[post("/torrent/create")]
+async fn torrent_create(
+    _user: User,
+    event_sender: web::Data<Sender<RsbtCommand>>,
+    broadcaster_sender: web::Data<Sender<BroadcasterMessage>>,
+    share_roots: web::Data<ShareRoots>,
+    query: web::Query<CreateQuery>,
+    body: web::Json<TorrentCreateOptions>,
+) -> Result<HttpResponse, Error> {
+    let mut options = body.into_inner();
+    let roots = share_roots.0.clone();
+    let created = match web::block(move || {
+        options.path = shared_path(&options.path, &roots)?;
+        create_torrent(&options)
+    })
+    .await
+    {
+        Ok(created) => created,
+        Err(BlockingError::Error(err @ RsbtError::PathNotShared(_))) => {
+            return Ok(HttpResponse::Forbidden().json(Failure {
+                error: format!("cannot create torrent: {}", err),
+            }))
+        }
+        Err(BlockingError::Error(err @ RsbtError::CreateTorrent(_)))
+        | Err(BlockingError::Error(err @ RsbtError::IO(_))) => {
+            return Ok(HttpResponse::BadRequest().json(Failure {
+                error: format!("cannot create torrent: {}", err),
+            }))
+        }
+        Err(err) => {
+            error!("cannot create torrent: {}", err);
+            return Ok(HttpResponse::InternalServerError().json(Failure {
+                error: format!("cannot create torrent: {}", err),
+            }));
+        }
+    };
+    let filename = format!("{}.torrent", created.name);
+
+    if query.seed {
+        let command = RsbtCommandAddTorrent {
+            data: created.data.clone(),
+            filename: filename.clone(),
+            state: TorrentDownloadStatus::Enabled,
+            priorities: vec![],
+            sequential: false,
+            backend: None,
+            preallocation: query.preallocation,
+            category: None,
+            save_to: Some(created.save_to),
+        };
+        if let Err(response) = add_torrent(&event_sender, &broadcaster_sender, command).await {
+            return Ok(response);
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-bittorrent")
+        .header(
+            http::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(created.data))
 }
@@ -1,18 +1,67 @@
 use clap_verbosity_flag::Verbosity;
-use rsbt_service::types::Config;
+use rsbt_service::{creator::TorrentCreateOptions, types::Config};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
 /// Extremely fast and simple torrent client
 #[derive(StructOpt)]
 pub(crate) struct Cli {
-    /// Path to torrent
-    #[structopt(parse(from_os_str))]
-    pub torrent: PathBuf,
     #[structopt(flatten)]
     pub verbose: Verbosity,
-    #[structopt(flatten)]
-    pub config: Config,
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+#[derive(StructOpt)]
+pub(crate) enum Command {
+    /// Downloads and seeds torrent, forever unless seeding goal is set, exits with error if
+    /// torrent fails or downloaded data does not match hashes
+    Download {
+        /// Path to torrent
+        #[structopt(parse(from_os_str))]
+        torrent: PathBuf,
+        /// Exits as soon as torrent is downloaded and verified
+        #[structopt(long)]
+        exit_on_complete: bool,
+        /// Seeds until uploaded bytes reach this ratio of torrent size, then exits
+        #[structopt(long)]
+        seed_ratio: Option<f64>,
+        /// Seeds for this many minutes after download, then exits
+        #[structopt(long)]
+        seed_time: Option<u64>,
+        #[structopt(flatten)]
+        config: Config,
+    },
+    /// Prints name, info hash, trackers, files and piece size of torrent
+    Inspect {
+        /// Path to torrent
+        #[structopt(parse(from_os_str))]
+        torrent: PathBuf,
+        /// Prints JSON instead of text
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Hashes data of torrent and reports missing or corrupt pieces per file, exits with
+    /// error if data is not complete
+    Verify {
+        /// Path to torrent
+        #[structopt(parse(from_os_str))]
+        torrent: PathBuf,
+        /// Directory with files of torrent, as it is saved to by download
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+        /// Prints JSON instead of text
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Creates torrent from file or directory
+    Create {
+        #[structopt(flatten)]
+        options: TorrentCreateOptions,
+        /// Torrent file to write, default is name of file or directory with `.torrent` extension
+        #[structopt(long, short, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
 }
 
 pub(crate) fn from_args() -> Cli {
@@ -2,11 +2,15 @@ use env_logger::Builder as LoggerBuilder;
 use exitfailure::ExitFailure;
 use log::{debug, info, Level};
 use rsbt_service::{
-    app::RsbtApp,
-    types::{Properties, Settings},
+    app::{RsbtApp, SeedingGoals},
+    creator::create_torrent,
+    inspect::{inspect_torrent, verify_torrent},
+    types::{torrent::parse_torrent, Config, Properties, Settings},
 };
+use std::{path::PathBuf, time::Duration};
 
 mod cli;
+mod report;
 
 /// Port for client to listen for peer connections
 ///
@@ -29,10 +33,64 @@ async fn main() -> Result<(), ExitFailure> {
             .try_init()?;
     }
 
+    match cli.command {
+        cli::Command::Download {
+            torrent,
+            exit_on_complete,
+            seed_ratio,
+            seed_time,
+            config,
+        } => {
+            let goals = SeedingGoals {
+                exit_on_complete,
+                ratio: seed_ratio,
+                time: seed_time.map(|minutes| Duration::from_secs(minutes * 60)),
+            };
+            download(torrent, goals, config).await?;
+        }
+        cli::Command::Inspect { torrent, json } => {
+            let torrent = parse_torrent(&tokio::fs::read(torrent).await?)?;
+            let inspection = inspect_torrent(&torrent)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&inspection)?);
+            } else {
+                report::print_inspection(&inspection);
+            }
+        }
+        cli::Command::Verify { torrent, dir, json } => {
+            let info = parse_torrent(&tokio::fs::read(torrent).await?)?.info()?;
+            let verification =
+                tokio::task::spawn_blocking(move || verify_torrent(&info, dir)).await??;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&verification)?);
+            } else {
+                report::print_verification(&verification);
+            }
+            if !verification.is_complete() {
+                std::process::exit(1);
+            }
+        }
+        cli::Command::Create { options, output } => {
+            let created = tokio::task::spawn_blocking(move || create_torrent(&options)).await??;
+            let output =
+                output.unwrap_or_else(|| PathBuf::from(format!("{}.torrent", created.name)));
+            tokio::fs::write(&output, &created.data).await?;
+            info!("torrent {} is written to {:?}", created.name, output);
+        }
+    }
+
+    Ok(())
+}
+
+async fn download(
+    torrent: PathBuf,
+    goals: SeedingGoals,
+    config: Config,
+) -> Result<(), ExitFailure> {
     info!("starting torrent client");
 
     let properties: Properties = (
-        load_settings()?.override_with(cli.config),
+        load_settings()?.override_with(config),
         rsbt_service::default_app_dir(),
     )
         .into();
@@ -41,7 +99,7 @@ async fn main() -> Result<(), ExitFailure> {
 
     let app = RsbtApp::new(properties);
 
-    app.download(cli.torrent).await?;
+    app.download(torrent, goals).await?;
 
     Ok(())
 }
@@ -0,0 +1,63 @@
+use rsbt_service::inspect::{TorrentInspection, TorrentVerification};
+
+pub(crate) fn print_inspection(inspection: &TorrentInspection) {
+    println!("name:         {}", inspection.name);
+    if let Some(info_hash) = &inspection.info_hash {
+        println!("info hash:    {}", info_hash);
+    }
+    if let Some(info_hash_v2) = &inspection.info_hash_v2 {
+        println!("info hash v2: {}", info_hash_v2);
+    }
+    println!("version:      {}", inspection.version);
+    println!("piece size:   {}", inspection.piece_length);
+    println!("pieces:       {}", inspection.pieces);
+    println!("length:       {}", inspection.length);
+    println!("trackers:");
+    for (tier, trackers) in inspection.trackers.iter().enumerate() {
+        for tracker in trackers {
+            println!("  {} {}", tier, tracker);
+        }
+    }
+    println!("files:");
+    for file in &inspection.files {
+        println!("  {:>14} {}", file.length, file.path.display());
+    }
+}
+
+pub(crate) fn print_verification(verification: &TorrentVerification) {
+    for file in &verification.files {
+        println!("{}", file.path.display());
+        if !file.missing.is_empty() {
+            println!("  missing pieces: {}", ranges(&file.missing));
+        }
+        if !file.corrupt.is_empty() {
+            println!("  corrupt pieces: {}", ranges(&file.corrupt));
+        }
+    }
+    println!(
+        "{} of {} pieces are valid",
+        verification.valid_pieces, verification.pieces
+    );
+}
+
+/// Formats sorted piece indexes as comma separated ranges, e.g. `1-3, 7`.
+fn ranges(pieces: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for &piece in pieces {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == piece => *end = piece,
+            _ => ranges.push((piece, piece)),
+        }
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,742 @@
+use flat_storage::*;
+use log::{debug, error};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{remove_file, File},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Storage which accesses files with positional reads and writes.
+///
+/// Only recently used files are kept open in [`FileHandles`] shared by all storages, so
+/// storage neither maps files into memory nor holds descriptors of every file of torrent.
+pub struct FileFlatStorage {
+    id: usize,
+    download_path: PathBuf,
+    files: Vec<FlatStorageFile>,
+    mapping: Vec<FlatStorageMapping>,
+    saved: Mutex<Vec<usize>>,
+    handles: Arc<FileHandles>,
+    preallocation: Preallocation,
+}
+
+/// Cache of open files of all file storages, so count of open descriptors is bounded
+/// whatever count of torrents is.
+pub struct FileHandles {
+    capacity: usize,
+    next_id: AtomicUsize,
+    /// Open files, least recently used first
+    handles: Mutex<Vec<OpenFile>>,
+    /// Errors of sync of written files, which were closed by other storages, by storage and
+    /// file, they are returned by flush of their storage
+    sync_errors: Mutex<HashMap<(usize, usize), io::Error>>,
+}
+
+struct OpenFile {
+    storage_id: usize,
+    file_index: usize,
+    file: Arc<File>,
+    written: bool,
+}
+
+impl fmt::Debug for FileHandles {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileHandles")
+            .field("capacity", &self.capacity)
+            .field("open", &self.handles.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl FileHandles {
+    /// Creates cache which keeps at most `capacity` files open, at least one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            next_id: AtomicUsize::new(0),
+            handles: Mutex::new(vec![]),
+            sync_errors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn storage_id(&self) -> usize {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Returns open file, or opens it with `open`, none if `open` returns none.
+    ///
+    /// File is opened without lock of cache, so other files are accessed meanwhile. Least
+    /// recently used file is closed if cache is full, and synced if it was written, error of
+    /// sync is kept for flush of its storage.
+    fn get_or_open<F>(
+        &self,
+        storage_id: usize,
+        file_index: usize,
+        open: F,
+    ) -> Result<Option<Arc<File>>, io::Error>
+    where
+        F: FnOnce() -> Result<Option<File>, io::Error>,
+    {
+        if let Some(file) = Self::touch(&mut self.handles.lock().unwrap(), storage_id, file_index) {
+            return Ok(Some(file));
+        }
+        let file = match open()? {
+            Some(file) => Arc::new(file),
+            None => return Ok(None),
+        };
+        let evicted = {
+            let mut handles = self.handles.lock().unwrap();
+            // file could be opened by another thread meanwhile
+            if let Some(file) = Self::touch(&mut handles, storage_id, file_index) {
+                return Ok(Some(file));
+            }
+            let evicted = if handles.len() >= self.capacity {
+                Some(handles.remove(0))
+            } else {
+                None
+            };
+            handles.push(OpenFile {
+                storage_id,
+                file_index,
+                file: file.clone(),
+                written: false,
+            });
+            evicted
+        };
+
+        if let Some(evicted) = evicted {
+            if evicted.written {
+                if let Err(err) = evicted.file.sync_data() {
+                    error!("cannot flush closed file: {}", err);
+                    self.sync_errors
+                        .lock()
+                        .unwrap()
+                        .insert((evicted.storage_id, evicted.file_index), err);
+                }
+            }
+        }
+        Ok(Some(file))
+    }
+
+    /// Returns open file and marks it as most recently used.
+    fn touch(
+        handles: &mut Vec<OpenFile>,
+        storage_id: usize,
+        file_index: usize,
+    ) -> Option<Arc<File>> {
+        let position = handles
+            .iter()
+            .position(|x| x.storage_id == storage_id && x.file_index == file_index)?;
+        let handle = handles.remove(position);
+        let file = handle.file.clone();
+        handles.push(handle);
+        Some(file)
+    }
+
+    /// Returns errors of sync of closed files of storage by file.
+    fn take_sync_errors(&self, storage_id: usize) -> Vec<(usize, io::Error)> {
+        let mut sync_errors = self.sync_errors.lock().unwrap();
+        let keys: Vec<_> = sync_errors
+            .keys()
+            .filter(|(id, _)| *id == storage_id)
+            .cloned()
+            .collect();
+        keys.into_iter()
+            .filter_map(|key| sync_errors.remove(&key).map(|err| (key.1, err)))
+            .collect()
+    }
+
+    /// Marks open file as written, so it is synced on flush, returns false if file was
+    /// evicted meanwhile.
+    fn set_written(&self, storage_id: usize, file_index: usize) -> bool {
+        let mut handles = self.handles.lock().unwrap();
+        match handles
+            .iter_mut()
+            .find(|x| x.storage_id == storage_id && x.file_index == file_index)
+        {
+            Some(handle) => {
+                handle.written = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns written files of storage and marks them as not written.
+    fn take_written(&self, storage_id: usize) -> Vec<Arc<File>> {
+        self.handles
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|x| x.storage_id == storage_id && x.written)
+            .map(|x| {
+                x.written = false;
+                x.file.clone()
+            })
+            .collect()
+    }
+
+    /// Closes all files of storage, returns files which were written.
+    fn remove(&self, storage_id: usize) -> Vec<Arc<File>> {
+        let mut handles = self.handles.lock().unwrap();
+        let (removed, kept) = handles.drain(..).partition(|x| x.storage_id == storage_id);
+        *handles = kept;
+        removed
+            .into_iter()
+            .filter(|x: &OpenFile| x.written)
+            .map(|x| x.file)
+            .collect()
+    }
+
+    /// Count of open files.
+    pub fn len(&self) -> usize {
+        self.handles.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl FileFlatStorage {
+    /// Creates storage and allocates existing or new files.
+    ///
+    /// Files marked in `skipped`, or all files with lazy `preallocation`, are not created,
+    /// they are allocated only when a piece which shares data with them is written. Pad files
+    /// are never created. Files are opened for reads and writes through shared `handles`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create<P: AsRef<Path>>(
+        download_path: P,
+        piece_count: usize,
+        piece_size: usize,
+        files: Vec<FlatStorageFile>,
+        downloaded: &[u8],
+        skipped: &[bool],
+        preallocation: Preallocation,
+        handles: Arc<FileHandles>,
+    ) -> Result<Self, io::Error> {
+        let download_path = download_path.as_ref().to_path_buf();
+        let mapping = map_pieces_to_files(piece_size, &files);
+        let mut saved = vec![];
+        for (index, file) in files.iter().enumerate() {
+            saved.push(calculate_saved(piece_count, index, &mapping, downloaded));
+            let file_path = download_path.join(&file.path);
+            let skip = skipped.get(index).cloned().unwrap_or_default() || preallocation.is_lazy();
+            if file.pad {
+                debug!("pad file: {:?}", file_path);
+            } else if skip && !file_path.is_file() {
+                debug!("skipped file: {:?}", file_path);
+            } else {
+                open_file(&file_path, file.length, preallocation)?;
+            }
+            debug!("processed file: {:?}", file_path);
+        }
+        Ok(Self {
+            id: handles.storage_id(),
+            download_path,
+            files,
+            mapping,
+            saved: Mutex::new(saved),
+            handles,
+            preallocation,
+        })
+    }
+
+    /// Returns open file, none if file is not created yet and `write` is not set, or if it is
+    /// pad file.
+    fn file(&self, file_index: usize, write: bool) -> Result<Option<Arc<File>>, io::Error> {
+        if self.files[file_index].pad {
+            return Ok(None);
+        }
+        let file = &self.files[file_index];
+        let file_path = self.download_path.join(&file.path);
+        self.handles.get_or_open(self.id, file_index, || {
+            if !write && !file_path.is_file() {
+                return Ok(None);
+            }
+            open_file(&file_path, file.length, self.preallocation).map(Some)
+        })
+    }
+
+    /// Remembers that file was written, or syncs it if it was closed while it was written.
+    fn written(&self, file_index: usize, file: &File) -> Result<(), io::Error> {
+        if self.handles.set_written(self.id, file_index) {
+            Ok(())
+        } else {
+            file.sync_data()
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> Result<usize, io::Error> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> Result<usize, io::Error> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(unix)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> Result<usize, io::Error> {
+    std::os::unix::fs::FileExt::write_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> Result<usize, io::Error> {
+    std::os::windows::fs::FileExt::seek_write(file, buf, offset)
+}
+
+/// Reads until buffer is full or end of file is reached, rest of buffer is not changed.
+fn read_full_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> Result<(), io::Error> {
+    while !buf.is_empty() {
+        match read_at(file, buf, offset) {
+            Ok(0) => break,
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> Result<(), io::Error> {
+    while !buf.is_empty() {
+        match write_at(file, buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+impl FlatStorage for FileFlatStorage {
+    fn files(&self) -> &[FlatStorageFile] {
+        &self.files
+    }
+
+    fn read_piece<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+    ) -> Result<Option<Vec<u8>>, FlatStorageError> {
+        let index = *index.into();
+        self.read_block(index, 0, self.mapping[index].size())
+    }
+
+    fn write_piece<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        block: Vec<u8>,
+    ) -> Result<(), FlatStorageError> {
+        let index = *index.into();
+        self.write_block(index, 0, &block)?;
+        self.piece_saved(index);
+        Ok(())
+    }
+
+    fn read_block<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        begin: usize,
+        length: usize,
+    ) -> Result<Option<Vec<u8>>, FlatStorageError> {
+        let index = *index.into();
+        let parts = map_block(&self.mapping, index, begin, length).ok_or_else(|| {
+            error!("block {}+{} is out of piece {}", begin, length, index);
+            FlatStorageError::ReadBlock
+        })?;
+        let mut result = vec![0; length];
+        for part in parts {
+            let read = match self.file(part.file_index, false) {
+                Ok(Some(file)) => read_full_at(
+                    &file,
+                    &mut result[part.offset..part.offset + part.size],
+                    part.file_offset as u64,
+                ),
+                Ok(None) => Ok(()),
+                Err(err) => Err(err),
+            };
+            read.map_err(|err| {
+                error!(
+                    "cannot read file {:?}: {}",
+                    self.files[part.file_index].path, err
+                );
+                FlatStorageError::ReadBlock
+            })?;
+        }
+
+        Ok(Some(result))
+    }
+
+    fn write_block<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        begin: usize,
+        block: &[u8],
+    ) -> Result<(), FlatStorageError> {
+        let index = *index.into();
+        let parts = map_block(&self.mapping, index, begin, block.len()).ok_or_else(|| {
+            error!("block {}+{} is out of piece {}", begin, block.len(), index);
+            FlatStorageError::WriteBlock
+        })?;
+        for part in parts {
+            let file = self.file(part.file_index, true).map_err(|err| {
+                error!(
+                    "cannot allocate file {:?}: {}",
+                    self.files[part.file_index].path, err
+                );
+                storage_error(&err, FlatStorageError::AllocateFile)
+            })?;
+            if let Some(file) = file {
+                write_all_at(
+                    &file,
+                    &block[part.offset..part.offset + part.size],
+                    part.file_offset as u64,
+                )
+                .and_then(|()| self.written(part.file_index, &file))
+                .map_err(|err| {
+                    error!(
+                        "cannot write file {:?}: {}",
+                        self.files[part.file_index].path, err
+                    );
+                    storage_error(&err, FlatStorageError::WriteBlock)
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), FlatStorageError> {
+        for file in self.handles.take_written(self.id) {
+            file.sync_data().map_err(|err| {
+                error!("cannot flush file: {}", err);
+                FlatStorageError::Flush
+            })?;
+        }
+        let sync_errors = self.handles.take_sync_errors(self.id);
+        for (file_index, err) in &sync_errors {
+            error!(
+                "cannot flush file {:?}: {}",
+                self.files[*file_index].path, err
+            );
+        }
+        if sync_errors.is_empty() {
+            Ok(())
+        } else {
+            Err(FlatStorageError::Flush)
+        }
+    }
+
+    fn saved(&self) -> Vec<usize> {
+        self.saved.lock().unwrap().clone()
+    }
+
+    fn update_saved(&self, downloaded: &[u8]) {
+        let pieces_count = self.mapping.len();
+        let mut saved = self.saved.lock().unwrap();
+        for (index, saved) in saved.iter_mut().enumerate() {
+            *saved = calculate_saved(pieces_count, index, &self.mapping, downloaded);
+        }
+    }
+
+    fn piece_saved(&self, index: usize) {
+        if let Some(map_to_files) = self.mapping.get(index) {
+            let mut saved = self.saved.lock().unwrap();
+            for file_block in &map_to_files.0 {
+                saved[file_block.file_index] += file_block.size;
+            }
+        }
+    }
+
+    fn file_info(&self, file_id: usize) -> Option<FileInfo> {
+        file_info(&self.files, &self.mapping, file_id)
+    }
+
+    fn delete_files(&self) -> Result<(), FlatStorageError> {
+        self.handles.remove(self.id);
+        for file in &self.files {
+            let file_path = self.download_path.join(&file.path);
+            debug!("deleting file: {:?}", file_path);
+            if file_path.is_file() {
+                remove_file(&file_path).map_err(|err| {
+                    error!("cannot delete file {:?}: {}", file_path, err);
+                    FlatStorageError::DeleteFile
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FileFlatStorage {
+    fn drop(&mut self) {
+        for file in self.handles.remove(self.id) {
+            if let Err(err) = file.sync_data() {
+                error!("cannot flush file: {}", err);
+            }
+        }
+        self.handles.take_sync_errors(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_blocks_across_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let handles = Arc::new(FileHandles::new(1));
+        let storage = test_util::check_write_blocks_across_files(|pieces, size, files| {
+            FileFlatStorage::create(
+                dir.path(),
+                pieces,
+                size,
+                files,
+                &[],
+                &[],
+                Preallocation::Sparse,
+                handles.clone(),
+            )
+            .unwrap()
+        });
+        assert_eq!(handles.len(), 1);
+
+        storage.delete_files().unwrap();
+        assert!(!dir.path().join("test1").exists());
+        assert!(handles.is_empty());
+    }
+
+    #[test]
+    fn skipped_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = vec![
+            FlatStorageFile {
+                path: "test1".into(),
+                length: 300,
+                pad: false,
+            },
+            FlatStorageFile {
+                path: "sub/test2".into(),
+                length: 400,
+                pad: false,
+            },
+            FlatStorageFile {
+                path: "test3".into(),
+                length: 100,
+                pad: false,
+            },
+        ];
+        let handles = Arc::new(FileHandles::new(1));
+        let storage = FileFlatStorage::create(
+            dir.path(),
+            2,
+            500,
+            files,
+            &[],
+            &[false, false, true],
+            Preallocation::Sparse,
+            handles,
+        )
+        .unwrap();
+        assert!(dir.path().join("sub/test2").is_file());
+        assert!(!dir.path().join("test3").exists());
+
+        let piece: Vec<u8> = (0..300).map(|x| x as u8).collect();
+        assert_eq!(storage.read_block(1, 200, 100).unwrap(), Some(vec![0; 100]));
+        storage.write_piece(1, piece).unwrap();
+        assert!(dir.path().join("test3").is_file());
+        assert_eq!(storage.saved(), vec![0, 200, 100]);
+        storage.flush().unwrap();
+    }
+
+    #[test]
+    fn shared_file_handles() {
+        let dir = tempfile::tempdir().unwrap();
+        let handles = Arc::new(FileHandles::new(2));
+        let storages: Vec<_> = (0..3)
+            .map(|x| {
+                let files = vec![FlatStorageFile {
+                    path: format!("test{}", x).into(),
+                    length: 100,
+                    pad: false,
+                }];
+                FileFlatStorage::create(
+                    dir.path(),
+                    1,
+                    100,
+                    files,
+                    &[],
+                    &[],
+                    Preallocation::Sparse,
+                    handles.clone(),
+                )
+                .unwrap()
+            })
+            .collect();
+
+        for (x, storage) in storages.iter().enumerate() {
+            storage.write_piece(0, vec![x as u8; 100]).unwrap();
+            assert!(handles.len() <= 2);
+        }
+        for (x, storage) in storages.iter().enumerate() {
+            assert_eq!(storage.read_piece(0).unwrap(), Some(vec![x as u8; 100]));
+            storage.flush().unwrap();
+        }
+        assert_eq!(handles.len(), 2);
+
+        drop(storages);
+        assert!(handles.is_empty());
+    }
+
+    #[test]
+    fn file_handles_report_sync_errors_to_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        let handles = Arc::new(FileHandles::new(1));
+        let storages: Vec<_> = (0..2)
+            .map(|x| {
+                let files = vec![FlatStorageFile {
+                    path: format!("test{}", x).into(),
+                    length: 100,
+                    pad: false,
+                }];
+                FileFlatStorage::create(
+                    dir.path(),
+                    1,
+                    100,
+                    files,
+                    &[],
+                    &[],
+                    Preallocation::Sparse,
+                    handles.clone(),
+                )
+                .unwrap()
+            })
+            .collect();
+
+        // file is opened without lock of cache
+        let file = handles
+            .get_or_open(storages[0].id, 0, || {
+                assert!(handles.is_empty());
+                open_file(&dir.path().join("test0"), 100, Preallocation::Sparse).map(Some)
+            })
+            .unwrap();
+        assert!(file.is_some());
+
+        storages[0].write_piece(0, vec![1; 100]).unwrap();
+        handles.sync_errors.lock().unwrap().insert(
+            (storages[0].id, 0),
+            io::Error::new(io::ErrorKind::Other, "sync failure"),
+        );
+        storages[1].write_piece(0, vec![2; 100]).unwrap();
+
+        storages[1].flush().unwrap();
+        assert!(storages[0].flush().is_err());
+        storages[0].flush().unwrap();
+    }
+
+    #[test]
+    fn pad_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = vec![
+            FlatStorageFile {
+                path: "test1".into(),
+                length: 300,
+                pad: false,
+            },
+            FlatStorageFile {
+                path: ".pad/200".into(),
+                length: 200,
+                pad: true,
+            },
+        ];
+        let storage = FileFlatStorage::create(
+            dir.path(),
+            1,
+            500,
+            files,
+            &[],
+            &[],
+            Preallocation::Sparse,
+            Arc::new(FileHandles::new(1)),
+        )
+        .unwrap();
+        assert!(!dir.path().join(".pad").exists());
+
+        storage.write_piece(0, vec![1; 500]).unwrap();
+        assert!(!dir.path().join(".pad").exists());
+        let mut piece = vec![1; 300];
+        piece.resize(500, 0);
+        assert_eq!(storage.read_piece(0).unwrap(), Some(piece));
+        assert_eq!(required_space(dir.path(), storage.files(), &[]).unwrap(), 0);
+    }
+
+    #[test]
+    fn preallocation_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = vec![
+            FlatStorageFile {
+                path: "lazy1".into(),
+                length: 300,
+                pad: false,
+            },
+            FlatStorageFile {
+                path: "lazy2".into(),
+                length: 400,
+                pad: false,
+            },
+        ];
+        let handles = Arc::new(FileHandles::new(1));
+        let storage = FileFlatStorage::create(
+            dir.path(),
+            2,
+            500,
+            files,
+            &[],
+            &[],
+            Preallocation::None,
+            handles.clone(),
+        )
+        .unwrap();
+        assert!(!dir.path().join("lazy1").exists());
+        assert!(!dir.path().join("lazy2").exists());
+
+        storage.write_block(0, 0, &[1; 100]).unwrap();
+        assert_eq!(dir.path().join("lazy1").metadata().unwrap().len(), 300);
+        assert!(!dir.path().join("lazy2").exists());
+
+        let files = vec![FlatStorageFile {
+            path: "full".into(),
+            length: 700,
+            pad: false,
+        }];
+        FileFlatStorage::create(
+            dir.path(),
+            2,
+            500,
+            files,
+            &[],
+            &[],
+            Preallocation::Full,
+            handles,
+        )
+        .unwrap();
+        assert_eq!(dir.path().join("full").metadata().unwrap().len(), 700);
+    }
+}
@@ -0,0 +1,188 @@
+use flat_storage::*;
+use log::error;
+use std::sync::Mutex;
+
+/// Storage which keeps torrent data in memory only.
+///
+/// Pieces are mapped to files the same way as in storages backed by disk. Memory for file
+/// is allocated when data is written to it first time, missing data is read as zeros.
+pub struct MemoryFlatStorage {
+    files: Vec<FlatStorageFile>,
+    mapping: Vec<FlatStorageMapping>,
+    file_handles: Vec<Mutex<FileHandle>>,
+}
+
+#[derive(Default)]
+struct FileHandle {
+    data: Option<Vec<u8>>,
+    saved: usize,
+}
+
+impl MemoryFlatStorage {
+    pub fn create(piece_size: usize, files: Vec<FlatStorageFile>) -> Self {
+        let mapping = map_pieces_to_files(piece_size, &files);
+        let file_handles = files.iter().map(|_| Default::default()).collect();
+        Self {
+            files,
+            mapping,
+            file_handles,
+        }
+    }
+}
+
+impl FlatStorage for MemoryFlatStorage {
+    fn files(&self) -> &[FlatStorageFile] {
+        &self.files
+    }
+
+    fn read_piece<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+    ) -> Result<Option<Vec<u8>>, FlatStorageError> {
+        let index = *index.into();
+        self.read_block(index, 0, self.mapping[index].size())
+    }
+
+    fn write_piece<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        block: Vec<u8>,
+    ) -> Result<(), FlatStorageError> {
+        let index = *index.into();
+        self.write_block(index, 0, &block)?;
+        self.piece_saved(index);
+        Ok(())
+    }
+
+    fn read_block<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        begin: usize,
+        length: usize,
+    ) -> Result<Option<Vec<u8>>, FlatStorageError> {
+        let index = *index.into();
+        let parts = map_block(&self.mapping, index, begin, length).ok_or_else(|| {
+            error!("block {}+{} is out of piece {}", begin, length, index);
+            FlatStorageError::ReadBlock
+        })?;
+        let mut result = vec![0; length];
+        for part in parts {
+            if let Some(data) = &self.file_handles[part.file_index].lock().unwrap().data {
+                result[part.offset..part.offset + part.size]
+                    .copy_from_slice(&data[part.file_offset..part.file_offset + part.size]);
+            }
+        }
+
+        Ok(Some(result))
+    }
+
+    fn write_block<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        begin: usize,
+        block: &[u8],
+    ) -> Result<(), FlatStorageError> {
+        let index = *index.into();
+        let parts = map_block(&self.mapping, index, begin, block.len()).ok_or_else(|| {
+            error!("block {}+{} is out of piece {}", begin, block.len(), index);
+            FlatStorageError::WriteBlock
+        })?;
+        for part in parts {
+            let file = &self.files[part.file_index];
+            if file.pad {
+                continue;
+            }
+            let length = file.length;
+            let mut file_handle = self.file_handles[part.file_index].lock().unwrap();
+            let data = file_handle.data.get_or_insert_with(|| vec![0; length]);
+            data[part.file_offset..part.file_offset + part.size]
+                .copy_from_slice(&block[part.offset..part.offset + part.size]);
+        }
+
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), FlatStorageError> {
+        Ok(())
+    }
+
+    fn saved(&self) -> Vec<usize> {
+        self.file_handles
+            .iter()
+            .map(|x| x.lock().unwrap().saved)
+            .collect()
+    }
+
+    fn update_saved(&self, downloaded: &[u8]) {
+        let pieces_count = self.mapping.len();
+        for (index, file_handle) in self.file_handles.iter().enumerate() {
+            file_handle.lock().unwrap().saved =
+                calculate_saved(pieces_count, index, &self.mapping, downloaded);
+        }
+    }
+
+    fn piece_saved(&self, index: usize) {
+        if let Some(map_to_files) = self.mapping.get(index) {
+            for file_block in &map_to_files.0 {
+                self.file_handles[file_block.file_index]
+                    .lock()
+                    .unwrap()
+                    .saved += file_block.size;
+            }
+        }
+    }
+
+    fn file_info(&self, file_id: usize) -> Option<FileInfo> {
+        file_info(&self.files, &self.mapping, file_id)
+    }
+
+    fn delete_files(&self) -> Result<(), FlatStorageError> {
+        for file_handle in &self.file_handles {
+            *file_handle.lock().unwrap() = Default::default();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_blocks_across_files() {
+        let storage = test_util::check_write_blocks_across_files(|_, size, files| {
+            MemoryFlatStorage::create(size, files)
+        });
+        assert_eq!(storage.read_piece(1).unwrap(), Some(vec![0; 200]));
+
+        storage.delete_files().unwrap();
+        assert_eq!(storage.read_piece(0).unwrap(), Some(vec![0; 500]));
+        assert_eq!(storage.saved(), vec![0, 0]);
+    }
+
+    #[test]
+    fn pad_files() {
+        let files = vec![
+            FlatStorageFile {
+                path: "test1".into(),
+                length: 300,
+                pad: false,
+            },
+            FlatStorageFile {
+                path: ".pad/200".into(),
+                length: 200,
+                pad: true,
+            },
+        ];
+        let storage = MemoryFlatStorage::create(500, files);
+
+        let mut piece = vec![1; 300];
+        piece.extend_from_slice(&[7; 200]);
+        storage.write_piece(0, piece).unwrap();
+
+        assert!(storage.file_handles[1].lock().unwrap().data.is_none());
+        let mut expected = vec![1; 300];
+        expected.extend_from_slice(&[0; 200]);
+        assert_eq!(storage.read_piece(0).unwrap(), Some(expected));
+    }
+}
@@ -1,100 +1,104 @@
 use flat_storage::*;
-use log::debug;
+use log::{debug, error};
 use memmap::MmapMut;
 use std::{
-    fs::{create_dir_all, remove_file, OpenOptions},
-    path::Path,
+    fs::{remove_file, File},
+    path::{Path, PathBuf},
     sync::Mutex,
 };
 
 pub struct MmapFlatStorage {
+    download_path: PathBuf,
     files: Vec<FlatStorageFile>,
     file_handles: Vec<Mutex<FileHandle>>,
-    mapping: Vec<MmapFlatStorageMapping>,
-}
-
-#[derive(Debug, PartialEq)]
-struct MmapFlatStorageMapping(Vec<FileBlock>);
-
-#[derive(Debug, Clone, PartialEq)]
-struct FileBlock {
-    offset: usize,
-    file_index: usize,
-    file_offset: usize,
-    size: usize,
-}
-
-#[derive(Debug)]
-pub struct FileInfo {
-    pub file: FlatStorageFile,
-    pub piece: usize,
-    pub piece_offset: usize,
+    mapping: Vec<FlatStorageMapping>,
+    preallocation: Preallocation,
 }
 
 struct FileHandle {
+    file: Option<File>,
     mmap: Option<MmapMut>,
     saved: usize,
 }
 
 impl MmapFlatStorage {
+    /// Creates storage and maps existing or new files.
+    ///
+    /// Files marked in `skipped`, or all files with lazy `preallocation`, are not created,
+    /// they are allocated only when a piece which shares data with them is written. Pad files
+    /// are never created.
     pub fn create<P: AsRef<Path>>(
         download_path: P,
         piece_count: usize,
         piece_size: usize,
         files: Vec<FlatStorageFile>,
         downloaded: &[u8],
+        skipped: &[bool],
+        preallocation: Preallocation,
     ) -> Result<Self, std::io::Error> {
         let mapping = map_pieces_to_files(piece_size, &files);
-        let file_handles = load_files(&download_path, &files, downloaded, &mapping, piece_count)?;
+        let file_handles = load_files(
+            &download_path,
+            &files,
+            downloaded,
+            &mapping,
+            piece_count,
+            skipped,
+            preallocation,
+        )?;
         Ok(Self {
+            download_path: download_path.as_ref().to_path_buf(),
             files,
             file_handles,
             mapping,
+            preallocation,
         })
     }
 
-    pub fn delete_files<P: AsRef<Path>>(&self, download_path: P) -> Result<(), std::io::Error> {
-        for file_handle in &self.file_handles {
-            if let Some(mut file_handle) = file_handle.lock().ok() {
-                if let Some(mmap) = file_handle.mmap.take() {
-                    mmap.flush()?;
-                }
-            }
-        }
-        for file in &self.files {
-            let file_path = download_path.as_ref().join(&file.path);
-            debug!("deleting file: {:?}", file_path);
-            if file_path.is_file() {
-                remove_file(file_path)?
-            }
+    /// Maps file which was skipped on creation, pad files are never mapped.
+    fn allocate(
+        &self,
+        file_index: usize,
+        file_handle: &mut FileHandle,
+    ) -> Result<(), FlatStorageError> {
+        let file = &self.files[file_index];
+        if file_handle.mmap.is_none() && !file.pad {
+            let file_path = self.download_path.join(&file.path);
+            let (file, mmap) =
+                map_file(&file_path, file.length, self.preallocation).map_err(|err| {
+                    error!("cannot allocate file {:?}: {}", file_path, err);
+                    storage_error(&err, FlatStorageError::AllocateFile)
+                })?;
+            file_handle.file = Some(file);
+            file_handle.mmap = Some(mmap);
         }
         Ok(())
     }
 
-    pub fn saved(&self) -> Vec<usize> {
-        self.file_handles
-            .iter()
-            .map(|x| x.lock().unwrap().saved)
-            .collect()
-    }
-
-    pub fn file_info(&self, file_id: usize) -> Option<FileInfo> {
-        self.files.get(file_id).cloned().and_then(|file| {
-            self.mapping
-                .iter()
-                .enumerate()
-                .find_map(move |(piece, m)| {
-                    m.0.iter()
-                        .filter(|x| x.file_index == file_id)
-                        .map(|x| (piece, x.offset))
-                        .next()
-                })
-                .map(|(piece, piece_offset)| FileInfo {
-                    file,
-                    piece,
-                    piece_offset,
-                })
-        })
+    /// Allocates and returns part of mapped file, so write to it never hits full disk.
+    fn reserve<'a>(
+        &self,
+        file_index: usize,
+        file_handle: &'a mut FileHandle,
+        offset: usize,
+        size: usize,
+    ) -> Result<Option<&'a mut [u8]>, FlatStorageError> {
+        self.allocate(file_index, file_handle)?;
+        if self.preallocation != Preallocation::Full {
+            if let Some(file) = &file_handle.file {
+                allocate_range(file, offset as u64, size as u64).map_err(|err| {
+                    error!(
+                        "cannot allocate space in file {:?}: {}",
+                        self.files[file_index].path, err
+                    );
+                    storage_error(&err, FlatStorageError::WriteBlock)
+                })?;
+            }
+        }
+        Ok(file_handle
+            .mmap
+            .as_mut()
+            .map(|data| &mut data[offset..offset + size]))
     }
 }
 
@@ -102,36 +106,43 @@ fn load_files<P: AsRef<Path>>(
     download_path: P,
     files: &[FlatStorageFile],
     downloaded: &[u8],
-    mapping: &[MmapFlatStorageMapping],
+    mapping: &[FlatStorageMapping],
     pieces_count: usize,
+    skipped: &[bool],
+    preallocation: Preallocation,
 ) -> Result<Vec<Mutex<FileHandle>>, std::io::Error> {
     let mut result = vec![];
     for (index, file) in files.iter().enumerate() {
         let saved = calculate_saved(pieces_count, index, mapping, downloaded);
         let file_path = download_path.as_ref().join(&file.path);
-        debug!("checking file: {:?}", file_path);
-        if !file_path.is_file() {
-            if let Some(path) = file_path.parent() {
-                debug!("create dir {:?}", path);
-                create_dir_all(path)?;
-            }
-        }
-        debug!("create file");
-        let f = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&file_path)?;
-        debug!("set len");
-        f.set_len(file.length as u64)?;
-        debug!("creating mmap...");
-        let mmap = Some(unsafe { MmapMut::map_mut(&f)? });
-        result.push(Mutex::new(FileHandle { mmap, saved }));
+        let skip = skipped.get(index).cloned().unwrap_or_default() || preallocation.is_lazy();
+        let (file, mmap) = if file.pad {
+            debug!("pad file: {:?}", file_path);
+            (None, None)
+        } else if skip && !file_path.is_file() {
+            debug!("skipped file: {:?}", file_path);
+            (None, None)
+        } else {
+            let (file, mmap) = map_file(&file_path, file.length, preallocation)?;
+            (Some(file), Some(mmap))
+        };
+        result.push(Mutex::new(FileHandle { file, mmap, saved }));
         debug!("processed file: {:?}", file_path);
     }
     Ok(result)
 }
 
+fn map_file(
+    file_path: &Path,
+    length: usize,
+    preallocation: Preallocation,
+) -> Result<(File, MmapMut), std::io::Error> {
+    let f = open_file(file_path, length, preallocation)?;
+    debug!("creating mmap...");
+    let mmap = unsafe { MmapMut::map_mut(&f)? };
+    Ok((f, mmap))
+}
+
 impl FlatStorage for MmapFlatStorage {
     fn files(&self) -> &[FlatStorageFile] {
         &self.files
@@ -141,17 +152,8 @@ impl FlatStorage for MmapFlatStorage {
         &self,
         index: I,
     ) -> Result<Option<Vec<u8>>, FlatStorageError> {
-        let map_to_files = &self.mapping[*index.into()];
-        let mut result = vec![];
-        for file_block in &map_to_files.0 {
-            let f = &self.file_handles[file_block.file_index];
-            if let Some(data) = &f.lock().unwrap().mmap {
-                let data = &data[file_block.file_offset..file_block.file_offset + file_block.size];
-                result.extend_from_slice(data);
-            }
-        }
-
-        Ok(Some(result))
+        let index = *index.into();
+        self.read_block(index, 0, self.mapping[index].size())
     }
 
     fn write_piece<I: Into<FlatStoragePieceIndex>>(
@@ -163,84 +165,134 @@ impl FlatStorage for MmapFlatStorage {
         for file_block in &map_to_files.0 {
             let f = &self.file_handles[file_block.file_index];
             let mut f_lock = f.lock().unwrap();
-            f_lock.saved += file_block.size;
-            if let Some(data) = f_lock.mmap.as_mut() {
-                let data =
-                    &mut data[file_block.file_offset..file_block.file_offset + file_block.size];
+            if let Some(data) = self.reserve(
+                file_block.file_index,
+                &mut f_lock,
+                file_block.file_offset,
+                file_block.size,
+            )? {
                 data.copy_from_slice(&block[file_block.offset..file_block.offset + file_block.size])
             }
+            f_lock.saved += file_block.size;
         }
 
         Ok(())
     }
-}
 
-fn map_pieces_to_files(
-    piece_size: usize,
-    files: &[FlatStorageFile],
-) -> Vec<MmapFlatStorageMapping> {
-    let mut current_piece_left = piece_size;
-    let mut current_piece = MmapFlatStorageMapping(vec![]);
-    let mut offset = 0;
+    fn read_block<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        begin: usize,
+        length: usize,
+    ) -> Result<Option<Vec<u8>>, FlatStorageError> {
+        let index = *index.into();
+        let parts = map_block(&self.mapping, index, begin, length).ok_or_else(|| {
+            error!("block {}+{} is out of piece {}", begin, length, index);
+            FlatStorageError::ReadBlock
+        })?;
+        let mut result = Vec::with_capacity(length);
+        for part in parts {
+            let f = &self.file_handles[part.file_index];
+            if let Some(data) = &f.lock().unwrap().mmap {
+                result.extend_from_slice(&data[part.file_offset..part.file_offset + part.size]);
+            } else {
+                result.resize(result.len() + part.size, 0);
+            }
+        }
 
-    let mut mapping = vec![];
+        Ok(Some(result))
+    }
 
-    for (file_index, file) in files.iter().enumerate() {
-        let mut file_remaining_length = file.length;
-        let mut file_offset = 0;
-        while current_piece_left < file_remaining_length {
-            current_piece.0.push(FileBlock {
-                offset,
-                file_index,
-                file_offset,
-                size: current_piece_left,
-            });
+    fn write_block<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        begin: usize,
+        block: &[u8],
+    ) -> Result<(), FlatStorageError> {
+        let index = *index.into();
+        let parts = map_block(&self.mapping, index, begin, block.len()).ok_or_else(|| {
+            error!("block {}+{} is out of piece {}", begin, block.len(), index);
+            FlatStorageError::WriteBlock
+        })?;
+        for part in parts {
+            let f = &self.file_handles[part.file_index];
+            let mut f_lock = f.lock().unwrap();
+            if let Some(data) =
+                self.reserve(part.file_index, &mut f_lock, part.file_offset, part.size)?
+            {
+                data.copy_from_slice(&block[part.offset..part.offset + part.size]);
+            }
+        }
 
-            file_remaining_length -= current_piece_left;
-            file_offset += current_piece_left;
-            current_piece_left = piece_size;
+        Ok(())
+    }
 
-            mapping.push(current_piece);
-            current_piece = MmapFlatStorageMapping(vec![]);
-            offset = 0;
+    fn flush(&self) -> Result<(), FlatStorageError> {
+        for file_handle in &self.file_handles {
+            if let Some(mmap) = &file_handle.lock().unwrap().mmap {
+                mmap.flush().map_err(|err| {
+                    error!("cannot flush file: {}", err);
+                    FlatStorageError::Flush
+                })?;
+            }
         }
-        if current_piece_left >= file_remaining_length {
-            current_piece.0.push(FileBlock {
-                offset,
-                file_index,
-                file_offset,
-                size: file_remaining_length,
-            });
-            current_piece_left -= file_remaining_length;
-            offset += file_remaining_length;
+        Ok(())
+    }
+
+    fn saved(&self) -> Vec<usize> {
+        self.file_handles
+            .iter()
+            .map(|x| x.lock().unwrap().saved)
+            .collect()
+    }
+
+    fn update_saved(&self, downloaded: &[u8]) {
+        let pieces_count = self.mapping.len();
+        for (index, file_handle) in self.file_handles.iter().enumerate() {
+            file_handle.lock().unwrap().saved =
+                calculate_saved(pieces_count, index, &self.mapping, downloaded);
         }
     }
 
-    if !current_piece.0.is_empty() {
-        mapping.push(current_piece);
+    fn piece_saved(&self, index: usize) {
+        if let Some(map_to_files) = self.mapping.get(index) {
+            for file_block in &map_to_files.0 {
+                self.file_handles[file_block.file_index]
+                    .lock()
+                    .unwrap()
+                    .saved += file_block.size;
+            }
+        }
     }
 
-    mapping
-}
+    fn file_info(&self, file_id: usize) -> Option<FileInfo> {
+        file_info(&self.files, &self.mapping, file_id)
+    }
 
-fn calculate_saved(
-    pieces_count: usize,
-    file_index: usize,
-    mapping: &[MmapFlatStorageMapping],
-    downloaded: &[u8],
-) -> usize {
-    let mut saved = 0;
-    for piece in 0..pieces_count {
-        if bit_by_index(piece, downloaded).is_some() {
-            let mapping_block = &mapping[piece];
-            for file_block in &mapping_block.0 {
-                if file_block.file_index == file_index {
-                    saved += file_block.size;
+    fn delete_files(&self) -> Result<(), FlatStorageError> {
+        for file_handle in &self.file_handles {
+            if let Some(mut file_handle) = file_handle.lock().ok() {
+                if let Some(mmap) = file_handle.mmap.take() {
+                    mmap.flush().map_err(|err| {
+                        error!("cannot flush file: {}", err);
+                        FlatStorageError::Flush
+                    })?;
                 }
+                file_handle.file.take();
+            }
+        }
+        for file in &self.files {
+            let file_path = self.download_path.join(&file.path);
+            debug!("deleting file: {:?}", file_path);
+            if file_path.is_file() {
+                remove_file(&file_path).map_err(|err| {
+                    error!("cannot delete file {:?}: {}", file_path, err);
+                    FlatStorageError::DeleteFile
+                })?;
             }
         }
+        Ok(())
     }
-    saved
 }
 
 #[cfg(test)]
@@ -248,143 +300,20 @@ mod tests {
     use super::*;
 
     #[test]
-    fn pieces_to_files() {
-        let result = map_pieces_to_files(
-            100,
-            &[FlatStorageFile {
-                path: "test".into(),
-                length: 1000,
-            }],
-        );
-        dbg!(&result);
-        assert_eq!(result.len(), 10);
-
-        let result = map_pieces_to_files(
-            1000,
-            &[FlatStorageFile {
-                path: "test".into(),
-                length: 1000,
-            }],
-        );
-        assert_eq!(
-            result,
-            vec![MmapFlatStorageMapping(vec![FileBlock {
-                offset: 0,
-                file_index: 0,
-                file_offset: 0,
-                size: 1000,
-            }])]
-        );
-
-        let result = map_pieces_to_files(
-            1000,
-            &[FlatStorageFile {
-                path: "test".into(),
-                length: 800,
-            }],
-        );
-        assert_eq!(
-            result,
-            vec![MmapFlatStorageMapping(vec![FileBlock {
-                offset: 0,
-                file_index: 0,
-                file_offset: 0,
-                size: 800,
-            }])]
-        );
-
-        let result = map_pieces_to_files(
-            333,
-            &[FlatStorageFile {
-                path: "test".into(),
-                length: 1000,
-            }],
-        );
-        assert_eq!(
-            result,
-            vec![
-                MmapFlatStorageMapping(vec![FileBlock {
-                    offset: 0,
-                    file_index: 0,
-                    file_offset: 0,
-                    size: 333,
-                }]),
-                MmapFlatStorageMapping(vec![FileBlock {
-                    offset: 0,
-                    file_index: 0,
-                    file_offset: 333,
-                    size: 333,
-                }]),
-                MmapFlatStorageMapping(vec![FileBlock {
-                    offset: 0,
-                    file_index: 0,
-                    file_offset: 666,
-                    size: 333,
-                }]),
-                MmapFlatStorageMapping(vec![FileBlock {
-                    offset: 0,
-                    file_index: 0,
-                    file_offset: 999,
-                    size: 1,
-                }])
-            ]
-        );
-
-        let result = map_pieces_to_files(
-            500,
-            &[
-                FlatStorageFile {
-                    path: "test1".into(),
-                    length: 300,
-                },
-                FlatStorageFile {
-                    path: "test2".into(),
-                    length: 400,
-                },
-                FlatStorageFile {
-                    path: "test3".into(),
-                    length: 500,
-                },
-            ],
-        );
-        assert_eq!(
-            result,
-            vec![
-                MmapFlatStorageMapping(vec![
-                    FileBlock {
-                        offset: 0,
-                        file_index: 0,
-                        file_offset: 0,
-                        size: 300,
-                    },
-                    FileBlock {
-                        offset: 300,
-                        file_index: 1,
-                        file_offset: 0,
-                        size: 200,
-                    }
-                ]),
-                MmapFlatStorageMapping(vec![
-                    FileBlock {
-                        offset: 0,
-                        file_index: 1,
-                        file_offset: 200,
-                        size: 200,
-                    },
-                    FileBlock {
-                        offset: 200,
-                        file_index: 2,
-                        file_offset: 0,
-                        size: 300,
-                    }
-                ]),
-                MmapFlatStorageMapping(vec![FileBlock {
-                    offset: 0,
-                    file_index: 2,
-                    file_offset: 300,
-                    size: 200,
-                }])
-            ]
-        );
+    fn write_blocks_across_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_util::check_write_blocks_across_files(|pieces, size, files| {
+            MmapFlatStorage::create(
+                dir.path(),
+                pieces,
+                size,
+                files,
+                &[],
+                &[],
+                Preallocation::Sparse,
+            )
+            .unwrap()
+        });
+        assert!(storage.read_block(0, 480, 40).is_err());
     }
 }
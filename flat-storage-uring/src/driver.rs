@@ -0,0 +1,380 @@
+use io_uring::{opcode, squeue, types, IoUring, Probe};
+use log::{debug, error};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    future::Future,
+    io::{self, Write},
+    os::unix::{io::AsRawFd, net::UnixStream},
+    pin::Pin,
+    sync::{
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc, Condvar, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+/// Size of submission queue and max count of transfers in flight.
+const RING_ENTRIES: u32 = 256;
+
+/// User data of read of wake up socket, user data of transfers never reaches it.
+const WAKE_USER_DATA: u64 = u64::MAX;
+
+/// Completion queue is full, submission must be retried after reaping of completions.
+const EBUSY: i32 = 16;
+
+lazy_static::lazy_static! {
+    static ref DRIVER: Result<Arc<UringDriver>, String> =
+        UringDriver::start().map(Arc::new).map_err(|err| err.to_string());
+}
+
+/// Returns driver shared by all storages, it is started on first use.
+pub(crate) fn driver() -> Result<Arc<UringDriver>, io::Error> {
+    DRIVER
+        .clone()
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+}
+
+fn new_ring() -> Result<IoUring, io::Error> {
+    let ring = IoUring::new(RING_ENTRIES)?;
+    let mut probe = Probe::new();
+    ring.submitter().register_probe(&mut probe)?;
+    if !probe.is_supported(opcode::Read::CODE) || !probe.is_supported(opcode::Write::CODE) {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "io_uring read and write operations are not supported",
+        ));
+    }
+    Ok(ring)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Direction {
+    Read,
+    Write,
+}
+
+/// Transfer of `len` bytes between buffer of operation at `buf_offset` and `file` at
+/// `file_offset`.
+pub(crate) struct Transfer {
+    pub(crate) file: Arc<File>,
+    pub(crate) buf_offset: usize,
+    pub(crate) file_offset: u64,
+    pub(crate) len: usize,
+}
+
+/// Read or write of a block, which may be spread over many files.
+///
+/// Operation owns its buffer and files until all transfers are finished, so it is safe to
+/// drop its completion at any time.
+struct Operation {
+    direction: Direction,
+    buf: Vec<u8>,
+    transfers: Vec<Transfer>,
+    /// Bytes already transferred, by transfer
+    done: Vec<usize>,
+    /// Count of transfers, which are queued or in flight
+    pending: usize,
+    error: Option<io::Error>,
+    completion: Arc<Completion>,
+}
+
+impl Operation {
+    fn entry(&mut self, transfer_index: usize, user_data: u64) -> squeue::Entry {
+        let transfer = &self.transfers[transfer_index];
+        let done = self.done[transfer_index];
+        let fd = types::Fd(transfer.file.as_raw_fd());
+        let buf = self.buf[transfer.buf_offset + done..].as_mut_ptr();
+        let len = (transfer.len - done) as u32;
+        let offset = (transfer.file_offset + done as u64) as i64;
+        match self.direction {
+            Direction::Read => opcode::Read::new(fd, buf, len).offset(offset).build(),
+            Direction::Write => opcode::Write::new(fd, buf, len).offset(offset).build(),
+        }
+        .user_data(user_data)
+    }
+
+    /// Accounts result of transfer, returns true if rest of transfer must be submitted again.
+    fn transferred(&mut self, transfer_index: usize, result: i32) -> bool {
+        let remaining = self.transfers[transfer_index].len - self.done[transfer_index];
+        if result < 0 {
+            let err = io::Error::from_raw_os_error(-result);
+            match err.kind() {
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => return true,
+                _ => {
+                    self.error.get_or_insert(err);
+                }
+            }
+        } else if result == 0 {
+            // end of file is reached: rest of read is left zeroed, write cannot make progress
+            if self.direction == Direction::Write {
+                self.error
+                    .get_or_insert_with(|| io::ErrorKind::WriteZero.into());
+            }
+        } else if (result as usize) < remaining {
+            self.done[transfer_index] += result as usize;
+            return true;
+        } else {
+            self.done[transfer_index] += remaining;
+        }
+        self.pending -= 1;
+        false
+    }
+
+    fn complete(self) {
+        let result = match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.buf),
+        };
+        self.completion.complete(result);
+    }
+}
+
+/// Result of operation, shared by driver and waiting caller.
+#[derive(Default)]
+struct Completion {
+    state: Mutex<CompletionState>,
+    condvar: Condvar,
+}
+
+#[derive(Default)]
+struct CompletionState {
+    result: Option<Result<Vec<u8>, io::Error>>,
+    waker: Option<Waker>,
+}
+
+impl Completion {
+    fn complete(&self, result: Result<Vec<u8>, io::Error>) {
+        let mut state = self.state.lock().unwrap();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.condvar.notify_all();
+    }
+}
+
+/// Completion of submitted operation, resolves to its buffer.
+///
+/// It can be awaited, or waited for with blocking of current thread.
+pub(crate) struct CompletionFuture(Arc<Completion>);
+
+impl CompletionFuture {
+    pub(crate) fn wait(self) -> Result<Vec<u8>, io::Error> {
+        let mut state = self.0.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+            state = self.0.condvar.wait(state).unwrap();
+        }
+    }
+}
+
+impl Future for CompletionFuture {
+    type Output = Result<Vec<u8>, io::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Single io_uring shared by all storages.
+///
+/// Operations of all storages are queued to driver thread, which submits their transfers to
+/// kernel in batches and completes operations when all their transfers are finished. Transfers
+/// which are done partially are submitted again for the rest of data.
+pub(crate) struct UringDriver {
+    sender: Mutex<Sender<Operation>>,
+    wake: UnixStream,
+}
+
+impl UringDriver {
+    fn start() -> Result<Self, io::Error> {
+        let ring = new_ring()?;
+        let (wake, wake_receiver) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("io-uring".into())
+            .spawn(move || run(ring, receiver, wake_receiver))?;
+        debug!("io_uring driver is started");
+        Ok(Self {
+            sender: Mutex::new(sender),
+            wake,
+        })
+    }
+
+    /// Queues transfers between `buf` and files, completion returns `buf` back.
+    pub(crate) fn submit(
+        &self,
+        direction: Direction,
+        buf: Vec<u8>,
+        transfers: Vec<Transfer>,
+    ) -> CompletionFuture {
+        let completion = Arc::new(Completion::default());
+        if transfers.is_empty() {
+            completion.complete(Ok(buf));
+            return CompletionFuture(completion);
+        }
+        let operation = Operation {
+            direction,
+            buf,
+            done: vec![0; transfers.len()],
+            pending: transfers.len(),
+            transfers,
+            error: None,
+            completion: completion.clone(),
+        };
+        if self.sender.lock().unwrap().send(operation).is_err() {
+            completion.complete(Err(io::Error::new(
+                io::ErrorKind::Other,
+                "io_uring driver is stopped",
+            )));
+        } else if let Err(err) = (&self.wake).write(&[1]) {
+            // socket is full only if driver is not woken up yet
+            if err.kind() != io::ErrorKind::WouldBlock {
+                error!("cannot wake up io_uring driver: {}", err);
+            }
+        }
+        CompletionFuture(completion)
+    }
+}
+
+fn run(ring: IoUring, receiver: Receiver<Operation>, wake: UnixStream) {
+    let mut wake_buf = [0u8; 64];
+    // ring is dropped before buffer of wake up read
+    let mut ring = ring;
+    let mut operations: HashMap<u64, Operation> = HashMap::new();
+    let mut queue: VecDeque<(u64, usize)> = VecDeque::new();
+    let mut in_flight: HashMap<u64, (u64, usize)> = HashMap::new();
+    let mut next_operation = 0u64;
+    let mut next_user_data = 0u64;
+    let mut wake_armed = false;
+    let mut stopped = false;
+
+    loop {
+        loop {
+            match receiver.try_recv() {
+                Ok(operation) => {
+                    let id = next_operation;
+                    next_operation += 1;
+                    queue.extend((0..operation.transfers.len()).map(|x| (id, x)));
+                    operations.insert(id, operation);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    stopped = true;
+                    break;
+                }
+            }
+        }
+
+        if stopped && operations.is_empty() {
+            debug!("io_uring driver is stopped");
+            break;
+        }
+
+        {
+            let mut submission = ring.submission();
+            if !wake_armed && !stopped {
+                let entry = opcode::Read::new(
+                    types::Fd(wake.as_raw_fd()),
+                    wake_buf.as_mut_ptr(),
+                    wake_buf.len() as u32,
+                )
+                .build()
+                .user_data(WAKE_USER_DATA);
+                if unsafe { submission.push(&entry) }.is_ok() {
+                    wake_armed = true;
+                }
+            }
+            while in_flight.len() < RING_ENTRIES as usize - 1 && !submission.is_full() {
+                let (id, transfer_index) = match queue.pop_front() {
+                    Some(next) => next,
+                    None => break,
+                };
+                let operation = match operations.get_mut(&id) {
+                    Some(operation) => operation,
+                    None => continue,
+                };
+                if operation.error.is_some() {
+                    // operation is failed already, rest of its transfers is skipped
+                    operation.pending -= 1;
+                    if operation.pending == 0 {
+                        if let Some(operation) = operations.remove(&id) {
+                            operation.complete();
+                        }
+                    }
+                    continue;
+                }
+                let user_data = next_user_data;
+                next_user_data = (next_user_data + 1) % WAKE_USER_DATA;
+                let entry = operation.entry(transfer_index, user_data);
+                if unsafe { submission.push(&entry) }.is_err() {
+                    queue.push_front((id, transfer_index));
+                    break;
+                }
+                in_flight.insert(user_data, (id, transfer_index));
+            }
+        }
+
+        if let Err(err) = ring.submit_and_wait(1) {
+            if err.kind() != io::ErrorKind::Interrupted && err.raw_os_error() != Some(EBUSY) {
+                error!("io_uring submission failed: {}", err);
+                for operation in operations.values() {
+                    operation.completion.complete(Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("io_uring submission failed: {}", err),
+                    )));
+                }
+                // kernel may still use buffers of transfers in flight
+                std::mem::forget(operations);
+                break;
+            }
+        }
+
+        let completed: Vec<(u64, i32)> = ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result()))
+            .collect();
+        for (user_data, result) in completed {
+            if user_data == WAKE_USER_DATA {
+                wake_armed = false;
+                continue;
+            }
+            let (id, transfer_index) = match in_flight.remove(&user_data) {
+                Some(transfer) => transfer,
+                None => continue,
+            };
+            if let Some(operation) = operations.get_mut(&id) {
+                if operation.transferred(transfer_index, result) {
+                    queue.push_front((id, transfer_index));
+                } else if operation.pending == 0 {
+                    if let Some(operation) = operations.remove(&id) {
+                        operation.complete();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Checks if kernel supports io_uring operations used by storage.
+pub fn is_supported() -> bool {
+    match driver() {
+        Ok(_) => true,
+        Err(err) => {
+            debug!("io_uring is not supported: {}", err);
+            false
+        }
+    }
+}
@@ -0,0 +1,17 @@
+//! Flat storage based on io_uring, available on Linux only.
+
+#[cfg(target_os = "linux")]
+mod driver;
+#[cfg(target_os = "linux")]
+mod uring;
+
+#[cfg(target_os = "linux")]
+pub use driver::is_supported;
+#[cfg(target_os = "linux")]
+pub use uring::UringFlatStorage;
+
+/// Checks if kernel supports io_uring operations used by storage.
+#[cfg(not(target_os = "linux"))]
+pub fn is_supported() -> bool {
+    false
+}
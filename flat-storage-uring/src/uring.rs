@@ -0,0 +1,432 @@
+use crate::driver::{driver, Direction, Transfer, UringDriver};
+use flat_storage::*;
+use futures::future::{self, FutureExt};
+use log::{debug, error};
+use std::{
+    fs::{remove_file, File},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Storage which reads and writes files with io_uring.
+///
+/// All parts of a block (piece), which may be spread over many files, are queued to io_uring
+/// driver shared by all storages as a single operation.
+pub struct UringFlatStorage {
+    download_path: PathBuf,
+    files: Vec<FlatStorageFile>,
+    mapping: Vec<FlatStorageMapping>,
+    saved: Mutex<Vec<usize>>,
+    file_handles: Vec<Mutex<Option<Arc<File>>>>,
+    driver: Arc<UringDriver>,
+    preallocation: Preallocation,
+}
+
+impl UringFlatStorage {
+    /// Creates storage and allocates existing or new files.
+    ///
+    /// Files marked in `skipped`, or all files with lazy `preallocation`, are not created,
+    /// they are allocated only when a piece which shares data with them is written. Pad files
+    /// are never created. Fails if io_uring is not supported.
+    pub fn create<P: AsRef<Path>>(
+        download_path: P,
+        piece_count: usize,
+        piece_size: usize,
+        files: Vec<FlatStorageFile>,
+        downloaded: &[u8],
+        skipped: &[bool],
+        preallocation: Preallocation,
+    ) -> Result<Self, io::Error> {
+        let driver = driver()?;
+        let download_path = download_path.as_ref().to_path_buf();
+        let mapping = map_pieces_to_files(piece_size, &files);
+        let mut saved = vec![];
+        let mut file_handles = vec![];
+        for (index, file) in files.iter().enumerate() {
+            saved.push(calculate_saved(piece_count, index, &mapping, downloaded));
+            let file_path = download_path.join(&file.path);
+            let skip = skipped.get(index).cloned().unwrap_or_default() || preallocation.is_lazy();
+            let file_handle = if file.pad {
+                debug!("pad file: {:?}", file_path);
+                None
+            } else if skip && !file_path.is_file() {
+                debug!("skipped file: {:?}", file_path);
+                None
+            } else {
+                Some(Arc::new(open_file(&file_path, file.length, preallocation)?))
+            };
+            file_handles.push(Mutex::new(file_handle));
+            debug!("processed file: {:?}", file_path);
+        }
+        Ok(Self {
+            download_path,
+            files,
+            mapping,
+            saved: Mutex::new(saved),
+            file_handles,
+            driver,
+            preallocation,
+        })
+    }
+
+    /// Returns open file, none if file is not created yet and `write` is not set, or if it is
+    /// pad file.
+    fn file(&self, file_index: usize, write: bool) -> Result<Option<Arc<File>>, io::Error> {
+        let mut file_handle = self.file_handles[file_index].lock().unwrap();
+        let file = &self.files[file_index];
+        if file_handle.is_none() && write && !file.pad {
+            let file_path = self.download_path.join(&file.path);
+            *file_handle = Some(Arc::new(open_file(
+                &file_path,
+                file.length,
+                self.preallocation,
+            )?));
+        }
+        Ok(file_handle.clone())
+    }
+
+    /// Maps block to transfers of files.
+    ///
+    /// Parts of files which are not created yet are skipped on read. On write such files are
+    /// created if `allocate` is set, otherwise none is returned.
+    fn block_transfers(
+        &self,
+        direction: Direction,
+        index: usize,
+        begin: usize,
+        length: usize,
+        allocate: bool,
+    ) -> Result<Option<Vec<Transfer>>, FlatStorageError> {
+        let block_error = || match direction {
+            Direction::Read => FlatStorageError::ReadBlock,
+            Direction::Write => FlatStorageError::WriteBlock,
+        };
+        let parts = map_block(&self.mapping, index, begin, length).ok_or_else(|| {
+            error!("block {}+{} is out of piece {}", begin, length, index);
+            block_error()
+        })?;
+        let mut transfers = vec![];
+        for part in parts {
+            let write = direction == Direction::Write;
+            let file = self
+                .file(part.file_index, write && allocate)
+                .map_err(|err| {
+                    error!(
+                        "cannot allocate file {:?}: {}",
+                        self.files[part.file_index].path, err
+                    );
+                    storage_error(&err, FlatStorageError::AllocateFile)
+                })?;
+            match file {
+                Some(file) => transfers.push(Transfer {
+                    file,
+                    buf_offset: part.offset,
+                    file_offset: part.file_offset as u64,
+                    len: part.size,
+                }),
+                None if write && !self.files[part.file_index].pad => return Ok(None),
+                None => (),
+            }
+        }
+        Ok(Some(transfers))
+    }
+}
+
+/// Converts result of read operation, missing data at the end of files is left zeroed.
+fn read_result(
+    index: usize,
+    result: Result<Vec<u8>, io::Error>,
+) -> Result<Option<Vec<u8>>, FlatStorageError> {
+    result.map(Some).map_err(|err| {
+        error!("cannot read block of piece {}: {}", index, err);
+        FlatStorageError::ReadBlock
+    })
+}
+
+fn write_result(index: usize, result: Result<Vec<u8>, io::Error>) -> Result<(), FlatStorageError> {
+    result.map(|_| ()).map_err(|err| {
+        error!("cannot write block of piece {}: {}", index, err);
+        storage_error(&err, FlatStorageError::WriteBlock)
+    })
+}
+
+impl FlatStorage for UringFlatStorage {
+    fn files(&self) -> &[FlatStorageFile] {
+        &self.files
+    }
+
+    fn read_piece<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+    ) -> Result<Option<Vec<u8>>, FlatStorageError> {
+        let index = *index.into();
+        self.read_block(index, 0, self.mapping[index].size())
+    }
+
+    fn write_piece<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        block: Vec<u8>,
+    ) -> Result<(), FlatStorageError> {
+        let index = *index.into();
+        self.write_block(index, 0, &block)?;
+        self.piece_saved(index);
+        Ok(())
+    }
+
+    fn read_block<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        begin: usize,
+        length: usize,
+    ) -> Result<Option<Vec<u8>>, FlatStorageError> {
+        let index = *index.into();
+        let transfers = self
+            .block_transfers(Direction::Read, index, begin, length, false)?
+            .unwrap_or_default();
+        let result = self
+            .driver
+            .submit(Direction::Read, vec![0; length], transfers)
+            .wait();
+        read_result(index, result)
+    }
+
+    fn write_block<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        begin: usize,
+        block: &[u8],
+    ) -> Result<(), FlatStorageError> {
+        let index = *index.into();
+        let transfers = self
+            .block_transfers(Direction::Write, index, begin, block.len(), true)?
+            .unwrap_or_default();
+        let result = self
+            .driver
+            .submit(Direction::Write, block.to_vec(), transfers)
+            .wait();
+        write_result(index, result)
+    }
+
+    fn submit_read_block(
+        &self,
+        index: usize,
+        begin: usize,
+        length: usize,
+    ) -> Option<BlockFuture<Option<Vec<u8>>>> {
+        let transfers = match self.block_transfers(Direction::Read, index, begin, length, false) {
+            Ok(transfers) => transfers.unwrap_or_default(),
+            Err(err) => return Some(future::err(err).boxed()),
+        };
+        let completion = self
+            .driver
+            .submit(Direction::Read, vec![0; length], transfers);
+        Some(
+            completion
+                .map(move |result| read_result(index, result))
+                .boxed(),
+        )
+    }
+
+    fn submit_write_block(
+        &self,
+        index: usize,
+        begin: usize,
+        block: Vec<u8>,
+    ) -> Result<BlockFuture<()>, Vec<u8>> {
+        // files are allocated with blocking write only
+        let transfers =
+            match self.block_transfers(Direction::Write, index, begin, block.len(), false) {
+                Ok(Some(transfers)) => transfers,
+                Ok(None) => return Err(block),
+                Err(err) => return Ok(future::err(err).boxed()),
+            };
+        let completion = self.driver.submit(Direction::Write, block, transfers);
+        Ok(completion
+            .map(move |result| write_result(index, result))
+            .boxed())
+    }
+
+    fn flush(&self) -> Result<(), FlatStorageError> {
+        for file_handle in &self.file_handles {
+            let file = file_handle.lock().unwrap().clone();
+            if let Some(file) = file {
+                file.sync_data().map_err(|err| {
+                    error!("cannot flush file: {}", err);
+                    FlatStorageError::Flush
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn saved(&self) -> Vec<usize> {
+        self.saved.lock().unwrap().clone()
+    }
+
+    fn update_saved(&self, downloaded: &[u8]) {
+        let pieces_count = self.mapping.len();
+        let mut saved = self.saved.lock().unwrap();
+        for (index, saved) in saved.iter_mut().enumerate() {
+            *saved = calculate_saved(pieces_count, index, &self.mapping, downloaded);
+        }
+    }
+
+    fn piece_saved(&self, index: usize) {
+        if let Some(map_to_files) = self.mapping.get(index) {
+            let mut saved = self.saved.lock().unwrap();
+            for file_block in &map_to_files.0 {
+                saved[file_block.file_index] += file_block.size;
+            }
+        }
+    }
+
+    fn file_info(&self, file_id: usize) -> Option<FileInfo> {
+        file_info(&self.files, &self.mapping, file_id)
+    }
+
+    fn delete_files(&self) -> Result<(), FlatStorageError> {
+        for file_handle in &self.file_handles {
+            file_handle.lock().unwrap().take();
+        }
+        for file in &self.files {
+            let file_path = self.download_path.join(&file.path);
+            debug!("deleting file: {:?}", file_path);
+            if file_path.is_file() {
+                remove_file(&file_path).map_err(|err| {
+                    error!("cannot delete file {:?}: {}", file_path, err);
+                    FlatStorageError::DeleteFile
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::is_supported;
+    use futures::executor::block_on;
+    use std::{fs::OpenOptions, thread};
+
+    #[test]
+    fn write_blocks_across_files() {
+        if !is_supported() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        test_util::check_write_blocks_across_files(|pieces, size, files| {
+            UringFlatStorage::create(
+                dir.path(),
+                pieces,
+                size,
+                files,
+                &[],
+                &[],
+                Preallocation::Sparse,
+            )
+            .unwrap()
+        });
+    }
+
+    #[test]
+    fn shared_driver_completes_concurrent_blocks() {
+        if !is_supported() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let storages: Vec<_> = (0..4)
+            .map(|x| {
+                let files = vec![FlatStorageFile {
+                    path: format!("test{}", x).into(),
+                    length: 64 * 1024,
+                    pad: false,
+                }];
+                Arc::new(
+                    UringFlatStorage::create(
+                        dir.path(),
+                        4,
+                        16 * 1024,
+                        files,
+                        &[],
+                        &[],
+                        Preallocation::Sparse,
+                    )
+                    .unwrap(),
+                )
+            })
+            .collect();
+
+        let writers: Vec<_> = storages
+            .iter()
+            .enumerate()
+            .map(|(x, storage)| {
+                let storage = storage.clone();
+                thread::spawn(move || {
+                    for index in 0..4 {
+                        let piece = vec![(x * 4 + index) as u8; 16 * 1024];
+                        storage.write_piece(index, piece).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let reads: Vec<_> = storages
+            .iter()
+            .flat_map(|storage| (0..4).map(move |index| (storage, index)))
+            .map(|(storage, index)| storage.submit_read_block(index, 1024, 1024).unwrap())
+            .collect();
+        for (x, read) in reads.into_iter().enumerate() {
+            assert_eq!(block_on(read).unwrap(), Some(vec![x as u8; 1024]));
+        }
+    }
+
+    #[test]
+    fn submit_write_block_requires_allocated_files() {
+        if !is_supported() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let files = vec![FlatStorageFile {
+            path: "test".into(),
+            length: 300,
+            pad: false,
+        }];
+        let storage =
+            UringFlatStorage::create(dir.path(), 1, 300, files, &[], &[], Preallocation::None)
+                .unwrap();
+
+        let block = storage
+            .submit_write_block(0, 0, vec![1; 100])
+            .err()
+            .unwrap();
+        storage.write_block(0, 0, &block).unwrap();
+        block_on(
+            storage
+                .submit_write_block(0, 100, vec![2; 100])
+                .ok()
+                .unwrap(),
+        )
+        .unwrap();
+
+        // file is truncated behind storage, read past its end is zeroed
+        OpenOptions::new()
+            .write(true)
+            .open(dir.path().join("test"))
+            .unwrap()
+            .set_len(150)
+            .unwrap();
+        let mut expected = vec![1; 100];
+        expected.extend(vec![2; 50]);
+        expected.extend(vec![0; 150]);
+        assert_eq!(storage.read_block(0, 0, 300).unwrap(), Some(expected));
+    }
+}
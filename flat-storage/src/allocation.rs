@@ -0,0 +1,164 @@
+use super::*;
+use std::{
+    fs::{create_dir_all, File, OpenOptions},
+    io,
+    path::Path,
+    str::FromStr,
+};
+
+/// How disk space for torrent files is reserved
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Preallocation {
+    /// Files are created with full length, but space is allocated on write
+    #[default]
+    Sparse,
+    /// Files are created and space is allocated up front, less fragmentation on HDD
+    Full,
+    /// Files are created on first write
+    None,
+}
+
+impl FromStr for Preallocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sparse" => Ok(Preallocation::Sparse),
+            "full" => Ok(Preallocation::Full),
+            "none" => Ok(Preallocation::None),
+            _ => Err(format!("unknown preallocation mode: {}", s)),
+        }
+    }
+}
+
+impl Preallocation {
+    /// Checks if files should be created only when they are written.
+    pub fn is_lazy(self) -> bool {
+        self == Preallocation::None
+    }
+}
+
+/// Sets file length, with `Full` mode disk space is allocated too.
+pub fn allocate_file(file: &File, length: usize, preallocation: Preallocation) -> io::Result<()> {
+    if preallocation == Preallocation::Full && length > 0 {
+        fs2::FileExt::allocate(file, length as u64)?;
+    }
+    if file.metadata()?.len() != length as u64 {
+        file.set_len(length as u64)?;
+    }
+    Ok(())
+}
+
+/// Opens file for reads and writes, file and its directory are created if they do not exist.
+///
+/// File length is set with `allocate_file` if it differs from `length`.
+pub fn open_file(
+    file_path: &Path,
+    length: usize,
+    preallocation: Preallocation,
+) -> io::Result<File> {
+    if !file_path.is_file() {
+        if let Some(path) = file_path.parent() {
+            create_dir_all(path)?;
+        }
+    }
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(file_path)?;
+    if file.metadata()?.len() != length as u64 {
+        allocate_file(&file, length, preallocation)?;
+    }
+    Ok(file)
+}
+
+/// Allocates disk space for `length` bytes of file from `offset`.
+///
+/// Write into hole of memory mapped file on full disk crashes process with `SIGBUS`, so space
+/// must be reserved before. Space is reserved on Linux only.
+#[cfg(target_os = "linux")]
+pub fn allocate_range(file: &File, offset: u64, length: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let result = unsafe {
+        libc::posix_fallocate(
+            file.as_raw_fd(),
+            offset as libc::off_t,
+            length as libc::off_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(result))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allocate_range(_file: &File, _offset: u64, _length: u64) -> io::Result<()> {
+    Ok(())
+}
+
+/// Converts I/O error to `NoSpace` if disk is full, to `error` otherwise.
+pub fn storage_error(err: &io::Error, error: FlatStorageError) -> FlatStorageError {
+    if is_no_space(err) {
+        FlatStorageError::NoSpace
+    } else {
+        error
+    }
+}
+
+#[cfg(unix)]
+fn is_no_space(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ENOSPC)
+}
+
+#[cfg(windows)]
+fn is_no_space(err: &io::Error) -> bool {
+    const ERROR_HANDLE_DISK_FULL: i32 = 39;
+    const ERROR_DISK_FULL: i32 = 112;
+    match err.raw_os_error() {
+        Some(ERROR_HANDLE_DISK_FULL) | Some(ERROR_DISK_FULL) => true,
+        _ => false,
+    }
+}
+
+/// Returns space available to user on disk with `path`, which may not exist yet.
+pub fn available_space<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    let mut path = path.as_ref();
+    while !path.exists() {
+        path = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+    }
+    fs2::available_space(path)
+}
+
+/// Returns count of bytes which still have to be allocated on disk for files.
+///
+/// Files marked in `skipped` and pad files are not counted, existing files are counted without space
+/// already allocated for them.
+pub fn required_space<P: AsRef<Path>>(
+    download_path: P,
+    files: &[FlatStorageFile],
+    skipped: &[bool],
+) -> io::Result<u64> {
+    let mut required = 0;
+    for (index, file) in files.iter().enumerate() {
+        if file.pad || skipped.get(index).cloned().unwrap_or_default() {
+            continue;
+        }
+        let length = file.length as u64;
+        let file_path = download_path.as_ref().join(&file.path);
+        let allocated = if file_path.is_file() {
+            fs2::FileExt::allocated_size(&File::open(&file_path)?)?
+        } else {
+            0
+        };
+        required += length.saturating_sub(allocated);
+    }
+    Ok(required)
+}
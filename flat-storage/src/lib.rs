@@ -1,7 +1,16 @@
 use failure::Fail;
+use futures::future::BoxFuture;
 use serde::{Deserialize, Serialize};
 use std::{ops::Deref, path::PathBuf};
 
+mod allocation;
+mod mapping;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+
+pub use allocation::*;
+pub use mapping::*;
+
 #[inline]
 pub fn index_in_bitarray(index: usize) -> (usize, u8) {
     (index / 8, 128 >> (index % 8))
@@ -23,6 +32,9 @@ pub fn bit_by_index(index: usize, data: &[u8]) -> Option<(usize, u8)> {
 pub struct FlatStorageFile {
     pub path: PathBuf,
     pub length: usize,
+    /// Pad file (BEP 47), which is never created: reads return zeros, writes are discarded.
+    #[serde(default)]
+    pub pad: bool,
 }
 
 pub struct FlatStoragePieceIndex(usize);
@@ -40,6 +52,9 @@ impl Deref for FlatStoragePieceIndex {
     }
 }
 
+/// Block operation, which is completed without blocking of disk I/O threads.
+pub type BlockFuture<T> = BoxFuture<'static, Result<T, FlatStorageError>>;
+
 /// Flat storage of different sized files.
 ///
 /// Access to data is asynchronious. Files created lazy.
@@ -56,6 +71,64 @@ pub trait FlatStorage {
         index: I,
         block: Vec<u8>,
     ) -> Result<(), FlatStorageError>;
+
+    /// Reads `length` bytes of piece starting from `begin` offset inside of piece.
+    fn read_block<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        begin: usize,
+        length: usize,
+    ) -> Result<Option<Vec<u8>>, FlatStorageError>;
+
+    /// Writes part of piece, `begin` is offset inside of piece.
+    fn write_block<I: Into<FlatStoragePieceIndex>>(
+        &self,
+        index: I,
+        begin: usize,
+        block: &[u8],
+    ) -> Result<(), FlatStorageError>;
+
+    /// Starts read of block, which completes asynchronously.
+    ///
+    /// Returns none if storage supports blocking reads only.
+    fn submit_read_block(
+        &self,
+        _index: usize,
+        _begin: usize,
+        _length: usize,
+    ) -> Option<BlockFuture<Option<Vec<u8>>>> {
+        None
+    }
+
+    /// Starts write of block, which completes asynchronously.
+    ///
+    /// Returns block back if it must be written with blocking write, e.g. if storage does not
+    /// support asynchronous writes or files of block are not allocated yet.
+    fn submit_write_block(
+        &self,
+        _index: usize,
+        _begin: usize,
+        block: Vec<u8>,
+    ) -> Result<BlockFuture<()>, Vec<u8>> {
+        Err(block)
+    }
+
+    /// Flushes written data to disk.
+    fn flush(&self) -> Result<(), FlatStorageError>;
+
+    /// Bytes of verified pieces saved in every file.
+    fn saved(&self) -> Vec<usize>;
+
+    /// Recalculates saved bytes of every file from downloaded pieces (e.g. after recheck).
+    fn update_saved(&self, downloaded: &[u8]);
+
+    /// Counts verified piece in saved bytes of files.
+    fn piece_saved(&self, index: usize);
+
+    fn file_info(&self, file_id: usize) -> Option<FileInfo>;
+
+    /// Deletes all files of storage.
+    fn delete_files(&self) -> Result<(), FlatStorageError>;
 }
 
 #[derive(Debug, Fail)]
@@ -66,6 +139,12 @@ pub enum FlatStorageError {
     ReadBlock,
     #[fail(display = "cannot write block to file")]
     WriteBlock,
+    #[fail(display = "cannot flush file")]
+    Flush,
+    #[fail(display = "cannot delete file")]
+    DeleteFile,
+    #[fail(display = "no space left on device")]
+    NoSpace,
 }
 
 #[cfg(test)]
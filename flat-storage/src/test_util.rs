@@ -0,0 +1,48 @@
+//! Checks shared by tests of all storage implementations.
+
+use super::*;
+
+/// Checks reads and writes of blocks, which are spread over two files.
+///
+/// Storage is created by `make` with count of pieces, piece size and files: 2 pieces of 500
+/// bytes over files of 300 and 400 bytes. Storage is returned for checks of implementation.
+pub fn check_write_blocks_across_files<S, F>(make: F) -> S
+where
+    S: FlatStorage,
+    F: FnOnce(usize, usize, Vec<FlatStorageFile>) -> S,
+{
+    let files = vec![
+        FlatStorageFile {
+            path: "test1".into(),
+            length: 300,
+            pad: false,
+        },
+        FlatStorageFile {
+            path: "sub/test2".into(),
+            length: 400,
+            pad: false,
+        },
+    ];
+    let storage = make(2, 500, files);
+
+    let piece: Vec<u8> = (0..500).map(|x| x as u8).collect();
+    storage.write_block(0, 250, &piece[250..500]).unwrap();
+    storage.write_block(0, 0, &piece[0..250]).unwrap();
+    assert!(storage.write_block(1, 100, &piece[0..150]).is_err());
+
+    assert_eq!(storage.read_piece(0).unwrap(), Some(piece.clone()));
+    assert_eq!(
+        storage.read_block(0, 280, 40).unwrap(),
+        Some(piece[280..320].to_vec())
+    );
+    assert_eq!(storage.saved(), vec![0, 0]);
+    storage.flush().unwrap();
+
+    storage.piece_saved(0);
+    assert_eq!(storage.saved(), vec![300, 200]);
+
+    storage.update_saved(&[0b1100_0000]);
+    assert_eq!(storage.saved(), vec![300, 400]);
+
+    storage
+}
@@ -10,6 +10,7 @@ pub(crate) async fn http_announce(
     properties: Arc<Properties>,
     torrent_process: Arc<TorrentProcess>,
     announce_url: &str,
+    info_hash: [u8; SHA1_SIZE],
 ) -> Result<Duration, RsbtError> {
     let client: Client<_> = Client::new();
 
@@ -18,7 +19,7 @@ pub(crate) async fn http_announce(
         format!(
             "{}?info_hash={}&peer_id={}&left={}&port={}",
             announce_url,
-            url_encode(&torrent_process.hash_id[..]),
+            url_encode(&info_hash[..]),
             url_encode(&PEER_ID[..]),
             left,
             properties.port,
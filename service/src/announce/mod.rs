@@ -31,27 +31,62 @@ pub async fn announce_loop(
         return Err(RsbtError::AnnounceProtocolFailure);
     };
 
+    let info_hashes = announce_hashes(&torrent_process);
+
     loop {
-        let try_interval_to_query_tracker = match proto {
-            Announce::Http => {
-                http::http_announce(properties.clone(), torrent_process.clone(), announce_url).await
-            }
-            Announce::Udp => {
-                udp::udp_announce(properties.clone(), torrent_process.clone(), announce_url).await
-            }
-            _ => return Ok(()),
-        };
-
-        let interval_to_query_tracker = match try_interval_to_query_tracker {
-            Ok(i) => i,
-            Err(err) => {
-                error!("announce loop error {:?}", err);
-                Duration::from_secs(5)
-            }
-        };
+        let mut interval_to_query_tracker = None;
+        for &info_hash in &info_hashes {
+            let try_interval_to_query_tracker = match proto {
+                Announce::Http => {
+                    http::http_announce(
+                        properties.clone(),
+                        torrent_process.clone(),
+                        announce_url,
+                        info_hash,
+                    )
+                    .await
+                }
+                Announce::Udp => {
+                    udp::udp_announce(
+                        properties.clone(),
+                        torrent_process.clone(),
+                        announce_url,
+                        info_hash,
+                    )
+                    .await
+                }
+                _ => return Ok(()),
+            };
+
+            let interval = match try_interval_to_query_tracker {
+                Ok(i) => i,
+                Err(err) => {
+                    error!("announce loop error {:?}", err);
+                    Duration::from_secs(5)
+                }
+            };
+            interval_to_query_tracker = interval_to_query_tracker
+                .map(|current: Duration| current.min(interval))
+                .or(Some(interval));
+        }
+        let interval_to_query_tracker =
+            interval_to_query_tracker.unwrap_or_else(|| Duration::from_secs(5));
 
         debug!("query tracker in {:?}", interval_to_query_tracker);
 
         delay_for(interval_to_query_tracker).await;
     }
 }
+
+/// Info hashes announced to tracker.
+///
+/// Hybrid torrents are announced in v1 swarm and with truncated v2 hash in v2 swarm.
+fn announce_hashes(torrent_process: &TorrentProcess) -> Vec<[u8; SHA1_SIZE]> {
+    let mut info_hashes = vec![torrent_process.hash_id];
+    if let Some(hash_id_v2) = torrent_process.hash_id_v2 {
+        if hash_id_v2 != torrent_process.hash_id {
+            info_hashes.push(hash_id_v2);
+        }
+    }
+    info_hashes
+}
@@ -10,6 +10,7 @@ pub(crate) async fn udp_announce(
     properties: Arc<Properties>,
     torrent_process: Arc<TorrentProcess>,
     announce_url: &str,
+    info_hash: [u8; SHA1_SIZE],
 ) -> Result<Duration, RsbtError> {
     let addr = SocketAddr::new(properties.listen, properties.port);
     let udp_socket = UdpSocket::bind(addr).await?;
@@ -46,6 +47,7 @@ pub(crate) async fn udp_announce(
                         connection_id,
                         properties,
                         torrent_process.clone(),
+                        info_hash,
                     );
                     debug!("sending udp tracker announce request: {:?}", request);
                     wtransport.send((request.clone(), addr)).await?;
@@ -16,7 +16,10 @@ pub(crate) async fn connect_to_peer(
 
     let handshake_reply: Handshake = handshake_reply.try_into()?;
 
-    if handshake_reply.info_hash != torrent_process.hash_id {
+    // peers of hybrid torrent may reply with any of its hashes
+    if handshake_reply.info_hash != torrent_process.hash_id
+        && Some(handshake_reply.info_hash) != torrent_process.hash_id_v2
+    {
         error!("[{}] peer {:?}: hash is wrong. Disconnect.", peer_id, peer);
         torrent_process
             .broker_sender
@@ -10,6 +10,8 @@ pub(crate) async fn torrent_action(
         match request.action {
             RsbtTorrentAction::Enable => torrent.enable().await,
             RsbtTorrentAction::Disable => torrent.disable().await,
+            RsbtTorrentAction::Recheck => torrent.recheck().await,
+            RsbtTorrentAction::Clear => torrent.clear().await,
         }
     } else {
         Err(RsbtError::TorrentNotFound(id))
@@ -44,9 +46,26 @@ impl TorrentDownload {
         self.update_state(TorrentDownloadStatus::Disabled).await
     }
 
+    /// Clears error by enabling or disabling torrent again, as it was before failure.
+    async fn clear(&mut self) -> Result<(), RsbtError> {
+        debug!("clear {}", self.id);
+
+        if self.header.state == TorrentDownloadStatus::Enabled {
+            self.enable().await
+        } else {
+            self.disable().await
+        }
+    }
+
+    async fn recheck(&mut self) -> Result<(), RsbtError> {
+        debug!("recheck {}", self.id);
+
+        self.request((), DownloadTorrentEvent::Recheck).await
+    }
+
     async fn update_state(&mut self, state: TorrentDownloadStatus) -> Result<(), RsbtError> {
         let mut torrent_header = self.header.clone();
-        torrent_header.state = state;
+        torrent_header.state = state.clone();
         add_to_current_torrents(self.properties.clone(), torrent_header).await?;
 
         self.header.state = state;
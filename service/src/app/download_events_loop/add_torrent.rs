@@ -1,4 +1,5 @@
 use super::*;
+use flat_storage::{available_space, required_space, FlatStorageFile};
 
 pub(crate) async fn add_torrent(
     properties: Arc<Properties>,
@@ -10,21 +11,46 @@ pub(crate) async fn add_torrent(
         data,
         filename,
         state,
+        priorities,
+        sequential,
+        backend,
+        preallocation,
+        category,
+        save_to,
     } = request;
     debug!("we need to download {:?}", filename);
     let filepath = PathBuf::from(&filename);
     let name = filepath.file_stem().unwrap().to_string_lossy().into_owned();
 
     let torrent = parse_torrent(data)?;
-    let hash_id = torrent.info_sha1_hash();
     let info = torrent.info()?;
+    let hash_id = torrent.info_hash(info.version);
+    let hash_id_v2 = if info.version == TorrentVersion::V1 {
+        None
+    } else {
+        Some(torrent.info_hash_v2())
+    };
 
     debug!("torrent size: {}", info.len());
     debug!("piece length: {}", info.piece_length);
     debug!("total pieces: {}", info.pieces.len());
 
+    let location =
+        TorrentStorageLocation::new(&properties, save_to.as_deref(), category.as_deref())?;
+    let storage_backend = backend.unwrap_or(properties.storage_backend);
+    // torrents reloaded on start are in storage already, their space is taken or reserved
+    let reloaded = properties.storage.join(filename).is_file();
+    if storage_backend != StorageBackend::Memory && !reloaded {
+        let moved = location.files_moved(&info.files);
+        let (download_path, files) = location.storage_files(&info.files, moved);
+        check_free_space(properties.clone(), download_path, files, priorities).await?;
+    }
+
     let mut handshake = vec![];
     handshake.extend_from_slice(&crate::types::HANDSHAKE_PREFIX);
+    if hash_id_v2.is_some() {
+        handshake[HANDSHAKE_V2_RESERVED_BYTE] |= HANDSHAKE_V2_RESERVED_BIT;
+    }
     handshake.extend_from_slice(&hash_id);
     handshake.extend_from_slice(&PEER_ID);
 
@@ -35,21 +61,36 @@ pub(crate) async fn add_torrent(
     let torrent_process = Arc::new(TorrentProcess {
         info,
         hash_id,
+        hash_id_v2,
         torrent,
         handshake,
         broker_sender,
+        bandwidth: properties.bandwidth.clone(),
+        download_bucket: Default::default(),
+        upload_bucket: Default::default(),
     });
 
     let torrent_storage = TorrentStorage::new(
         properties.clone(),
         filename.clone(),
         torrent_process.clone(),
+        priorities,
+        storage_backend,
+        preallocation.unwrap_or(properties.preallocation),
+        location,
     )
     .await?;
+    let priorities = torrent_storage.receiver.borrow().priorities.clone();
 
     let torrent_header = TorrentDownloadHeader {
         file: filename.clone(),
         state: state.clone(),
+        priorities: priorities.clone(),
+        sequential: *sequential,
+        backend: *backend,
+        preallocation: *preallocation,
+        category: category.clone(),
+        save_to: save_to.clone(),
     };
     let storage_state_watch = torrent_storage.receiver.clone();
     tokio::spawn(download_torrent(
@@ -57,6 +98,8 @@ pub(crate) async fn add_torrent(
         torrent_storage,
         torrent_process.clone(),
         broker_receiver,
+        priorities,
+        *sequential,
     ));
 
     let (statistics_request_response, statistics_receiver) = RequestResponse::new(());
@@ -69,6 +112,18 @@ pub(crate) async fn add_torrent(
 
     let statistics_watch = statistics_receiver.await?;
 
+    let (status_request_response, status_receiver) = RequestResponse::new(());
+
+    torrent_process
+        .broker_sender
+        .clone()
+        .send(DownloadTorrentEvent::SubscribeStatus(
+            status_request_response,
+        ))
+        .await?;
+
+    let status_watch = status_receiver.await?;
+
     let torrent_download = TorrentDownload {
         id: *id,
         name,
@@ -77,6 +132,7 @@ pub(crate) async fn add_torrent(
         properties: properties.clone(),
         storage_state_watch,
         statistics_watch,
+        status_watch,
     };
 
     add_to_current_torrents(properties.clone(), torrent_header).await?;
@@ -97,3 +153,38 @@ pub(crate) async fn add_torrent(
 
     Ok(torrent_download)
 }
+
+/// Fails if files of torrent, which are not skipped, do not fit on disk.
+///
+/// Files are checked as they are stored at `download_path`, with part suffix if they are in
+/// progress.
+async fn check_free_space(
+    properties: Arc<Properties>,
+    download_path: PathBuf,
+    files: Vec<FlatStorageFile>,
+    priorities: &[FilePriority],
+) -> Result<(), RsbtError> {
+    let skipped: Vec<bool> = (0..files.len())
+        .map(|index| priorities.get(index) == Some(&FilePriority::Skip))
+        .collect();
+    let (required, available) = properties
+        .disk_io
+        .run(DiskJobPriority::Read, move || -> Result<_, RsbtError> {
+            Ok((
+                required_space(&download_path, &files, &skipped)?,
+                available_space(&download_path)?,
+            ))
+        })
+        .await??;
+    debug!(
+        "disk space required: {}, available: {}",
+        required, available
+    );
+    if required > available {
+        return Err(RsbtError::NotEnoughSpace {
+            required,
+            available,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use super::*;
+
+pub(crate) fn bandwidth_limits(properties: Arc<Properties>) -> Result<Limits, RsbtError> {
+    Ok(properties.bandwidth.limits())
+}
+
+/// Sets limits and saves them, so they survive restart.
+pub(crate) async fn set_bandwidth_limits(
+    properties: Arc<Properties>,
+    request: &Limits,
+) -> Result<(), RsbtError> {
+    debug!("set bandwidth limits: {:?}", request);
+    properties.bandwidth.set_limits(request.clone())?;
+    let limits_toml = properties.config_dir.join(LIMITS_TOML);
+    fs::write(limits_toml, toml::to_string(request)?).await?;
+    Ok(())
+}
+
+/// Restores limits saved with [`set_bandwidth_limits`], they override limits from settings.
+pub(crate) async fn load_bandwidth_limits(properties: Arc<Properties>) -> Result<(), RsbtError> {
+    let limits_toml = properties.config_dir.join(LIMITS_TOML);
+    if limits_toml.is_file() {
+        let limits: Limits = toml::from_str(&fs::read_to_string(&limits_toml).await?)?;
+        debug!("load bandwidth limits: {:?}", limits);
+        properties.bandwidth.set_limits(limits)?;
+    }
+    Ok(())
+}
@@ -1,31 +1,42 @@
 use super::*;
+use crate::types::{Limits, Preallocation, StorageBackend};
 use serde_with::skip_serializing_none;
 use std::path::PathBuf;
 
 mod action;
 mod add_torrent;
+mod bandwidth_limits;
 mod current_torrents;
 mod delete_torrent;
+mod statistics;
 mod torrent_announces;
 mod torrent_detail;
 mod torrent_file_download;
+mod torrent_file_priority;
 mod torrent_files;
+mod torrent_move;
 mod torrent_peers;
 mod torrent_pieces;
+mod torrent_sequential;
 
 pub use crate::storage::RsbtFileDownloadStream;
 use crate::storage::TorrentStorageState;
 use action::torrent_action;
 use add_torrent::add_torrent;
+use bandwidth_limits::{bandwidth_limits, load_bandwidth_limits, set_bandwidth_limits};
 use current_torrents::{add_to_current_torrents, remove_from_current_torrents};
 use delete_torrent::delete_torrent;
 use download_torrent::TorrentDownloadState;
+use statistics::statistics;
 use torrent_announces::torrent_announces;
 use torrent_detail::torrent_detail;
 use torrent_file_download::torrent_file_download;
+use torrent_file_priority::torrent_file_priority;
 use torrent_files::torrent_files;
+use torrent_move::torrent_move;
 use torrent_peers::torrent_peers;
 use torrent_pieces::torrent_pieces;
+use torrent_sequential::torrent_sequential;
 
 #[derive(Debug, Serialize, Clone)]
 pub struct TorrentDownloadView {
@@ -40,6 +51,19 @@ pub struct TorrentDownloadView {
     pub piece_size: u32,
     pub length: usize,
     pub active: bool,
+    pub sequential: bool,
+    pub checking: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moving: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RsbtTorrentErrorView>,
+}
+
+/// Error which stopped torrent
+#[derive(Debug, Serialize, Clone)]
+pub struct RsbtTorrentErrorView {
+    pub kind: TorrentErrorKind,
+    pub message: String,
 }
 
 #[derive(Debug, Clone)]
@@ -51,6 +75,7 @@ pub struct TorrentDownload {
     pub properties: Arc<Properties>,
     pub storage_state_watch: watch::Receiver<TorrentStorageState>,
     pub statistics_watch: watch::Receiver<TorrentDownloadState>,
+    pub status_watch: watch::Receiver<TorrentDownloadStatus>,
 }
 
 impl TorrentDownload {
@@ -70,22 +95,32 @@ impl TorrentDownload {
 
 impl From<&TorrentDownload> for TorrentDownloadView {
     fn from(torrent: &TorrentDownload) -> Self {
-        let (read, write, pieces_left) = {
+        let (read, write, pieces_left, checking, moving) = {
             let storage_state = torrent.storage_state_watch.borrow();
             (
                 storage_state.bytes_read,
                 storage_state.bytes_write,
                 storage_state.pieces_left,
+                storage_state.checking,
+                storage_state.moving,
             )
         };
         let (tx, rx) = {
             let state = torrent.statistics_watch.borrow();
             (state.uploaded, state.downloaded)
         };
+        let (active, error) = {
+            let status = torrent.status_watch.borrow();
+            (*status == TorrentDownloadStatus::Enabled, status.error())
+        };
         Self {
             id: torrent.id,
             name: torrent.name.clone(),
-            active: torrent.header.state == TorrentDownloadStatus::Enabled,
+            active,
+            error,
+            sequential: torrent.header.sequential,
+            checking,
+            moving,
             length: torrent.process.info.length,
             write,
             read,
@@ -102,12 +137,74 @@ impl From<&TorrentDownload> for TorrentDownloadView {
 pub struct TorrentDownloadHeader {
     pub file: String,
     pub state: TorrentDownloadStatus,
+    /// Download priorities of torrent files, by file id
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub priorities: Vec<FilePriority>,
+    /// Download pieces in order
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sequential: bool,
+    /// Storage backend, default from config if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<StorageBackend>,
+    /// Preallocation of torrent files, default from config if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preallocation: Option<Preallocation>,
+    /// Directory in download path, where completed files are saved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// Download path of torrent, default from config if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub save_to: Option<PathBuf>,
+}
+
+/// Download priority of single torrent file.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum FilePriority {
+    /// Do not download file
+    Skip,
+    Low,
+    Normal,
+    High,
+}
+
+impl Default for FilePriority {
+    fn default() -> Self {
+        FilePriority::Normal
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum TorrentDownloadStatus {
     Enabled,
     Disabled,
+    /// Torrent is stopped because of failure, until error is cleared
+    Error {
+        kind: TorrentErrorKind,
+        message: String,
+    },
+}
+
+/// Kind of failure which stopped torrent
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TorrentErrorKind {
+    /// No space left on disk
+    DiskFull,
+    /// Data cannot be read or written
+    Io,
+}
+
+impl TorrentDownloadStatus {
+    pub fn error(&self) -> Option<RsbtTorrentErrorView> {
+        match self {
+            TorrentDownloadStatus::Error { kind, message } => Some(RsbtTorrentErrorView {
+                kind: *kind,
+                message: message.clone(),
+            }),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -115,6 +212,12 @@ pub struct RsbtCommandAddTorrent {
     pub data: Vec<u8>,
     pub filename: String,
     pub state: TorrentDownloadStatus,
+    pub priorities: Vec<FilePriority>,
+    pub sequential: bool,
+    pub backend: Option<StorageBackend>,
+    pub preallocation: Option<Preallocation>,
+    pub category: Option<String>,
+    pub save_to: Option<PathBuf>,
 }
 
 #[derive(Debug)]
@@ -154,6 +257,25 @@ pub struct RsbtCommandTorrentFileDownload {
     pub file_id: usize,
 }
 
+#[derive(Debug)]
+pub struct RsbtCommandTorrentSequential {
+    pub id: usize,
+    pub sequential: bool,
+}
+
+#[derive(Debug)]
+pub struct RsbtCommandTorrentMove {
+    pub id: usize,
+    pub save_to: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct RsbtCommandTorrentFilePriority {
+    pub id: usize,
+    pub file_id: usize,
+    pub priority: FilePriority,
+}
+
 #[derive(Serialize, Clone, Debug)]
 pub struct RsbtAnnounceView {
     pub(crate) url: String,
@@ -165,6 +287,7 @@ pub struct RsbtFileView {
     pub(crate) name: String,
     pub(crate) saved: usize,
     pub(crate) size: usize,
+    pub(crate) priority: FilePriority,
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -187,6 +310,9 @@ pub enum RsbtPeerStateView {
         rx: usize,
         tx: usize,
     },
+    Disconnected {
+        reason: String,
+    },
 }
 
 impl From<&TorrentPeerState> for RsbtPeerStateView {
@@ -209,10 +335,30 @@ impl From<&TorrentPeerState> for RsbtPeerStateView {
                 rx: *downloaded,
                 tx: *uploaded,
             },
+            TorrentPeerState::Disconnected(reason) => Self::Disconnected {
+                reason: reason.clone(),
+            },
         }
     }
 }
 
+/// Client wide statistics
+#[derive(Serialize, Clone, Debug)]
+pub struct RsbtStatisticsView {
+    /// Count of disk I/O threads
+    pub disk_io_threads: usize,
+    /// Count of disk jobs waiting for execution
+    pub disk_io_queue_depth: usize,
+    /// Size of read cache in bytes
+    pub read_cache_capacity: usize,
+    /// Size of pieces in read cache in bytes
+    pub read_cache_size: usize,
+    /// Count of reads served from read cache
+    pub read_cache_hits: u64,
+    /// Count of reads which missed read cache
+    pub read_cache_misses: u64,
+}
+
 impl From<&PeerState> for RsbtPeerView {
     fn from(value: &PeerState) -> Self {
         let state = &value.state;
@@ -245,6 +391,12 @@ pub enum RsbtCommand {
     TorrentFileDownload(
         RequestResponse<RsbtCommandTorrentFileDownload, Result<RsbtFileDownloadStream, RsbtError>>,
     ),
+    TorrentFilePriority(RequestResponse<RsbtCommandTorrentFilePriority, Result<(), RsbtError>>),
+    TorrentSequential(RequestResponse<RsbtCommandTorrentSequential, Result<(), RsbtError>>),
+    TorrentMove(RequestResponse<RsbtCommandTorrentMove, Result<(), RsbtError>>),
+    BandwidthLimits(RequestResponse<(), Result<Limits, RsbtError>>),
+    SetBandwidthLimits(RequestResponse<Limits, Result<(), RsbtError>>),
+    Statistics(RequestResponse<(), Result<RsbtStatisticsView, RsbtError>>),
 }
 
 pub(crate) async fn download_events_loop(
@@ -254,6 +406,10 @@ pub(crate) async fn download_events_loop(
     let mut torrents = vec![];
     let mut id = 0;
 
+    if let Err(err) = load_bandwidth_limits(properties.clone()).await {
+        error!("cannot load bandwidth limits: {}", err);
+    }
+
     while let Some(event) = events.next().await {
         match event {
             RsbtCommand::AddTorrent(request_response) => {
@@ -282,7 +438,7 @@ pub(crate) async fn download_events_loop(
                         torrents
                             .iter()
                             .map(|x| &x.process)
-                            .find(|x| x.hash_id == hash_id)
+                            .find(|x| x.hash_id == hash_id || x.hash_id_v2 == Some(hash_id))
                             .cloned(),
                     )
                     .is_err()
@@ -347,6 +503,34 @@ pub(crate) async fn download_events_loop(
                     error!("cannot send response for torrent's files: {}", err);
                 }
             }
+            RsbtCommand::TorrentFilePriority(request_response) => {
+                debug!("torrent's file priority");
+                let response =
+                    torrent_file_priority(request_response.request(), &mut torrents).await;
+
+                if let Err(err) = request_response.response(response) {
+                    error!("cannot send response for torrent's file priority: {}", err);
+                }
+            }
+            RsbtCommand::TorrentSequential(request_response) => {
+                debug!("torrent's sequential mode");
+                let response = torrent_sequential(request_response.request(), &mut torrents).await;
+
+                if let Err(err) = request_response.response(response) {
+                    error!(
+                        "cannot send response for torrent's sequential mode: {}",
+                        err
+                    );
+                }
+            }
+            RsbtCommand::TorrentMove(request_response) => {
+                debug!("torrent's move");
+                let response = torrent_move(request_response.request(), &mut torrents).await;
+
+                if let Err(err) = request_response.response(response) {
+                    error!("cannot send response for torrent's move: {}", err);
+                }
+            }
             RsbtCommand::TorrentPieces(request_response) => {
                 debug!("torrent's pieces");
                 let response = torrent_pieces(request_response.request(), &torrents).await;
@@ -363,6 +547,31 @@ pub(crate) async fn download_events_loop(
                     error!("cannot send response for torrent's detail: {}", err);
                 }
             }
+            RsbtCommand::BandwidthLimits(request_response) => {
+                debug!("bandwidth limits");
+                let response = bandwidth_limits(properties.clone());
+
+                if let Err(err) = request_response.response(response) {
+                    error!("cannot send response for bandwidth limits: {}", err);
+                }
+            }
+            RsbtCommand::SetBandwidthLimits(request_response) => {
+                debug!("set bandwidth limits");
+                let response =
+                    set_bandwidth_limits(properties.clone(), request_response.request()).await;
+
+                if let Err(err) = request_response.response(response) {
+                    error!("cannot send response for set bandwidth limits: {}", err);
+                }
+            }
+            RsbtCommand::Statistics(request_response) => {
+                debug!("statistics");
+                let response = statistics(properties.clone());
+
+                if let Err(err) = request_response.response(response) {
+                    error!("cannot send response for statistics: {}", err);
+                }
+            }
         }
     }
 
@@ -0,0 +1,14 @@
+use super::*;
+
+pub(crate) fn statistics(properties: Arc<Properties>) -> Result<RsbtStatisticsView, RsbtError> {
+    let disk_io = &properties.disk_io;
+    let read_cache = &properties.read_cache;
+    Ok(RsbtStatisticsView {
+        disk_io_threads: disk_io.threads(),
+        disk_io_queue_depth: disk_io.queue_depth(),
+        read_cache_capacity: read_cache.capacity(),
+        read_cache_size: read_cache.size(),
+        read_cache_hits: read_cache.hits(),
+        read_cache_misses: read_cache.misses(),
+    })
+}
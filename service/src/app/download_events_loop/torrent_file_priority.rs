@@ -0,0 +1,49 @@
+use super::*;
+
+pub(crate) async fn torrent_file_priority(
+    request: &RsbtCommandTorrentFilePriority,
+    torrents: &mut Vec<TorrentDownload>,
+) -> Result<(), RsbtError> {
+    let id = request.id;
+
+    if let Some(torrent) = torrents.iter_mut().find(|x| x.id == id) {
+        torrent
+            .file_priority(request.file_id, request.priority)
+            .await
+    } else {
+        Err(RsbtError::TorrentNotFound(id))
+    }
+}
+
+impl TorrentDownload {
+    async fn file_priority(
+        &mut self,
+        file_id: usize,
+        priority: FilePriority,
+    ) -> Result<(), RsbtError> {
+        debug!("file {} priority {:?} for {}", file_id, priority, self.id);
+
+        let files_count = self.process.info.files.len();
+        if file_id >= files_count {
+            return Err(RsbtError::TorrentFileNotFound(file_id));
+        }
+
+        let mut torrent_header = self.header.clone();
+        torrent_header
+            .priorities
+            .resize(files_count, FilePriority::default());
+        torrent_header.priorities[file_id] = priority;
+
+        self.request(
+            torrent_header.priorities.clone(),
+            DownloadTorrentEvent::FilePriorities,
+        )
+        .await?;
+
+        add_to_current_torrents(self.properties.clone(), torrent_header.clone()).await?;
+
+        self.header = torrent_header;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use super::*;
+
+pub(crate) async fn torrent_move(
+    request: &RsbtCommandTorrentMove,
+    torrents: &mut Vec<TorrentDownload>,
+) -> Result<(), RsbtError> {
+    let id = request.id;
+
+    if let Some(torrent) = torrents.iter_mut().find(|x| x.id == id) {
+        torrent.move_storage(request.save_to.clone()).await
+    } else {
+        Err(RsbtError::TorrentNotFound(id))
+    }
+}
+
+impl TorrentDownload {
+    /// Starts move of torrent files to `save_to`, torrent is paused until files are moved.
+    ///
+    /// New save path is saved once move is started, if move is interrupted, it is finished
+    /// on next start.
+    async fn move_storage(&mut self, save_to: PathBuf) -> Result<(), RsbtError> {
+        debug!("move {} to {:?}", self.id, save_to);
+
+        let location = TorrentStorageLocation::new(
+            &self.properties,
+            Some(&save_to),
+            self.header.category.as_deref(),
+        )?;
+        self.request(location, DownloadTorrentEvent::Move).await?;
+
+        let mut torrent_header = self.header.clone();
+        torrent_header.save_to = Some(save_to);
+        add_to_current_torrents(self.properties.clone(), torrent_header.clone()).await?;
+
+        self.header = torrent_header;
+
+        Ok(())
+    }
+}
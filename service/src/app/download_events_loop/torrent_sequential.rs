@@ -0,0 +1,31 @@
+use super::*;
+
+pub(crate) async fn torrent_sequential(
+    request: &RsbtCommandTorrentSequential,
+    torrents: &mut Vec<TorrentDownload>,
+) -> Result<(), RsbtError> {
+    let id = request.id;
+
+    if let Some(torrent) = torrents.iter_mut().find(|x| x.id == id) {
+        torrent.sequential(request.sequential).await
+    } else {
+        Err(RsbtError::TorrentNotFound(id))
+    }
+}
+
+impl TorrentDownload {
+    async fn sequential(&mut self, sequential: bool) -> Result<(), RsbtError> {
+        debug!("sequential mode {} for {}", sequential, self.id);
+
+        self.request(sequential, DownloadTorrentEvent::Sequential)
+            .await?;
+
+        let mut torrent_header = self.header.clone();
+        torrent_header.sequential = sequential;
+        add_to_current_torrents(self.properties.clone(), torrent_header).await?;
+
+        self.header.sequential = sequential;
+
+        Ok(())
+    }
+}
@@ -4,6 +4,7 @@ use std::{sync::Mutex, task::Waker};
 
 mod process_announce;
 mod process_peer_announced;
+mod process_peer_block_downloaded;
 mod process_peer_connected;
 mod process_peer_forwarded;
 mod process_peer_interested;
@@ -13,9 +14,11 @@ mod process_peer_piece_downloaded;
 mod process_peer_piece_request;
 mod process_peer_pieces;
 mod process_peer_unchoke;
+mod process_priorities_changed;
 
 use process_announce::process_announce;
 use process_peer_announced::process_peer_announced;
+use process_peer_block_downloaded::process_peer_block_downloaded;
 use process_peer_connected::process_peer_connected;
 use process_peer_forwarded::process_peer_forwarded;
 use process_peer_interested::process_peer_interested;
@@ -25,6 +28,7 @@ use process_peer_piece_downloaded::process_peer_piece_downloaded;
 use process_peer_piece_request::process_peer_piece_request;
 use process_peer_pieces::process_peer_pieces;
 use process_peer_unchoke::process_peer_unchoke;
+use process_priorities_changed::process_priorities_changed;
 
 #[derive(Debug)]
 pub(crate) struct DownloadTorrentEventQueryPiece {
@@ -39,12 +43,18 @@ pub(crate) enum DownloadTorrentEvent {
     PeerConnected(Uuid, TcpStream),
     PeerForwarded(TcpStream),
     PeerConnectFailed(Uuid),
-    PeerDisconnect(Uuid),
+    PeerDisconnect(Uuid, Option<String>),
     PeerPieces(Uuid, Vec<u8>),
     PeerPiece(Uuid, usize),
     PeerUnchoke(Uuid),
     PeerInterested(Uuid),
-    PeerPieceDownloaded(Uuid, Vec<u8>),
+    PeerBlockDownloaded {
+        peer_id: Uuid,
+        index: usize,
+        begin: usize,
+        block: Vec<u8>,
+    },
+    PeerPieceDownloaded(Uuid),
     PeerPieceCanceled(Uuid),
     PeerPieceRequest {
         peer_id: Uuid,
@@ -55,20 +65,36 @@ pub(crate) enum DownloadTorrentEvent {
     Enable(RequestResponse<(), Result<(), RsbtError>>),
     Disable(RequestResponse<(), Result<(), RsbtError>>),
     Subscribe(RequestResponse<(), watch::Receiver<TorrentDownloadState>>),
+    SubscribeStatus(RequestResponse<(), watch::Receiver<TorrentDownloadStatus>>),
     Delete(RequestResponse<bool, Result<(), RsbtError>>),
+    Recheck(RequestResponse<(), Result<(), RsbtError>>),
     PeersView(RequestResponse<(), Result<Vec<RsbtPeerView>, RsbtError>>),
     AnnounceView(RequestResponse<(), Result<Vec<RsbtAnnounceView>, RsbtError>>),
     FilesView(RequestResponse<(), Result<Vec<RsbtFileView>, RsbtError>>),
+    FilePriorities(RequestResponse<Vec<FilePriority>, Result<(), RsbtError>>),
+    Sequential(RequestResponse<bool, Result<(), RsbtError>>),
     FileDownload(RequestResponse<usize, Result<RsbtFileDownloadStream, RsbtError>>),
     QueryPiece(RequestResponse<DownloadTorrentEventQueryPiece, Result<Vec<u8>, RsbtError>>),
+    Move(RequestResponse<TorrentStorageLocation, Result<(), RsbtError>>),
+    StorageMoved(Result<(), RsbtError>),
 }
 
 impl Display for DownloadTorrentEvent {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         match self {
-            DownloadTorrentEvent::PeerPieceDownloaded(uuid, data) => {
-                write!(f, "PeerPieceDownloaded({}, [{}])", uuid, data.len())
-            }
+            DownloadTorrentEvent::PeerBlockDownloaded {
+                peer_id,
+                index,
+                begin,
+                block,
+            } => write!(
+                f,
+                "PeerBlockDownloaded({}, {}, {}, [{}])",
+                peer_id,
+                index,
+                begin,
+                block.len()
+            ),
             _ => write!(f, "{:?}", self),
         }
     }
@@ -92,12 +118,21 @@ pub(crate) async fn download_torrent(
     mut torrent_storage: TorrentStorage,
     torrent_process: Arc<TorrentProcess>,
     mut broker_receiver: Receiver<DownloadTorrentEvent>,
+    mut file_priorities: Vec<FilePriority>,
+    sequential: bool,
 ) {
     let mut peer_states = HashMap::new();
+    let mut priorities = PiecePriorities {
+        pieces: piece_priorities(&torrent_process.info, &file_priorities),
+        sequential,
+        ..Default::default()
+    };
     let mut mode = TorrentDownloadMode::Normal;
     let mut active = false;
     let mut announce_abort_handle = None;
+    let mut resume_after_move = false;
     let mut awaiting_for_piece = HashMap::new();
+    let (status_sender, status_receiver) = watch::channel(TorrentDownloadStatus::Disabled);
 
     let (mut statistic_sender, mut statistic_receiver) = mpsc::channel(DEFAULT_CHANNEL_BUFFER);
 
@@ -156,11 +191,22 @@ pub(crate) async fn download_torrent(
                     error!("cannot process peer announced {:?}: {}", peer, err);
                 }
             }
-            DownloadTorrentEvent::PeerDisconnect(peer_id) => {
-                if let Some(_peer_state) = peer_states.remove(&peer_id) {
+            DownloadTorrentEvent::PeerDisconnect(peer_id, None) => {
+                // banned peers are kept to not connect them again
+                let banned = matches!(
+                    peer_states.get(&peer_id).map(|x| &x.state),
+                    Some(TorrentPeerState::Disconnected(_))
+                );
+                if !banned && peer_states.remove(&peer_id).is_some() {
                     debug!("[{}] removed peer due to disconnect", peer_id);
                 }
             }
+            DownloadTorrentEvent::PeerDisconnect(peer_id, Some(reason)) => {
+                if let Some(peer_state) = peer_states.get_mut(&peer_id) {
+                    debug!("[{}] peer disconnected: {}", peer_id, reason);
+                    peer_state.state = TorrentPeerState::Disconnected(reason);
+                }
+            }
             DownloadTorrentEvent::PeerConnectFailed(peer_id) => {
                 if let Some(_peer_state) = peer_states.remove(&peer_id) {
                     debug!("[{}] removed peer due to connection failure", peer_id);
@@ -199,6 +245,7 @@ pub(crate) async fn download_torrent(
                 if let Err(err) = process_peer_piece(
                     &mut peer_states,
                     &mode,
+                    &priorities,
                     peer_id,
                     piece,
                     &mut torrent_storage,
@@ -213,6 +260,7 @@ pub(crate) async fn download_torrent(
                 if let Err(err) = process_peer_pieces(
                     &mut peer_states,
                     &mode,
+                    &priorities,
                     peer_id,
                     pieces,
                     &mut torrent_storage,
@@ -239,6 +287,7 @@ pub(crate) async fn download_torrent(
                 if let Err(err) = process_peer_piece_canceled(
                     &mut peer_states,
                     &mode,
+                    &priorities,
                     peer_id,
                     &mut torrent_storage,
                 )
@@ -247,13 +296,41 @@ pub(crate) async fn download_torrent(
                     error!("[{}] cannot process peer piece canceled: {}", peer_id, err);
                 }
             }
-            DownloadTorrentEvent::PeerPieceDownloaded(peer_id, piece) => {
+            DownloadTorrentEvent::PeerBlockDownloaded {
+                peer_id,
+                index,
+                begin,
+                block,
+            } => {
+                debug!("[{}] downloaded block for peer", peer_id);
+                if let Err(err) = process_peer_block_downloaded(
+                    &mut peer_states,
+                    peer_id,
+                    index,
+                    begin,
+                    block,
+                    &mut torrent_storage,
+                )
+                .await
+                {
+                    error!(
+                        "[{}] cannot process peer block downloaded: {}",
+                        peer_id, err
+                    );
+                    if let Some(status) = storage_failure(&err) {
+                        stop_peers(&mut peer_states, &mut announce_abort_handle).await;
+                        active = false;
+                        broadcast_status(&status_sender, status);
+                    }
+                }
+            }
+            DownloadTorrentEvent::PeerPieceDownloaded(peer_id) => {
                 debug!("[{}] downloaded piece for peer", peer_id);
                 if let Err(err) = process_peer_piece_downloaded(
                     &mut peer_states,
                     &mode,
+                    &priorities,
                     peer_id,
-                    piece.into(),
                     &mut torrent_storage,
                     &mut awaiting_for_piece,
                 )
@@ -263,7 +340,16 @@ pub(crate) async fn download_torrent(
                         "[{}] cannot process peer piece downloaded: {}",
                         peer_id, err
                     );
+                    if let Some(status) = storage_failure(&err) {
+                        stop_peers(&mut peer_states, &mut announce_abort_handle).await;
+                        active = false;
+                        broadcast_status(&status_sender, status);
+                        continue;
+                    }
                 }
+                priorities
+                    .deadline
+                    .retain(|piece| awaiting_for_piece.contains_key(piece));
 
                 mode = determine_download_mode(&mut peer_states, &mut torrent_storage, peer_id);
 
@@ -295,9 +381,23 @@ pub(crate) async fn download_torrent(
                 .await
                 {
                     error!("[{}] cannot process peer piece request: {}", peer_id, err);
+                    if let Some(status) = storage_failure(&err) {
+                        stop_peers(&mut peer_states, &mut announce_abort_handle).await;
+                        active = false;
+                        broadcast_status(&status_sender, status);
+                    }
                 }
             }
             DownloadTorrentEvent::Enable(request_response) => {
+                if torrent_storage.receiver.borrow().moving.is_some() {
+                    debug!("torrent files are moving, enable torrent when moved");
+                    resume_after_move = true;
+                    broadcast_status(&status_sender, TorrentDownloadStatus::Enabled);
+                    if let Err(err) = request_response.response(Ok(())) {
+                        error!("cannot send response for enable torrent: {}", err);
+                    }
+                    continue;
+                }
                 if active {
                     if let Err(err) = request_response.response(Ok(())) {
                         error!("cannot send response for disable torrent: {}", err);
@@ -305,59 +405,34 @@ pub(crate) async fn download_torrent(
                     continue;
                 }
 
-                let (abort_handle, abort_registration) = AbortHandle::new_pair();
-
-                let announce_loop = Abortable::new(
-                    announce::announce_loop(properties.clone(), torrent_process.clone()).map_err(
-                        |e| {
-                            error!("announce loop error: {}", e);
-                            e
-                        },
-                    ),
-                    abort_registration,
-                );
-
-                tokio::spawn(announce_loop);
-
-                announce_abort_handle = Some(abort_handle);
+                announce_abort_handle =
+                    Some(start_announce(properties.clone(), torrent_process.clone()));
                 if let Err(err) = request_response.response(Ok(())) {
                     error!("cannot send response for enable torrent: {}", err);
                 }
                 active = true;
+                broadcast_status(&status_sender, TorrentDownloadStatus::Enabled);
             }
             DownloadTorrentEvent::Disable(request_response) => {
                 if !active {
+                    // error status is kept, torrent is enabled only to resume after move
+                    if resume_after_move {
+                        broadcast_status(&status_sender, TorrentDownloadStatus::Disabled);
+                    }
+                    resume_after_move = false;
                     if let Err(err) = request_response.response(Ok(())) {
                         error!("cannot send response for disable torrent: {}", err);
                     }
                     continue;
                 }
-                if let Some(abort_handle) = announce_abort_handle.take() {
-                    abort_handle.abort();
-                }
-
-                for (peer_id, peer_state) in peer_states {
-                    match peer_state.state {
-                        TorrentPeerState::Connected { mut sender, .. } => {
-                            if let Err(err) = sender.send(PeerMessage::Disconnect).await {
-                                error!(
-                                    "[{}] disable torrent: cannot send disconnect message to peer: {}",
-                                    peer_id, err
-                                );
-                            }
-                        }
-                        TorrentPeerState::Connecting(_) => {
-                            error!("FIXME: need to stop cennecting too");
-                        }
-                        _ => (),
-                    }
-                }
-                peer_states = HashMap::new();
+                stop_peers(&mut peer_states, &mut announce_abort_handle).await;
 
                 if let Err(err) = request_response.response(Ok(())) {
                     error!("cannot send response for disable torrent: {}", err);
                 }
                 active = false;
+                resume_after_move = false;
+                broadcast_status(&status_sender, TorrentDownloadStatus::Disabled);
             }
             DownloadTorrentEvent::Subscribe(request_response) => {
                 if let Err(err) = statistic_sender
@@ -367,6 +442,14 @@ pub(crate) async fn download_torrent(
                     error!("cannot subscribe: {}", err);
                 }
             }
+            DownloadTorrentEvent::SubscribeStatus(request_response) => {
+                if let Err(err) = request_response.response(status_receiver.clone()) {
+                    error!(
+                        "cannot send subscription response to torrent status: {}",
+                        err
+                    );
+                }
+            }
             DownloadTorrentEvent::Delete(request_response) => {
                 let delete_result = torrent_storage.delete(*request_response.request()).await;
 
@@ -375,6 +458,13 @@ pub(crate) async fn download_torrent(
                 }
                 break;
             }
+            DownloadTorrentEvent::Recheck(request_response) => {
+                let recheck_result = torrent_storage.recheck().await;
+
+                if let Err(err) = request_response.response(recheck_result) {
+                    error!("cannot send response for recheck torrent: {}", err);
+                }
+            }
             DownloadTorrentEvent::PeersView(request_response) => {
                 let peers_view = peer_states.values().map(RsbtPeerView::from).collect();
 
@@ -390,12 +480,59 @@ pub(crate) async fn download_torrent(
                 }
             }
             DownloadTorrentEvent::FilesView(request_response) => {
-                let files_result = torrent_storage.files().await;
+                let files_result = torrent_storage.files().await.map(|files| {
+                    files
+                        .into_iter()
+                        .map(|file| RsbtFileView {
+                            priority: file_priorities.get(file.id).copied().unwrap_or_default(),
+                            ..file
+                        })
+                        .collect()
+                });
 
                 if let Err(err) = request_response.response(files_result) {
                     error!("cannot send response for delete torrent: {}", err);
                 }
             }
+            DownloadTorrentEvent::FilePriorities(request_response) => {
+                file_priorities = request_response.request().clone();
+                priorities.pieces = piece_priorities(&torrent_process.info, &file_priorities);
+
+                let result = match torrent_storage
+                    .set_priorities(file_priorities.clone())
+                    .await
+                {
+                    Ok(()) => {
+                        process_priorities_changed(
+                            &mut peer_states,
+                            &mode,
+                            &priorities,
+                            &mut torrent_storage,
+                        )
+                        .await
+                    }
+                    Err(err) => Err(err),
+                };
+
+                if let Err(err) = request_response.response(result) {
+                    error!("cannot send response for file priorities: {}", err);
+                }
+            }
+            DownloadTorrentEvent::Sequential(request_response) => {
+                priorities.sequential = *request_response.request();
+
+                let result = process_priorities_changed(
+                    &mut peer_states,
+                    &mode,
+                    &priorities,
+                    &mut torrent_storage,
+                )
+                .await;
+
+                if let Err(err) = request_response.response(result) {
+                    error!("cannot send response for sequential mode: {}", err);
+                }
+            }
             DownloadTorrentEvent::FileDownload(request_response) => {
                 debug!("processing file download");
                 let files_download = torrent_storage.download(*request_response.request()).await;
@@ -404,6 +541,49 @@ pub(crate) async fn download_torrent(
                     error!("cannot send response for download torrent: {}", err);
                 }
             }
+            DownloadTorrentEvent::Move(request_response) => {
+                let was_active = active;
+                if active {
+                    stop_peers(&mut peer_states, &mut announce_abort_handle).await;
+                    active = false;
+                }
+                let result = torrent_storage
+                    .move_to(request_response.request().clone())
+                    .await;
+                if result.is_ok() {
+                    resume_after_move = was_active;
+                } else if was_active {
+                    announce_abort_handle =
+                        Some(start_announce(properties.clone(), torrent_process.clone()));
+                    active = true;
+                }
+
+                if let Err(err) = request_response.response(result) {
+                    error!("cannot send response for move torrent: {}", err);
+                }
+            }
+            DownloadTorrentEvent::StorageMoved(result) => match result {
+                Ok(()) => {
+                    debug!("torrent files moved");
+                    if resume_after_move && !active {
+                        announce_abort_handle =
+                            Some(start_announce(properties.clone(), torrent_process.clone()));
+                        active = true;
+                    }
+                    resume_after_move = false;
+                }
+                Err(err) => {
+                    error!("cannot move torrent files: {}", err);
+                    resume_after_move = false;
+                    broadcast_status(
+                        &status_sender,
+                        storage_failure(&err).unwrap_or_else(|| TorrentDownloadStatus::Error {
+                            kind: TorrentErrorKind::Io,
+                            message: err.to_string(),
+                        }),
+                    );
+                }
+            },
             DownloadTorrentEvent::QueryPiece(request_response) => {
                 debug!("query piece event: processing query piece");
                 let request = request_response.request();
@@ -456,6 +636,19 @@ pub(crate) async fn download_torrent(
                     .or_insert_with(|| vec![]);
                 awaiters.push(request_response);
                 dbg!(&awaiters);
+
+                if priorities.deadline.insert(piece_index) {
+                    if let Err(err) = process_priorities_changed(
+                        &mut peer_states,
+                        &mode,
+                        &priorities,
+                        &mut torrent_storage,
+                    )
+                    .await
+                    {
+                        error!("cannot request awaited piece {}: {}", piece_index, err);
+                    }
+                }
             }
         }
     }
@@ -466,3 +659,70 @@ pub(crate) async fn download_torrent(
 
     debug!("download_torrent done");
 }
+
+/// Starts announce loop, which connects torrent to peers, until returned handle is aborted.
+fn start_announce(
+    properties: Arc<Properties>,
+    torrent_process: Arc<TorrentProcess>,
+) -> AbortHandle {
+    let (abort_handle, abort_registration) = AbortHandle::new_pair();
+
+    let announce_loop = Abortable::new(
+        announce::announce_loop(properties, torrent_process).map_err(|e| {
+            error!("announce loop error: {}", e);
+            e
+        }),
+        abort_registration,
+    );
+
+    tokio::spawn(announce_loop);
+
+    abort_handle
+}
+
+/// Stops announces, connects to peers and disconnects all peers of torrent.
+async fn stop_peers(
+    peer_states: &mut HashMap<Uuid, PeerState>,
+    announce_abort_handle: &mut Option<AbortHandle>,
+) {
+    if let Some(abort_handle) = announce_abort_handle.take() {
+        abort_handle.abort();
+    }
+
+    for (peer_id, peer_state) in peer_states.drain() {
+        match peer_state.state {
+            TorrentPeerState::Connected { mut sender, .. } => {
+                if let Err(err) = sender.send(PeerMessage::Disconnect).await {
+                    error!(
+                        "[{}] stop torrent: cannot send disconnect message to peer: {}",
+                        peer_id, err
+                    );
+                }
+            }
+            TorrentPeerState::Connecting(abort_handle) => abort_handle.abort(),
+            _ => (),
+        }
+    }
+}
+
+/// Error state of torrent if `err` is failure of its storage.
+fn storage_failure(err: &RsbtError) -> Option<TorrentDownloadStatus> {
+    let kind = match err {
+        RsbtError::Storage(flat_storage::FlatStorageError::NoSpace) => TorrentErrorKind::DiskFull,
+        RsbtError::Storage(_) | RsbtError::IO(_) | RsbtError::DiskIoJob => TorrentErrorKind::Io,
+        _ => return None,
+    };
+    Some(TorrentDownloadStatus::Error {
+        kind,
+        message: err.to_string(),
+    })
+}
+
+fn broadcast_status(
+    status_sender: &watch::Sender<TorrentDownloadStatus>,
+    status: TorrentDownloadStatus,
+) {
+    if let Err(err) = status_sender.broadcast(status) {
+        error!("cannot broadcast torrent status: {}", err);
+    }
+}
@@ -11,10 +11,14 @@ pub(crate) async fn process_peer_announced(
         let peer_id = *peer_id;
         match existing_peer.state {
             TorrentPeerState::Idle => {
-                let handler = spawn_and_log_error(
-                    connect_to_peer(torrent_process, peer_id, peer),
-                    move || format!("connect to existing peer {} {:?} failed", peer_id, peer_err),
-                );
+                let handler = spawn_connect(async move {
+                    if let Err(err) = connect_to_peer(torrent_process, peer_id, peer).await {
+                        error!(
+                            "connect to existing peer {} {:?} failed: {}",
+                            peer_id, peer_err, err
+                        );
+                    }
+                });
                 existing_peer.state = TorrentPeerState::Connecting(handler);
             }
             TorrentPeerState::Connected { .. } => {
@@ -29,7 +33,7 @@ pub(crate) async fn process_peer_announced(
             peer_id,
             PeerState {
                 peer: peer.clone(),
-                state: TorrentPeerState::Connecting(tokio::spawn(async move {
+                state: TorrentPeerState::Connecting(spawn_connect(async move {
                     if let Err(err) = connect_to_peer(torrent_process, peer_id, peer).await {
                         error!(
                             "[{}] connect to new peer {:?} failed: {}",
@@ -52,3 +56,13 @@ pub(crate) async fn process_peer_announced(
 
     Ok(())
 }
+
+/// Spawns connect to peer, which is aborted with returned handle when torrent is stopped.
+fn spawn_connect<F>(connect: F) -> AbortHandle
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let (abort_handle, abort_registration) = AbortHandle::new_pair();
+    tokio::spawn(Abortable::new(connect, abort_registration));
+    abort_handle
+}
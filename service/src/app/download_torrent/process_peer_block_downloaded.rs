@@ -0,0 +1,23 @@
+use super::*;
+
+pub(crate) async fn process_peer_block_downloaded(
+    peer_states: &mut HashMap<Uuid, PeerState>,
+    peer_id: Uuid,
+    index: usize,
+    begin: usize,
+    block: Vec<u8>,
+    storage: &mut TorrentStorage,
+) -> Result<(), RsbtError> {
+    debug!("[{}] peer block downloaded", peer_id);
+
+    if let Some(existing_peer) = peer_states.get_mut(&peer_id) {
+        if let TorrentPeerState::Connected {
+            ref mut downloaded, ..
+        } = existing_peer.state
+        {
+            *downloaded += block.len();
+        }
+    }
+
+    storage.save_block(index, begin, block).await
+}
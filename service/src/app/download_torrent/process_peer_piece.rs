@@ -4,6 +4,7 @@ use super::*;
 pub(crate) async fn process_peer_piece(
     peer_states: &mut HashMap<Uuid, PeerState>,
     mode: &TorrentDownloadMode,
+    priorities: &PiecePriorities,
     peer_id: Uuid,
     peer_piece: usize,
     storage: &mut TorrentStorage,
@@ -23,7 +24,9 @@ pub(crate) async fn process_peer_piece(
                 );
                 downloadable
             }
-            TorrentPeerState::Idle | TorrentPeerState::Connecting(_) => {
+            TorrentPeerState::Idle
+            | TorrentPeerState::Connecting(_)
+            | TorrentPeerState::Disconnected(_) => {
                 error!(
                     "[{}] cannot process peer piece: wrong state: {:?}",
                     peer_id, existing_peer.state
@@ -35,7 +38,7 @@ pub(crate) async fn process_peer_piece(
         vec![]
     };
 
-    select_new_peer(&new_pieces, peer_states, mode, peer_id).await?;
+    select_new_peer(&new_pieces, priorities, peer_states, mode, peer_id, storage).await?;
 
     Ok(())
 }
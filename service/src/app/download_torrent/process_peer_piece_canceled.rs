@@ -3,6 +3,7 @@ use super::*;
 pub(crate) async fn process_peer_piece_canceled(
     peer_states: &mut HashMap<Uuid, PeerState>,
     mode: &TorrentDownloadMode,
+    priorities: &PiecePriorities,
     peer_id: Uuid,
     storage: &mut TorrentStorage,
 ) -> Result<(), RsbtError> {
@@ -35,7 +36,7 @@ pub(crate) async fn process_peer_piece_canceled(
         return Ok(());
     };
 
-    select_new_peer(&new_pieces, peer_states, mode, peer_id).await?;
+    select_new_peer(&new_pieces, priorities, peer_states, mode, peer_id, storage).await?;
 
     Ok(())
 }
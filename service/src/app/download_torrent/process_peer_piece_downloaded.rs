@@ -3,8 +3,8 @@ use super::*;
 pub(crate) async fn process_peer_piece_downloaded(
     peer_states: &mut HashMap<Uuid, PeerState>,
     mode: &TorrentDownloadMode,
+    priorities: &PiecePriorities,
     peer_id: Uuid,
-    piece: Vec<u8>,
     storage: &mut TorrentStorage,
     awaiters: &mut HashMap<
         usize,
@@ -13,20 +13,22 @@ pub(crate) async fn process_peer_piece_downloaded(
 ) -> Result<(), RsbtError> {
     debug!("[{}] peer piece downloaded", peer_id);
 
-    let (index, new_pieces) = if let Some(existing_peer) = peer_states.get_mut(&peer_id) {
+    let (index, piece, new_pieces) = if let Some(existing_peer) = peer_states.get_mut(&peer_id) {
         if let TorrentPeerState::Connected {
             ref pieces,
             ref mut downloading_piece,
             ref mut downloading_since,
-            ref mut downloaded,
             ..
         } = existing_peer.state
         {
-            *downloaded += piece.len();
             if let (Some(index), Some(_since)) =
                 (downloading_piece.take(), downloading_since.take())
             {
-                storage.save(index, piece.to_vec()).await?;
+                let piece = match storage.complete_piece(index).await {
+                    Ok(piece) => Some(piece),
+                    Err(RsbtError::PieceHashMismatch(_)) => None,
+                    Err(err) => return Err(err),
+                };
 
                 let mut downloadable = vec![];
                 for (i, &a) in pieces.iter().enumerate() {
@@ -37,7 +39,7 @@ pub(crate) async fn process_peer_piece_downloaded(
                         a,
                     );
                 }
-                (index, downloadable)
+                (index, piece, downloadable)
             } else {
                 return Ok(());
             }
@@ -48,6 +50,16 @@ pub(crate) async fn process_peer_piece_downloaded(
         return Ok(());
     };
 
+    let piece = match piece {
+        Some(piece) => piece,
+        None => {
+            error!("[{}] piece {} sha1 failure", peer_id, index);
+            ban_peer(peer_states, peer_id, index).await;
+            requeue_piece(peer_states, priorities, mode, index, storage).await?;
+            return Ok(());
+        }
+    };
+
     for (peer_id, peer_state) in peer_states.iter_mut().filter(|(&key, _)| key != peer_id) {
         if let TorrentPeerState::Connected {
             ref mut sender,
@@ -56,6 +68,17 @@ pub(crate) async fn process_peer_piece_downloaded(
             ..
         } = peer_state.state
         {
+            // in final mode the same piece is downloaded from many peers
+            let peer_downloads_same_piece = *downloading_piece == Some(index);
+            if peer_downloads_same_piece {
+                if let Err(err) = sender.send(PeerMessage::Cancel).await {
+                    error!(
+                        "[{}] cannot send Cancel to {:?}: {}",
+                        peer_id, peer_state.peer, err
+                    );
+                };
+            }
+
             let peer_already_have_piece = bit_by_index(index, pieces).is_some();
             if peer_already_have_piece {
                 continue;
@@ -67,25 +90,15 @@ pub(crate) async fn process_peer_piece_downloaded(
                     peer_id, peer_state.peer, err
                 );
             };
-
-            let peer_downloads_same_piece = *downloading_piece == Some(index);
-            if peer_downloads_same_piece {
-                if let Err(err) = sender.send(PeerMessage::Cancel).await {
-                    error!(
-                        "[{}] cannot send Have to {:?}: {}",
-                        peer_id, peer_state.peer, err
-                    );
-                };
-            }
         }
     }
 
-    select_new_peer(&new_pieces, peer_states, mode, peer_id).await?;
+    select_new_peer(&new_pieces, priorities, peer_states, mode, peer_id, storage).await?;
 
     if let Some(awaiters) = awaiters.remove(&index) {
         for awaiter in awaiters {
             let waker = awaiter.request().waker.lock().unwrap().take();
-            if let Err(err) = awaiter.response(Ok(piece.to_vec())) {
+            if let Err(err) = awaiter.response(Ok(piece.as_ref().to_vec())) {
                 error!("cannot send to awaiter: {}", err);
             }
             if let Some(waker) = waker {
@@ -96,3 +109,41 @@ pub(crate) async fn process_peer_piece_downloaded(
 
     Ok(())
 }
+
+/// Disconnects peer, which sent corrupt piece, it is not connected again.
+async fn ban_peer(peer_states: &mut HashMap<Uuid, PeerState>, peer_id: Uuid, index: usize) {
+    if let Some(peer_state) = peer_states.get_mut(&peer_id) {
+        if let TorrentPeerState::Connected { ref mut sender, .. } = peer_state.state {
+            if let Err(err) = sender.send(PeerMessage::Disconnect).await {
+                error!("[{}] cannot send Disconnect: {}", peer_id, err);
+            }
+        }
+        peer_state.state =
+            TorrentPeerState::Disconnected(format!("piece {} failed hash check", index));
+    }
+}
+
+/// Offers piece, which failed hash check, to idle peers which have it.
+async fn requeue_piece(
+    peer_states: &mut HashMap<Uuid, PeerState>,
+    priorities: &PiecePriorities,
+    mode: &TorrentDownloadMode,
+    index: usize,
+    storage: &TorrentStorage,
+) -> Result<(), RsbtError> {
+    let idle_peers: Vec<Uuid> = peer_states
+        .iter()
+        .filter_map(|(&peer_id, peer_state)| match peer_state.state {
+            TorrentPeerState::Connected {
+                ref pieces,
+                downloading_piece: None,
+                ..
+            } if bit_by_index(index, pieces).is_some() => Some(peer_id),
+            _ => None,
+        })
+        .collect();
+    for peer_id in idle_peers {
+        select_new_peer(&[index], priorities, peer_states, mode, peer_id, storage).await?;
+    }
+    Ok(())
+}
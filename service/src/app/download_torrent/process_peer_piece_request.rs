@@ -14,9 +14,11 @@ pub(crate) async fn process_peer_piece_request(
         ..
     }) = peer_states.get_mut(&peer_id).map(|x| &mut x.state)
     {
-        if let Some(piece) = storage.load(index as usize).await? {
+        if let Some(block) = storage
+            .load_block(index as usize, begin as usize, length as usize)
+            .await?
+        {
             *uploaded += length as usize;
-            let block = piece.as_ref()[begin as usize..(begin as usize + length as usize)].to_vec();
             sender
                 .send(PeerMessage::Piece {
                     index,
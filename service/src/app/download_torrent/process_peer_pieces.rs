@@ -3,6 +3,7 @@ use super::*;
 pub(crate) async fn process_peer_pieces(
     peer_states: &mut HashMap<Uuid, PeerState>,
     mode: &TorrentDownloadMode,
+    priorities: &PiecePriorities,
     peer_id: Uuid,
     peer_pieces: Vec<u8>,
     storage: &mut TorrentStorage,
@@ -16,7 +17,9 @@ pub(crate) async fn process_peer_pieces(
                 &peer_pieces,
                 &storage.receiver.borrow().downloaded,
             ),
-            TorrentPeerState::Idle | TorrentPeerState::Connecting(_) => {
+            TorrentPeerState::Idle
+            | TorrentPeerState::Connecting(_)
+            | TorrentPeerState::Disconnected(_) => {
                 error!(
                     "[{}] cannot process peer pieces: wrong state: {:?}",
                     peer_id, existing_peer.state
@@ -28,7 +31,7 @@ pub(crate) async fn process_peer_pieces(
         vec![]
     };
 
-    select_new_peer(&new_pieces, peer_states, mode, peer_id).await?;
+    select_new_peer(&new_pieces, priorities, peer_states, mode, peer_id, storage).await?;
 
     Ok(())
 }
@@ -0,0 +1,38 @@
+use super::*;
+
+/// Piece priorities changed, idle peers may have something to download now.
+pub(crate) async fn process_priorities_changed(
+    peer_states: &mut HashMap<Uuid, PeerState>,
+    mode: &TorrentDownloadMode,
+    priorities: &PiecePriorities,
+    storage: &mut TorrentStorage,
+) -> Result<(), RsbtError> {
+    let idle_peers: Vec<(Uuid, Vec<usize>)> = peer_states
+        .iter()
+        .filter_map(|(&peer_id, peer_state)| match &peer_state.state {
+            TorrentPeerState::Connected {
+                pieces,
+                downloading_piece: None,
+                ..
+            } => {
+                let mut downloadable = vec![];
+                for (i, &a) in pieces.iter().enumerate() {
+                    match_pieces(
+                        &mut downloadable,
+                        &storage.receiver.borrow().downloaded,
+                        i,
+                        a,
+                    );
+                }
+                Some((peer_id, downloadable))
+            }
+            _ => None,
+        })
+        .collect();
+
+    for (peer_id, new_pieces) in idle_peers {
+        select_new_peer(&new_pieces, priorities, peer_states, mode, peer_id, storage).await?;
+    }
+
+    Ok(())
+}
@@ -10,10 +10,17 @@ pub enum TorrentEvent {
         write: u64,
         read: u64,
         left: u32,
+        checking: Option<u32>,
+        moving: Option<u64>,
     },
     Stat {
         id: usize,
         rx: u64,
         tx: u64,
     },
+    Status {
+        id: usize,
+        active: bool,
+        error: Option<RsbtTorrentErrorView>,
+    },
 }
@@ -1,17 +1,19 @@
 use super::*;
 use crate::{
+    bandwidth::TokenBucket,
     bit_by_index,
     errors::RsbtError,
     index_in_bitarray,
     types::{
-        info::TorrentInfo,
+        info::{TorrentInfo, TorrentVersion},
         message::{Message, MessageCodec},
         peer::{Handshake, Peer},
         torrent::{parse_torrent, Torrent},
-        Properties,
+        Properties, HANDSHAKE_PREFIX, HANDSHAKE_V2_RESERVED_BIT, HANDSHAKE_V2_RESERVED_BYTE,
     },
     PEER_ID, SHA1_SIZE,
 };
+use futures::future::{select, Either};
 
 mod accept_connections_loop;
 mod connect_to_peer;
@@ -23,6 +25,7 @@ mod peer_connection;
 mod peer_loop;
 mod peer_loop_message;
 mod request_response;
+mod seeding;
 mod select_new_peer;
 
 use accept_connections_loop::accept_connections_loop;
@@ -34,9 +37,12 @@ use peer_connection::peer_connection;
 use peer_loop::peer_loop;
 use peer_loop_message::PeerLoopMessage;
 pub use request_response::RequestResponse;
-use select_new_peer::select_new_peer;
+use seeding::seed_until_goals;
+pub use seeding::SeedingGoals;
+use select_new_peer::{select_new_peer, PiecePriorities};
 
 const TORRENTS_TOML: &str = "torrents.toml";
+const LIMITS_TOML: &str = "limits.toml";
 
 pub struct RsbtApp {
     pub properties: Arc<Properties>,
@@ -47,14 +53,19 @@ pub struct TorrentProcess {
     pub(crate) torrent: Torrent,
     pub info: TorrentInfo,
     pub(crate) hash_id: [u8; SHA1_SIZE],
+    /// Truncated SHA-256 info hash of v2 and hybrid torrents
+    pub(crate) hash_id_v2: Option<[u8; SHA1_SIZE]>,
     pub(crate) handshake: Vec<u8>,
     pub(crate) broker_sender: Sender<DownloadTorrentEvent>,
+    pub(crate) bandwidth: Arc<Bandwidth>,
+    pub(crate) download_bucket: Arc<TokenBucket>,
+    pub(crate) upload_bucket: TokenBucket,
 }
 
 #[derive(Debug)]
 enum TorrentPeerState {
     Idle,
-    Connecting(JoinHandle<()>),
+    Connecting(AbortHandle),
     Connected {
         chocked: bool,
         interested: bool,
@@ -65,6 +76,8 @@ enum TorrentPeerState {
         sender: Sender<PeerMessage>,
         pieces: Vec<u8>,
     },
+    /// Peer was disconnected for violating protocol, it is not connected again.
+    Disconnected(String),
 }
 
 impl Default for TorrentPeerState {
@@ -78,6 +91,9 @@ impl Default for TorrentPeerState {
 pub enum RsbtTorrentAction {
     Enable,
     Disable,
+    Recheck,
+    /// Clear error state and resume torrent if it is enabled
+    Clear,
 }
 
 #[derive(Debug)]
@@ -98,7 +114,11 @@ pub(crate) enum PeerMessage {
     Disconnect,
     Cancel,
     Message(Message),
-    Download(usize),
+    Download {
+        piece: usize,
+        /// Blocks of piece already stored, bitmap
+        blocks: Vec<u8>,
+    },
     Have(usize),
     Bitfield(Vec<u8>),
     Piece {
@@ -173,30 +193,50 @@ impl RsbtApp {
         Ok(Default::default())
     }
 
-    pub async fn download<P: AsRef<Path>>(&self, torrent_file: P) -> Result<(), RsbtError> {
+    /// Downloads torrent and seeds it until any of `goals` is reached, forever by default.
+    ///
+    /// Fails if torrent is stopped by error, or if its data fails hash check after download.
+    pub async fn download<P: AsRef<Path>>(
+        &self,
+        torrent_file: P,
+        goals: SeedingGoals,
+    ) -> Result<(), RsbtError> {
         let (mut download_events_sender, download_events_receiver) =
             mpsc::channel(DEFAULT_CHANNEL_BUFFER);
 
         let data = std::fs::read(torrent_file.as_ref())?;
 
+        let (request_response, response) = RequestResponse::new(RsbtCommandAddTorrent {
+            data,
+            filename: torrent_file
+                .as_ref()
+                .file_name()
+                .unwrap_or_default()
+                .to_str()
+                .unwrap_or_default()
+                .into(),
+            state: TorrentDownloadStatus::Enabled,
+            priorities: vec![],
+            sequential: false,
+            backend: None,
+            preallocation: None,
+            category: None,
+            save_to: None,
+        });
         download_events_sender
-            .send(RsbtCommand::AddTorrent(RequestResponse::RequestOnly(
-                RsbtCommandAddTorrent {
-                    data,
-                    filename: torrent_file
-                        .as_ref()
-                        .file_name()
-                        .unwrap_or_default()
-                        .to_str()
-                        .unwrap_or_default()
-                        .into(),
-                    state: TorrentDownloadStatus::Enabled,
-                },
-            )))
+            .send(RsbtCommand::AddTorrent(request_response))
             .await?;
 
-        self.processing_loop(download_events_sender, download_events_receiver)
-            .await
+        let processing_loop =
+            self.processing_loop(download_events_sender, download_events_receiver);
+        let seeding = async move {
+            let torrent = response.await??;
+            seed_until_goals(torrent, goals).await
+        };
+        futures::pin_mut!(processing_loop, seeding);
+        match select(processing_loop, seeding).await {
+            Either::Left((result, _)) | Either::Right((result, _)) => result,
+        }
     }
 }
 
@@ -212,15 +252,57 @@ where
     })
 }
 
-fn request_message(buffer: &[u8], piece: usize, piece_length: usize) -> (u32, u32, u32) {
-    let index = piece as u32;
-    let begin = buffer.len() as u32;
-    let length = if piece_length - buffer.len() < BLOCK_SIZE {
-        piece_length - buffer.len()
-    } else {
-        BLOCK_SIZE
-    } as u32;
-    (index, begin, length)
+/// Request for the first missing block of piece, none if all blocks are downloaded.
+fn request_message(blocks: &[u8], piece: usize, piece_length: usize) -> Option<(u32, u32, u32)> {
+    (0..count_parts(piece_length, BLOCK_SIZE))
+        .find(|&block| bit_by_index(block, blocks).is_none())
+        .map(|block| {
+            let begin = block * BLOCK_SIZE;
+            let length = (piece_length - begin).min(BLOCK_SIZE);
+            (piece as u32, begin as u32, length as u32)
+        })
+}
+
+/// Max length of requested block, larger requests are considered malicious.
+const MAX_REQUEST_LENGTH: usize = 1 << 17;
+
+/// Checks that bitfield has a bit for every piece and its spare bits are cleared.
+fn validate_bitfield(pieces: &[u8], pieces_count: usize) -> Result<(), String> {
+    let expected = count_parts(pieces_count, 8);
+    if pieces.len() != expected {
+        return Err(format!(
+            "bitfield of {} bytes, expected {} bytes",
+            pieces.len(),
+            expected
+        ));
+    }
+    let spare_bits = expected * 8 - pieces_count;
+    match pieces.last() {
+        Some(last) if last & ((1u8 << spare_bits) - 1) != 0 => {
+            Err("bitfield with spare bits set".into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks that block of request, piece or cancel message lies within the piece.
+fn validate_block(info: &TorrentInfo, index: u32, begin: u32, length: usize) -> Result<(), String> {
+    let index = index as usize;
+    if index >= info.pieces.len() {
+        return Err(format!(
+            "piece {} out of {} pieces",
+            index,
+            info.pieces.len()
+        ));
+    }
+    let piece_length = info.sizes(index).0;
+    if length == 0 || length > MAX_REQUEST_LENGTH || begin as usize + length > piece_length {
+        return Err(format!(
+            "block {}+{} outside of piece {} of {} bytes",
+            begin, length, index, piece_length
+        ));
+    }
+    Ok(())
 }
 
 fn collect_pieces_and_update(
@@ -242,6 +324,28 @@ fn collect_pieces_and_update(
     pieces
 }
 
+/// Calculates piece priorities from file priorities.
+///
+/// Piece gets the highest priority of files it overlaps, so pieces straddling skipped
+/// and wanted files are still downloaded. Missing file priorities are treated as normal.
+fn piece_priorities(info: &TorrentInfo, file_priorities: &[FilePriority]) -> Vec<FilePriority> {
+    let mut priorities = vec![FilePriority::Skip; info.pieces.len()];
+    let mut offset = 0;
+    for (file_id, file) in info.files.iter().enumerate() {
+        if file.length == 0 {
+            continue;
+        }
+        let priority = file_priorities.get(file_id).copied().unwrap_or_default();
+        let first_piece = offset / info.piece_length;
+        let last_piece = (offset + file.length - 1) / info.piece_length;
+        for piece_priority in priorities.iter_mut().take(last_piece + 1).skip(first_piece) {
+            *piece_priority = (*piece_priority).max(priority);
+        }
+        offset += file.length;
+    }
+    priorities
+}
+
 /// Adds matching (new) pieces ( downloaded_pieces[i] & a ) to pieces (list of indexes).
 fn match_pieces(pieces: &mut Vec<usize>, downloaded_pieces: &[u8], i: usize, a: u8) {
     let new = if let Some(d) = downloaded_pieces.get(i) {
@@ -294,6 +398,131 @@ mod tests {
         assert_eq!(current_pieces, vec![0b1010_1010]);
     }
 
+    #[test]
+    fn check_piece_priorities() {
+        use crate::types::info::{TorrentInfoFileRaw, TorrentInfoRaw};
+
+        let file = |length, name: &str| TorrentInfoFileRaw {
+            length,
+            path: vec![name.into()],
+            attr: None,
+            symlink_path: None,
+            sha1: None,
+        };
+        let info: TorrentInfo = TorrentInfoRaw {
+            name: "test".into(),
+            piece_length: 10,
+            pieces: Some(vec![0; SHA1_SIZE * 4]),
+            length: None,
+            files: Some(vec![
+                file(15, "a"),
+                file(0, "empty"),
+                file(10, "b"),
+                file(10, "c"),
+            ]),
+            attr: None,
+            sha1: None,
+            meta_version: None,
+            file_tree: None,
+        }
+        .try_into()
+        .unwrap();
+
+        assert_eq!(piece_priorities(&info, &[]), vec![FilePriority::Normal; 4]);
+        assert_eq!(
+            piece_priorities(
+                &info,
+                &[
+                    FilePriority::Skip,
+                    FilePriority::High,
+                    FilePriority::Low,
+                    FilePriority::Skip
+                ]
+            ),
+            vec![
+                FilePriority::Skip,
+                FilePriority::Low,
+                FilePriority::Low,
+                FilePriority::Skip
+            ]
+        );
+        assert_eq!(
+            piece_priorities(
+                &info,
+                &[
+                    FilePriority::High,
+                    FilePriority::Skip,
+                    FilePriority::Skip,
+                    FilePriority::Low
+                ]
+            ),
+            vec![
+                FilePriority::High,
+                FilePriority::High,
+                FilePriority::Low,
+                FilePriority::Low
+            ]
+        );
+    }
+
+    #[test]
+    fn check_validate_bitfield() {
+        assert!(validate_bitfield(&[0b1111_1111], 8).is_ok());
+        assert!(validate_bitfield(&[0xff, 0b1110_0000], 11).is_ok());
+        assert!(validate_bitfield(&[], 0).is_ok());
+        assert!(validate_bitfield(&[0xff], 11).is_err());
+        assert!(validate_bitfield(&[0xff, 0, 0], 11).is_err());
+        assert!(validate_bitfield(&[0xff, 0b1111_0000], 11).is_err());
+        assert!(validate_bitfield(&[0xff, 0b1110_0001], 11).is_err());
+    }
+
+    #[test]
+    fn check_validate_block() {
+        use crate::types::info::TorrentInfoRaw;
+
+        let piece_length = BLOCK_SIZE as i64 * 2;
+        let info: TorrentInfo = TorrentInfoRaw {
+            name: "test".into(),
+            piece_length,
+            pieces: Some(vec![0; SHA1_SIZE * 2]),
+            length: Some(piece_length + 100),
+            files: None,
+            attr: None,
+            sha1: None,
+            meta_version: None,
+            file_tree: None,
+        }
+        .try_into()
+        .unwrap();
+
+        assert!(validate_block(&info, 0, 0, BLOCK_SIZE).is_ok());
+        assert!(validate_block(&info, 0, BLOCK_SIZE as u32, BLOCK_SIZE).is_ok());
+        assert!(validate_block(&info, 1, 0, 100).is_ok());
+        assert!(validate_block(&info, 0, BLOCK_SIZE as u32 + 1, BLOCK_SIZE).is_err());
+        assert!(validate_block(&info, 1, 0, 101).is_err());
+        assert!(validate_block(&info, 1, 0, 0).is_err());
+        assert!(validate_block(&info, 2, 0, 100).is_err());
+        assert!(validate_block(&info, 0, u32::MAX, BLOCK_SIZE).is_err());
+    }
+
+    #[test]
+    fn check_request_message() {
+        let piece_length = BLOCK_SIZE * 2 + 100;
+        assert_eq!(
+            request_message(&[], 3, piece_length),
+            Some((3, 0, BLOCK_SIZE as u32))
+        );
+        assert_eq!(
+            request_message(&[0b1000_0000], 3, piece_length),
+            Some((3, BLOCK_SIZE as u32, BLOCK_SIZE as u32))
+        );
+        assert_eq!(
+            request_message(&[0b1100_0000], 3, piece_length),
+            Some((3, BLOCK_SIZE as u32 * 2, 100))
+        );
+        assert_eq!(request_message(&[0b1110_0000], 3, piece_length), None);
+    }
+
     #[tokio::test]
     async fn check_process_peer_pieces() {}
 }
@@ -9,6 +9,7 @@ pub(crate) async fn peer_connection(
     socket.read_exact(&mut handshake_request).await?;
 
     let handshake_request: Handshake = handshake_request.try_into()?;
+    let info_hash = handshake_request.info_hash;
 
     let (handshake_sender, handshake_receiver) = oneshot::channel();
 
@@ -31,7 +32,11 @@ pub(crate) async fn peer_connection(
         }
     };
 
-    socket.write_all(&torrent_process.handshake).await?;
+    // hybrid torrent is also found by v2 info hash, reply with the hash peer knows
+    let mut handshake = torrent_process.handshake.clone();
+    handshake[HANDSHAKE_PREFIX.len()..HANDSHAKE_PREFIX.len() + SHA1_SIZE]
+        .copy_from_slice(&info_hash);
+    socket.write_all(&handshake).await?;
 
     debug!("handshake done, connected with peer");
 
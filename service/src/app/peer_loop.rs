@@ -1,5 +1,8 @@
 use super::*;
-use crate::app::download_torrent::TorrentStatisticMessage;
+use crate::{
+    app::download_torrent::TorrentStatisticMessage, bandwidth::ThrottledStream,
+    types::message::MessageCodecError,
+};
 
 pub(crate) async fn peer_loop(
     torrent_process: Arc<TorrentProcess>,
@@ -9,6 +12,11 @@ pub(crate) async fn peer_loop(
     stream: TcpStream,
     mut statistic_sender: Sender<TorrentStatisticMessage>,
 ) -> Result<(), RsbtError> {
+    let stream = ThrottledStream::new(
+        stream,
+        torrent_process.bandwidth.clone(),
+        torrent_process.download_bucket.clone(),
+    );
     let (wtransport, mut rtransport) = Framed::new(stream, MessageCodec).split();
 
     let mut broker_sender = torrent_process.broker_sender.clone();
@@ -16,6 +24,7 @@ pub(crate) async fn peer_loop(
     let command_loop_broker_sender = broker_sender.clone();
 
     let command_loop = async move {
+        let upload_bucket = TokenBucket::default();
         let mut processor = PeerLoopMessage {
             peer_id,
             command_loop_broker_sender,
@@ -25,10 +34,11 @@ pub(crate) async fn peer_loop(
             message_count: 0,
             downloading: None,
             piece_length: 0,
-            torrent_piece: None,
+            blocks: vec![],
             wtransport,
             request: None,
             statistic_sender: statistic_sender.clone(),
+            disconnect_reason: None,
         };
 
         while let Some(message) = receiver.next().await {
@@ -50,6 +60,10 @@ pub(crate) async fn peer_loop(
                     block,
                 } => {
                     let block_len = block.len() as u64;
+                    torrent_process
+                        .bandwidth
+                        .upload(&torrent_process.upload_bucket, &upload_bucket, block.len())
+                        .await;
                     debug!(
                         "[{}] sending piece {} {} [{}]",
                         peer_id, index, begin, block_len
@@ -82,35 +96,24 @@ pub(crate) async fn peer_loop(
                             .await?;
                         processor.request = None;
                         processor.downloading = None;
-                        processor.torrent_piece = None;
+                        processor.blocks.clear();
                         processor
                             .command_loop_broker_sender
                             .send(DownloadTorrentEvent::PeerPieceCanceled(peer_id))
                             .await?;
                     }
                 }
-                PeerMessage::Download(piece) => {
+                PeerMessage::Download { piece, blocks } => {
                     debug!("[{}] download now piece: {}", peer_id, piece);
                     processor.piece_length = torrent_process.info.sizes(piece).0;
                     processor.downloading = Some(piece);
-                    processor.torrent_piece = Some(Vec::with_capacity(processor.piece_length));
+                    processor.blocks = blocks;
 
                     if processor.chocked {
                         debug!("[{}] send interested message", peer_id);
                         processor.wtransport.send(Message::Interested).await?;
-                    } else if let Some(ref torrent_peer_piece) = processor.torrent_piece {
-                        let (index, begin, length) =
-                            request_message(torrent_peer_piece, piece, processor.piece_length);
-                        processor.request = Some((index, begin, length));
-                        processor
-                            .wtransport
-                            .send(Message::Request {
-                                index,
-                                begin,
-                                length,
-                            })
-                            .await?;
                     }
+                    processor.request_next_block().await?;
                 }
                 PeerMessage::Disconnect => break,
                 PeerMessage::Message(message) => {
@@ -125,11 +128,24 @@ pub(crate) async fn peer_loop(
 
         processor.wtransport.close().await?;
 
-        Ok::<(), RsbtError>(())
+        Ok::<_, RsbtError>(processor.disconnect_reason)
     };
 
     let receive_loop = async move {
-        while let Some(Ok(message)) = rtransport.next().await {
+        let mut disconnect_reason = None;
+        while let Some(message) = rtransport.next().await {
+            let message = match message {
+                Ok(message) => message,
+                Err(MessageCodecError::IoError(err)) => {
+                    debug!("[{}] peer loop receive error: {}", peer_id, err);
+                    break;
+                }
+                Err(err) => {
+                    error!("[{}] disconnect peer: {}", peer_id, err);
+                    disconnect_reason = Some(err.to_string());
+                    break;
+                }
+            };
             sender.send(PeerMessage::Message(message)).await?;
         }
 
@@ -142,15 +158,22 @@ pub(crate) async fn peer_loop(
             );
         }
 
-        Ok::<(), RsbtError>(())
+        Ok::<_, RsbtError>(disconnect_reason)
     };
 
-    if let Err(err) = try_join(command_loop, receive_loop).await {
-        error!("[{}] peer join fail: {}", peer_id, err);
+    let disconnect_reason = match try_join(command_loop, receive_loop).await {
+        Ok((command_reason, receive_reason)) => command_reason.or(receive_reason),
+        Err(err) => {
+            error!("[{}] peer join fail: {}", peer_id, err);
+            None
+        }
     };
 
     broker_sender
-        .send(DownloadTorrentEvent::PeerDisconnect(peer_id))
+        .send(DownloadTorrentEvent::PeerDisconnect(
+            peer_id,
+            disconnect_reason,
+        ))
         .await?;
 
     debug!("[{}] peer loop exit", peer_id);
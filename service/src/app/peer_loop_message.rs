@@ -1,5 +1,15 @@
 use super::*;
-use crate::app::download_torrent::TorrentStatisticMessage;
+use crate::{
+    app::download_torrent::TorrentStatisticMessage,
+    bandwidth::ThrottledStream,
+    types::{
+        merkle::{layer_proof, pad_hash},
+        message::HashRequest,
+    },
+};
+
+/// Max count of hashes answered in single hashes message.
+const MAX_HASHES: u32 = 512;
 
 pub(crate) struct PeerLoopMessage {
     pub(crate) torrent_process: Arc<TorrentProcess>,
@@ -9,22 +19,31 @@ pub(crate) struct PeerLoopMessage {
     pub(crate) peer_id: Uuid,
     pub(crate) command_loop_broker_sender: Sender<DownloadTorrentEvent>,
     pub(crate) downloading: Option<usize>,
-    pub(crate) torrent_piece: Option<Vec<u8>>,
+    /// Downloaded blocks of piece, bitmap
+    pub(crate) blocks: Vec<u8>,
     pub(crate) piece_length: usize,
-    pub(crate) wtransport: SplitSink<Framed<TcpStream, MessageCodec>, Message>,
+    pub(crate) wtransport: SplitSink<Framed<ThrottledStream<TcpStream>, MessageCodec>, Message>,
     pub(crate) request: Option<(u32, u32, u32)>,
     pub(crate) statistic_sender: Sender<TorrentStatisticMessage>,
+    /// Protocol violation of peer, which caused disconnect
+    pub(crate) disconnect_reason: Option<String>,
 }
 
 impl PeerLoopMessage {
+    /// Records reason of disconnect and asks peer loop to stop.
+    fn disconnect(&mut self, reason: String) -> Result<bool, RsbtError> {
+        error!("[{}] disconnect peer: {}", self.peer_id, reason);
+        self.disconnect_reason = Some(reason);
+        Ok(true)
+    }
+
     pub(crate) async fn bitfield(&mut self, pieces: Vec<u8>) -> Result<bool, RsbtError> {
         let peer_id = self.peer_id;
         if self.message_count != 1 {
-            error!(
-                "[{}] wrong message sequence for peer: bitfield message must be first message",
-                peer_id
-            );
-            return Ok(true);
+            return self.disconnect("bitfield message must be first message".into());
+        }
+        if let Err(reason) = validate_bitfield(&pieces, self.torrent_process.info.pieces.len()) {
+            return self.disconnect(reason);
         }
         self.command_loop_broker_sender
             .send(DownloadTorrentEvent::PeerPieces(peer_id, pieces))
@@ -35,6 +54,13 @@ impl PeerLoopMessage {
 
     pub(crate) async fn have(&mut self, piece_index: usize) -> Result<bool, RsbtError> {
         let peer_id = self.peer_id;
+        let pieces_count = self.torrent_process.info.pieces.len();
+        if piece_index >= pieces_count {
+            return self.disconnect(format!(
+                "have piece {} out of {} pieces",
+                piece_index, pieces_count
+            ));
+        }
 
         self.command_loop_broker_sender
             .send(DownloadTorrentEvent::PeerPiece(peer_id, piece_index))
@@ -62,10 +88,24 @@ impl PeerLoopMessage {
             "[{}] checking piece progress: {:?}",
             peer_id, self.downloading
         );
-        if let Some(piece) = self.downloading {
-            if let Some(ref torrent_peer_piece) = self.torrent_piece {
-                let (index, begin, length) =
-                    request_message(torrent_peer_piece, piece, self.piece_length);
+        self.request_next_block().await?;
+
+        Ok(false)
+    }
+
+    /// Requests next missing block of downloading piece, or reports piece as downloaded
+    /// when all blocks are stored.
+    pub(crate) async fn request_next_block(&mut self) -> Result<(), RsbtError> {
+        let piece = match self.downloading {
+            Some(piece) => piece,
+            None => return Ok(()),
+        };
+
+        match request_message(&self.blocks, piece, self.piece_length) {
+            Some((index, begin, length)) => {
+                if self.chocked {
+                    return Ok(());
+                }
                 self.request = Some((index, begin, length));
                 self.wtransport
                     .send(Message::Request {
@@ -75,9 +115,17 @@ impl PeerLoopMessage {
                     })
                     .await?;
             }
+            None => {
+                self.request = None;
+                self.downloading = None;
+                self.blocks.clear();
+                self.command_loop_broker_sender
+                    .send(DownloadTorrentEvent::PeerPieceDownloaded(self.peer_id))
+                    .await?;
+            }
         }
 
-        Ok(false)
+        Ok(())
     }
 
     pub(crate) async fn piece(
@@ -87,6 +135,9 @@ impl PeerLoopMessage {
         block: Vec<u8>,
     ) -> Result<bool, RsbtError> {
         let peer_id = self.peer_id;
+        if let Err(reason) = validate_block(&self.torrent_process.info, index, begin, block.len()) {
+            return self.disconnect(reason);
+        }
         if let Err(err) = self
             .statistic_sender
             .send(TorrentStatisticMessage::Downloaded(block.len() as u64))
@@ -103,59 +154,45 @@ impl PeerLoopMessage {
                 );
                 return Ok(false);
             }
-            if let Some(ref mut torrent_peer_piece) = self.torrent_piece {
-                if torrent_peer_piece.len() != begin as usize {
-                    error!(
-                            "[{}] abnormal piece message for peer piece {}, expected begin {} but got {}",
-                            peer_id, piece, torrent_peer_piece.len(), begin,
-                        );
-                    return Ok(false);
-                }
 
-                torrent_peer_piece.extend(block);
-
-                use std::cmp::Ordering;
-                match self.piece_length.cmp(&torrent_peer_piece.len()) {
-                    Ordering::Greater => {
-                        let (index, begin, length) =
-                            request_message(torrent_peer_piece, piece, self.piece_length);
-                        self.request = Some((index, begin, length));
-                        self.wtransport
-                            .send(Message::Request {
-                                index,
-                                begin,
-                                length,
-                            })
-                            .await?;
-                    }
-                    Ordering::Equal => {
-                        let control_piece = &self.torrent_process.info.pieces[piece];
-
-                        let sha1: types::info::Piece =
-                            Sha1::digest(torrent_peer_piece.as_slice())[..].try_into()?;
-                        if sha1 != *control_piece {
-                            error!("[{}] piece sha1 failure", peer_id);
-                        }
-
-                        self.downloading = None;
-                        self.command_loop_broker_sender
-                            .send(DownloadTorrentEvent::PeerPieceDownloaded(
-                                peer_id,
-                                self.torrent_piece.take().unwrap(),
-                            ))
-                            .await?;
-                    }
-                    _ => {
-                        error!(
-                            "[{}] wrong piece length: {} {}",
-                            peer_id,
-                            piece,
-                            torrent_peer_piece.len()
-                        );
-                        return Ok(false);
-                    }
-                }
+            let begin = begin as usize;
+            let block_index = begin / BLOCK_SIZE;
+            let expected_length = self.piece_length.saturating_sub(begin).min(BLOCK_SIZE);
+            if begin % BLOCK_SIZE != 0 || block.len() != expected_length || expected_length == 0 {
+                error!(
+                    "[{}] abnormal block for peer piece {}: begin {}, length {}",
+                    peer_id,
+                    piece,
+                    begin,
+                    block.len()
+                );
+                return Ok(false);
+            }
+
+            if bit_by_index(block_index, &self.blocks).is_some() {
+                debug!(
+                    "[{}] block {} of piece {} already downloaded",
+                    peer_id, block_index, piece
+                );
+                return Ok(false);
             }
+
+            self.command_loop_broker_sender
+                .send(DownloadTorrentEvent::PeerBlockDownloaded {
+                    peer_id,
+                    index: piece,
+                    begin,
+                    block,
+                })
+                .await?;
+
+            let (byte_index, bit) = index_in_bitarray(block_index);
+            if self.blocks.len() <= byte_index {
+                self.blocks.resize(byte_index + 1, 0);
+            }
+            self.blocks[byte_index] |= bit;
+
+            self.request_next_block().await?;
         } else {
             error!("[{}] abnormal piece message {} for peer", peer_id, index);
         }
@@ -172,8 +209,12 @@ impl PeerLoopMessage {
         let peer_id = self.peer_id;
 
         if !self.interested {
-            error!("[{}] peer requested data without unchoke", peer_id);
-            return Ok(true);
+            return self.disconnect("requested data without interested message".into());
+        }
+        if let Err(reason) =
+            validate_block(&self.torrent_process.info, index, begin, length as usize)
+        {
+            return self.disconnect(reason);
         }
 
         self.command_loop_broker_sender
@@ -188,6 +229,25 @@ impl PeerLoopMessage {
         Ok(false)
     }
 
+    pub(crate) async fn cancel(
+        &mut self,
+        index: u32,
+        begin: u32,
+        length: u32,
+    ) -> Result<bool, RsbtError> {
+        if let Err(reason) =
+            validate_block(&self.torrent_process.info, index, begin, length as usize)
+        {
+            return self.disconnect(reason);
+        }
+        debug!(
+            "[{}] unhandled cancel {} {} {}",
+            self.peer_id, index, begin, length
+        );
+
+        Ok(false)
+    }
+
     pub(crate) async fn interested(&mut self) -> Result<bool, RsbtError> {
         self.interested = true;
         self.command_loop_broker_sender
@@ -197,6 +257,36 @@ impl PeerLoopMessage {
         Ok(false)
     }
 
+    /// Answers hash request from piece layer of v2 file with hashes and proof, other
+    /// layers are not kept and rejected.
+    pub(crate) async fn hash_request(&mut self, request: HashRequest) -> Result<bool, RsbtError> {
+        let info = &self.torrent_process.info;
+        let piece_layer = (info.piece_length / BLOCK_SIZE).trailing_zeros();
+        let hashes = match info.piece_layers.get(&request.pieces_root) {
+            Some(layer) if request.base_layer == piece_layer && request.length <= MAX_HASHES => {
+                layer_proof(
+                    layer,
+                    pad_hash(piece_layer as usize),
+                    request.index as usize,
+                    request.length as usize,
+                    request.proof_layers as usize,
+                )
+            }
+            _ => None,
+        };
+
+        let message = match hashes {
+            Some(hashes) => Message::Hashes { request, hashes },
+            None => {
+                debug!("[{}] reject hash request {:?}", self.peer_id, request);
+                Message::HashReject(request)
+            }
+        };
+        self.wtransport.send(message).await?;
+
+        Ok(false)
+    }
+
     pub(crate) async fn keep_alive(&mut self) -> Result<bool, RsbtError> {
         debug!("[{}] send keep alive to peer", self.peer_id);
         self.wtransport.send(Message::KeepAlive).await?;
@@ -233,9 +323,19 @@ impl PeerLoopMessage {
             } => {
                 return self.request(index, begin, length).await;
             }
+            Message::Cancel {
+                index,
+                begin,
+                length,
+            } => {
+                return self.cancel(index, begin, length).await;
+            }
             Message::KeepAlive => {
                 return self.keep_alive().await;
             }
+            Message::HashRequest(request) => {
+                return self.hash_request(request).await;
+            }
             _ => debug!("[{}] unhandled message: {}", peer_id, message),
         }
 
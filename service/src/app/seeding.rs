@@ -0,0 +1,133 @@
+use super::*;
+use crate::{inspect::verify_torrent, storage::TorrentStorageState, types::StorageBackend};
+use log::info;
+
+/// How often torrent is checked for completion and seeding goals.
+const SEEDING_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When downloading torrent stops, by default it is seeded forever.
+#[derive(Debug, Default, Clone)]
+pub struct SeedingGoals {
+    /// Stops as soon as download is completed and verified
+    pub exit_on_complete: bool,
+    /// Stops when uploaded bytes reach this ratio of torrent length
+    pub ratio: Option<f64>,
+    /// Stops when torrent is seeded for this time after completion
+    pub time: Option<Duration>,
+}
+
+impl SeedingGoals {
+    fn reached(&self, uploaded: u64, length: usize, seeding_since: Instant) -> bool {
+        if self.exit_on_complete {
+            return true;
+        }
+        let ratio_reached = self
+            .ratio
+            .map(|ratio| uploaded as f64 >= ratio * length as f64)
+            .unwrap_or_default();
+        let time_reached = self
+            .time
+            .map(|time| seeding_since.elapsed() >= time)
+            .unwrap_or_default();
+        ratio_reached || time_reached
+    }
+}
+
+/// Checks if all pieces are downloaded and files are at complete location.
+fn is_complete(state: &TorrentStorageState, location: &TorrentStorageLocation) -> bool {
+    state.pieces_left == 0
+        && state.checking.is_none()
+        && state.moving.is_none()
+        && (state.moved || !location.needs_move())
+}
+
+/// Waits until torrent is downloaded, verifies its files and seeds it until any of goals is
+/// reached. Fails if torrent is stopped by error or downloaded data fails hash check.
+pub(crate) async fn seed_until_goals(
+    torrent: TorrentDownload,
+    goals: SeedingGoals,
+) -> Result<(), RsbtError> {
+    let header = &torrent.header;
+    let location = TorrentStorageLocation::new(
+        &torrent.properties,
+        header.save_to.as_deref(),
+        header.category.as_deref(),
+    )?;
+    loop {
+        if let TorrentDownloadStatus::Error { message, .. } = &*torrent.status_watch.borrow() {
+            return Err(RsbtError::TorrentStopped(message.clone()));
+        }
+        if is_complete(&torrent.storage_state_watch.borrow(), &location) {
+            break;
+        }
+        delay_for(SEEDING_CHECK_INTERVAL).await;
+    }
+    info!("torrent {} is downloaded", torrent.name);
+
+    let backend = header.backend.unwrap_or(torrent.properties.storage_backend);
+    if backend != StorageBackend::Memory {
+        let process = torrent.process.clone();
+        let verification =
+            tokio::task::spawn_blocking(move || verify_torrent(&process.info, location.complete))
+                .await??;
+        if !verification.is_complete() {
+            return Err(RsbtError::HashCheckFailed(
+                verification.pieces - verification.valid_pieces,
+                verification.pieces,
+            ));
+        }
+        info!("torrent {} is verified", torrent.name);
+    }
+
+    let seeding_since = Instant::now();
+    let length = torrent.process.info.len();
+    loop {
+        if let TorrentDownloadStatus::Error { message, .. } = &*torrent.status_watch.borrow() {
+            return Err(RsbtError::TorrentStopped(message.clone()));
+        }
+        let uploaded = torrent.statistics_watch.borrow().uploaded;
+        if goals.reached(uploaded, length, seeding_since) {
+            info!(
+                "torrent {} reached seeding goal: {} bytes uploaded",
+                torrent.name, uploaded
+            );
+            return Ok(());
+        }
+        delay_for(SEEDING_CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_seeding_goals() {
+        let now = Instant::now();
+        let long_ago = now - Duration::from_secs(120);
+
+        assert!(!SeedingGoals::default().reached(1000, 100, long_ago));
+
+        let exit = SeedingGoals {
+            exit_on_complete: true,
+            ..Default::default()
+        };
+        assert!(exit.reached(0, 100, now));
+
+        let ratio = SeedingGoals {
+            ratio: Some(1.5),
+            ..Default::default()
+        };
+        assert!(!ratio.reached(149, 100, long_ago));
+        assert!(ratio.reached(150, 100, now));
+
+        let time = SeedingGoals {
+            time: Some(Duration::from_secs(60)),
+            ratio: Some(2.0),
+            ..Default::default()
+        };
+        assert!(!time.reached(0, 100, now));
+        assert!(time.reached(0, 100, long_ago));
+        assert!(time.reached(200, 100, now));
+    }
+}
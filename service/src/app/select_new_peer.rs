@@ -1,12 +1,48 @@
 use super::*;
+use std::{cmp::Reverse, collections::HashSet};
+
+/// Order in which pieces are requested from peers.
+#[derive(Debug, Default)]
+pub(crate) struct PiecePriorities {
+    /// Priority of every piece, calculated from file priorities
+    pub(crate) pieces: Vec<FilePriority>,
+    /// Download pieces in order, file priorities are used only to skip pieces
+    pub(crate) sequential: bool,
+    /// Pieces somebody waits for right now (e.g. file download stream)
+    pub(crate) deadline: HashSet<usize>,
+}
+
+impl PiecePriorities {
+    /// Orders pieces to download.
+    ///
+    /// Pieces with deadline go first, even if they are skipped, then other pieces by priority
+    /// (highest first) or by index in sequential mode. Skipped pieces are removed.
+    fn order(&self, new_pieces: &[usize]) -> Vec<usize> {
+        let priority = |piece: usize| self.pieces.get(piece).copied().unwrap_or_default();
+        let deadline = |piece: usize| self.deadline.contains(&piece);
+        let mut pieces: Vec<usize> = new_pieces
+            .iter()
+            .copied()
+            .filter(|&piece| deadline(piece) || priority(piece) != FilePriority::Skip)
+            .collect();
+        if self.sequential {
+            pieces.sort_by_key(|&piece| (Reverse(deadline(piece)), piece));
+        } else {
+            pieces.sort_by_key(|&piece| (Reverse(deadline(piece)), Reverse(priority(piece))));
+        }
+        pieces
+    }
+}
 
 pub(crate) async fn select_new_peer(
     new_pieces: &[usize],
+    priorities: &PiecePriorities,
     peer_states: &mut HashMap<Uuid, PeerState>,
     mode: &TorrentDownloadMode,
     peer_id: Uuid,
+    storage: &TorrentStorage,
 ) -> Result<(), RsbtError> {
-    for &new_piece in new_pieces {
+    for new_piece in priorities.order(new_pieces) {
         if let TorrentDownloadMode::Normal = mode {
             let any_peer_downloading = peer_states.values().any(|x| match x.state {
                 TorrentPeerState::Connected {
@@ -30,7 +66,19 @@ pub(crate) async fn select_new_peer(
                 if downloading_piece.is_none() {
                     *downloading_piece = Some(new_piece);
                     *downloading_since = Some(Instant::now());
-                    sender.send(PeerMessage::Download(new_piece)).await?;
+                    let blocks = storage
+                        .receiver
+                        .borrow()
+                        .partial
+                        .get(&new_piece)
+                        .cloned()
+                        .unwrap_or_default();
+                    sender
+                        .send(PeerMessage::Download {
+                            piece: new_piece,
+                            blocks,
+                        })
+                        .await?;
                 }
             }
         }
@@ -38,3 +86,36 @@ pub(crate) async fn select_new_peer(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_piece_priorities_order() {
+        let mut priorities = PiecePriorities::default();
+        assert_eq!(priorities.order(&[3, 1, 2]), vec![3, 1, 2]);
+
+        priorities.pieces = vec![
+            FilePriority::Low,
+            FilePriority::Skip,
+            FilePriority::High,
+            FilePriority::Normal,
+            FilePriority::High,
+        ];
+        assert_eq!(priorities.order(&[0, 1, 2, 3, 4, 5]), vec![2, 4, 3, 5, 0]);
+
+        priorities.deadline.insert(1);
+        priorities.deadline.insert(3);
+        assert_eq!(
+            priorities.order(&[0, 1, 2, 3, 4, 5]),
+            vec![3, 1, 2, 4, 5, 0]
+        );
+
+        priorities.sequential = true;
+        assert_eq!(
+            priorities.order(&[5, 4, 3, 2, 1, 0]),
+            vec![1, 3, 0, 2, 4, 5]
+        );
+    }
+}
@@ -0,0 +1,364 @@
+use super::*;
+use crate::types::Limits;
+use chrono::{Local, NaiveTime};
+use std::{
+    io,
+    pin::Pin,
+    sync::{Mutex, RwLock},
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::Delay,
+};
+
+const TIME_OF_DAY_FORMAT: &str = "%H:%M";
+
+/// Global bandwidth control.
+///
+/// Holds current limits and global token buckets. Limits can be changed at runtime,
+/// all buckets pick up new rates on next transfer.
+#[derive(Debug)]
+pub struct Bandwidth {
+    limits: RwLock<Limits>,
+    download: TokenBucket,
+    upload: TokenBucket,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rates {
+    download: Option<u64>,
+    upload: Option<u64>,
+}
+
+impl Bandwidth {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits: RwLock::new(limits),
+            download: TokenBucket::default(),
+            upload: TokenBucket::default(),
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits.read().unwrap().clone()
+    }
+
+    pub fn set_limits(&self, limits: Limits) -> Result<(), RsbtError> {
+        if let Some(alternative) = &limits.alternative {
+            parse_time_of_day(&alternative.from)?;
+            parse_time_of_day(&alternative.to)?;
+        }
+        *self.limits.write().unwrap() = limits;
+        Ok(())
+    }
+
+    /// Takes `amount` received bytes from global, torrent and peer limits and returns time to
+    /// wait before next read.
+    fn download_delay(&self, torrent: &TokenBucket, peer: &TokenBucket, amount: usize) -> Duration {
+        let (global_rate, torrent_rate, peer_rate) = {
+            let limits = self.limits.read().unwrap();
+            (
+                global_rates(&limits, Local::now().time()).download,
+                limits.torrent_download_rate,
+                limits.peer_download_rate,
+            )
+        };
+        self.download
+            .reserve(global_rate, amount)
+            .max(torrent.reserve(torrent_rate, amount))
+            .max(peer.reserve(peer_rate, amount))
+    }
+
+    /// Waits until `amount` bytes can be sent with global, torrent and peer limits.
+    pub(crate) async fn upload(&self, torrent: &TokenBucket, peer: &TokenBucket, amount: usize) {
+        let (global_rate, torrent_rate, peer_rate) = {
+            let limits = self.limits.read().unwrap();
+            (
+                global_rates(&limits, Local::now().time()).upload,
+                limits.torrent_upload_rate,
+                limits.peer_upload_rate,
+            )
+        };
+        let delay = self
+            .upload
+            .reserve(global_rate, amount)
+            .max(torrent.reserve(torrent_rate, amount))
+            .max(peer.reserve(peer_rate, amount));
+        if delay > Duration::from_millis(0) {
+            delay_for(delay).await;
+        }
+    }
+}
+
+/// Peer connection with download limits applied to every read from socket.
+///
+/// Received bytes are charged after read, following read waits until they are paid off, so
+/// socket is not drained faster than limits allow, whatever messages are received.
+pub(crate) struct ThrottledStream<S> {
+    inner: S,
+    bandwidth: Arc<Bandwidth>,
+    torrent: Arc<TokenBucket>,
+    peer: TokenBucket,
+    delay: Option<Delay>,
+}
+
+impl<S> ThrottledStream<S> {
+    pub(crate) fn new(inner: S, bandwidth: Arc<Bandwidth>, torrent: Arc<TokenBucket>) -> Self {
+        Self {
+            inner,
+            bandwidth,
+            torrent,
+            peer: TokenBucket::default(),
+            delay: None,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ThrottledStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if let Some(delay) = self.delay.as_mut() {
+            futures::ready!(Pin::new(delay).poll(cx));
+            self.delay = None;
+        }
+        let amount = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let delay = self
+            .bandwidth
+            .download_delay(&self.torrent, &self.peer, amount);
+        if delay > Duration::from_millis(0) {
+            self.delay = Some(tokio::time::delay_for(delay));
+        }
+        Poll::Ready(Ok(amount))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ThrottledStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Token bucket with capacity of one second of traffic.
+///
+/// Rate is passed on every reservation, so the same bucket follows runtime limit changes.
+#[derive(Debug)]
+pub(crate) struct TokenBucket(Mutex<TokenBucketState>);
+
+#[derive(Debug)]
+struct TokenBucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Default for TokenBucket {
+    fn default() -> Self {
+        Self(Mutex::new(TokenBucketState {
+            tokens: 0.0,
+            updated: Instant::now(),
+        }))
+    }
+}
+
+impl TokenBucket {
+    /// Takes `amount` tokens from bucket and returns time to wait before transfer.
+    ///
+    /// Bucket can go into debt, following reservations wait until debt is paid off.
+    fn reserve(&self, rate: Option<u64>, amount: usize) -> Duration {
+        self.reserve_at(rate, amount, Instant::now())
+    }
+
+    fn reserve_at(&self, rate: Option<u64>, amount: usize, now: Instant) -> Duration {
+        let mut state = self.0.lock().unwrap();
+        let elapsed = now.saturating_duration_since(state.updated).as_secs_f64();
+        state.updated = now;
+
+        let rate = match rate {
+            Some(rate) if rate > 0 => rate as f64,
+            _ => {
+                state.tokens = 0.0;
+                return Duration::from_millis(0);
+            }
+        };
+
+        state.tokens = (state.tokens + elapsed * rate).min(rate) - amount as f64;
+
+        if state.tokens < 0.0 {
+            Duration::from_secs_f64(-state.tokens / rate)
+        } else {
+            Duration::from_millis(0)
+        }
+    }
+}
+
+fn parse_time_of_day(value: &str) -> Result<NaiveTime, RsbtError> {
+    NaiveTime::parse_from_str(value, TIME_OF_DAY_FORMAT)
+        .map_err(|_| RsbtError::InvalidTimeOfDay(value.to_string()))
+}
+
+fn global_rates(limits: &Limits, now: NaiveTime) -> Rates {
+    if let Some(alternative) = &limits.alternative {
+        match (
+            parse_time_of_day(&alternative.from),
+            parse_time_of_day(&alternative.to),
+        ) {
+            (Ok(from), Ok(to)) => {
+                let active = if from <= to {
+                    from <= now && now < to
+                } else {
+                    from <= now || now < to
+                };
+                if active {
+                    return Rates {
+                        download: alternative.download_rate,
+                        upload: alternative.upload_rate,
+                    };
+                }
+            }
+            _ => error!(
+                "wrong alternative limits schedule: {} - {}",
+                alternative.from, alternative.to
+            ),
+        }
+    }
+    Rates {
+        download: limits.download_rate,
+        upload: limits.upload_rate,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::AlternativeLimits;
+
+    fn time(value: &str) -> NaiveTime {
+        parse_time_of_day(value).unwrap()
+    }
+
+    #[test]
+    fn token_bucket_delays_over_rate() {
+        let bucket = TokenBucket::default();
+        let start = Instant::now();
+
+        assert_eq!(
+            bucket.reserve_at(None, 1_000_000, start),
+            Duration::from_millis(0)
+        );
+        assert_eq!(
+            bucket.reserve_at(Some(0), 1_000_000, start),
+            Duration::from_millis(0)
+        );
+
+        assert_eq!(
+            bucket.reserve_at(Some(1000), 500, start),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            bucket.reserve_at(Some(1000), 500, start),
+            Duration::from_millis(1000)
+        );
+        assert_eq!(
+            bucket.reserve_at(Some(1000), 500, start + Duration::from_secs(2)),
+            Duration::from_millis(0)
+        );
+    }
+
+    #[test]
+    fn token_bucket_capacity_is_one_second() {
+        let bucket = TokenBucket::default();
+        let start = Instant::now();
+
+        bucket.reserve_at(Some(1000), 0, start);
+        assert_eq!(
+            bucket.reserve_at(Some(1000), 2000, start + Duration::from_secs(60)),
+            Duration::from_millis(1000)
+        );
+    }
+
+    #[test]
+    fn alternative_rates_by_time_of_day() {
+        let mut limits = Limits {
+            download_rate: Some(100),
+            upload_rate: Some(10),
+            alternative: Some(AlternativeLimits {
+                download_rate: Some(200),
+                upload_rate: None,
+                from: "08:00".into(),
+                to: "18:30".into(),
+            }),
+            ..Default::default()
+        };
+
+        let normal = Rates {
+            download: Some(100),
+            upload: Some(10),
+        };
+        let alternative = Rates {
+            download: Some(200),
+            upload: None,
+        };
+
+        assert_eq!(global_rates(&limits, time("07:59")), normal);
+        assert_eq!(global_rates(&limits, time("08:00")), alternative);
+        assert_eq!(global_rates(&limits, time("18:29")), alternative);
+        assert_eq!(global_rates(&limits, time("18:30")), normal);
+
+        if let Some(alternative) = limits.alternative.as_mut() {
+            alternative.from = "22:00".into();
+            alternative.to = "06:00".into();
+        }
+
+        assert_eq!(global_rates(&limits, time("12:00")), normal);
+        assert_eq!(global_rates(&limits, time("23:00")), alternative);
+        assert_eq!(global_rates(&limits, time("05:59")), alternative);
+    }
+
+    #[tokio::test]
+    async fn throttled_stream_delays_reads() {
+        let bandwidth = Arc::new(Bandwidth::new(Limits {
+            download_rate: Some(100_000),
+            ..Default::default()
+        }));
+        let data = vec![1u8; 20_000];
+        let mut stream = ThrottledStream::new(&data[..], bandwidth, Default::default());
+        let mut buf = vec![0; 10_000];
+        let start = Instant::now();
+
+        assert_eq!(stream.read(&mut buf).await.unwrap(), 10_000);
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(stream.read(&mut buf).await.unwrap(), 10_000);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn set_limits_checks_schedule() {
+        let bandwidth = Bandwidth::new(Limits::default());
+        let limits = Limits {
+            alternative: Some(AlternativeLimits {
+                download_rate: None,
+                upload_rate: None,
+                from: "25:00".into(),
+                to: "06:00".into(),
+            }),
+            ..Default::default()
+        };
+        assert!(bandwidth.set_limits(limits).is_err());
+        assert_eq!(bandwidth.limits(), Limits::default());
+    }
+}
@@ -0,0 +1,522 @@
+use super::*;
+use crate::types::to_bencode;
+use sha1::{Digest, Sha1};
+use std::{
+    fs::{self as std_fs, File},
+    io::{Read, Seek, SeekFrom},
+    str::FromStr,
+    thread,
+};
+use structopt::StructOpt;
+
+/// Smallest piece length chosen automatically.
+const MIN_PIECE_LENGTH: usize = BLOCK_SIZE;
+
+/// Largest piece length chosen automatically.
+const MAX_PIECE_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Piece length is doubled until torrent has not more pieces than this.
+const TARGET_PIECES_COUNT: usize = 2000;
+
+/// Options of new torrent, passed as arguments or as JSON.
+#[derive(StructOpt, Deserialize, Debug, Clone, Default)]
+pub struct TorrentCreateOptions {
+    /// File or directory to share
+    #[structopt(parse(from_os_str))]
+    pub path: PathBuf,
+    /// Announce tier, comma separated tracker urls, can be repeated, at least one tracker
+    /// is required
+    #[structopt(long, short, required = true, number_of_values = 1)]
+    pub announce: Vec<AnnounceTier>,
+    /// Web seed url (BEP 19), can be repeated
+    #[structopt(long, number_of_values = 1)]
+    #[serde(default)]
+    pub web_seed: Vec<String>,
+    /// Marks torrent as private, peers are only taken from trackers
+    #[structopt(long)]
+    #[serde(default)]
+    pub private: bool,
+    /// Piece length in bytes, power of two not less than 16 KiB, chosen by size of files if
+    /// not set
+    #[structopt(long)]
+    pub piece_length: Option<usize>,
+    /// Free form comment
+    #[structopt(long)]
+    pub comment: Option<String>,
+    /// Program which created torrent, default is rsbt with its version
+    #[structopt(long)]
+    pub creator: Option<String>,
+    /// Count of threads for piece hashing, default is count of CPUs
+    #[structopt(long)]
+    pub threads: Option<usize>,
+}
+
+/// Trackers of the same priority, they are tried in random order.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct AnnounceTier(pub Vec<String>);
+
+impl FromStr for AnnounceTier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let urls: Vec<String> = s
+            .split(',')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(String::from)
+            .collect();
+        if urls.is_empty() {
+            Err(format!("empty announce tier: {}", s))
+        } else {
+            Ok(AnnounceTier(urls))
+        }
+    }
+}
+
+/// Content of `.torrent` file, keys are sorted by serializer.
+#[derive(Serialize)]
+struct MetaInfo<'a> {
+    announce: &'a str,
+    #[serde(rename = "announce-list")]
+    announce_list: Option<&'a Vec<AnnounceTier>>,
+    comment: Option<&'a String>,
+    #[serde(rename = "created by")]
+    created_by: String,
+    #[serde(rename = "creation date")]
+    creation_date: i64,
+    info: MetaInfoInfo<'a>,
+    #[serde(rename = "url-list")]
+    url_list: Option<&'a Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct MetaInfoInfo<'a> {
+    files: Option<Vec<MetaInfoFile>>,
+    length: Option<usize>,
+    name: &'a str,
+    #[serde(rename = "piece length")]
+    piece_length: usize,
+    #[serde(with = "crate::types::bencode_bytes")]
+    pieces: Vec<u8>,
+    private: Option<u8>,
+}
+
+#[derive(Serialize)]
+struct MetaInfoFile {
+    length: usize,
+    path: Vec<String>,
+}
+
+/// Created torrent.
+#[derive(Debug)]
+pub struct TorrentCreated {
+    /// Name of torrent, file name or directory name
+    pub name: String,
+    /// Content of `.torrent` file
+    pub data: Vec<u8>,
+    /// Download path, where files of torrent are, to seed torrent
+    pub save_to: PathBuf,
+}
+
+/// Resolves path of files to share, relative path is resolved against first of `roots`.
+///
+/// Path is canonicalized, so neither `..` nor symlinks lead out of `roots`, and it must be
+/// inside of any of them. Path may not exist yet (e.g. new download path), then its existing
+/// parent is canonicalized and `..` is not allowed in the rest of path.
+pub fn shared_path<P: AsRef<Path>>(path: P, roots: &[PathBuf]) -> Result<PathBuf, RsbtError> {
+    let path = match roots.first() {
+        Some(root) => root.join(path.as_ref()),
+        None => path.as_ref().to_path_buf(),
+    };
+    let mut existing = path.as_path();
+    let mut missing = vec![];
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return Err(RsbtError::PathNotShared(path)),
+        }
+    }
+    let mut resolved = existing.canonicalize()?;
+    resolved.extend(missing.into_iter().rev());
+    let path = resolved;
+    let shared = roots
+        .iter()
+        .filter_map(|root| root.canonicalize().ok())
+        .any(|root| path.starts_with(root));
+    if shared {
+        Ok(path)
+    } else {
+        Err(RsbtError::PathNotShared(path))
+    }
+}
+
+/// Builds torrent from file or directory.
+///
+/// Files of directory are added in order of their paths, symlinks are not followed. Pieces
+/// are read and hashed in parallel, every thread takes its own range of pieces.
+pub fn create_torrent(options: &TorrentCreateOptions) -> Result<TorrentCreated, RsbtError> {
+    let announce_url = options
+        .announce
+        .first()
+        .and_then(|x| x.0.first())
+        .ok_or_else(|| RsbtError::CreateTorrent("no announce url".into()))?;
+    let metadata = std_fs::metadata(&options.path)?;
+    let path = options.path.canonicalize()?;
+    let name = path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| RsbtError::CreateTorrent(format!("invalid name of {:?}", path)))?
+        .to_string();
+
+    let (save_to, files) = if metadata.is_dir() {
+        let mut files = vec![];
+        collect_files(&path, &mut vec![], &mut files)?;
+        files.sort();
+        (path, files)
+    } else {
+        let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        (parent, vec![(vec![name.clone()], metadata.len() as usize)])
+    };
+    if files.is_empty() {
+        return Err(RsbtError::CreateTorrent(format!(
+            "no files in {:?}",
+            options.path
+        )));
+    }
+
+    let length: usize = files.iter().map(|(_, length)| length).sum();
+    let piece_length = match options.piece_length {
+        Some(piece_length) if piece_length.is_power_of_two() && piece_length >= BLOCK_SIZE => {
+            piece_length
+        }
+        Some(piece_length) => {
+            return Err(RsbtError::CreateTorrent(format!(
+                "invalid piece length {}",
+                piece_length
+            )))
+        }
+        None => auto_piece_length(length),
+    };
+
+    let threads = options.threads.unwrap_or_else(num_cpus::get);
+    let file_paths = files
+        .iter()
+        .map(|(path, length)| (save_to.join(path.iter().collect::<PathBuf>()), *length))
+        .collect();
+    let pieces = hash_pieces(file_paths, length, piece_length, threads)?;
+
+    let (info_files, info_length) = if metadata.is_dir() {
+        let files = files
+            .into_iter()
+            .map(|(path, length)| MetaInfoFile { length, path })
+            .collect();
+        (Some(files), None)
+    } else {
+        (None, Some(length))
+    };
+    let multiple_trackers =
+        options.announce.len() > 1 || options.announce.iter().any(|x| x.0.len() > 1);
+    let meta_info = MetaInfo {
+        announce: announce_url,
+        announce_list: Some(&options.announce).filter(|_| multiple_trackers),
+        comment: options.comment.as_ref(),
+        created_by: options
+            .creator
+            .clone()
+            .unwrap_or_else(|| format!("rsbt/{}", env!("CARGO_PKG_VERSION"))),
+        creation_date: chrono::Utc::now().timestamp(),
+        info: MetaInfoInfo {
+            files: info_files,
+            length: info_length,
+            name: &name,
+            piece_length,
+            pieces,
+            private: Some(1).filter(|_| options.private),
+        },
+        url_list: Some(&options.web_seed).filter(|x| !x.is_empty()),
+    };
+
+    let data = to_bencode(&meta_info)?;
+    debug!("created torrent {} of {} bytes", name, length);
+
+    Ok(TorrentCreated {
+        name,
+        data,
+        save_to,
+    })
+}
+
+/// Chooses power of two piece length, so torrent has not too many pieces.
+fn auto_piece_length(length: usize) -> usize {
+    let mut piece_length = MIN_PIECE_LENGTH;
+    while piece_length < MAX_PIECE_LENGTH && count_parts(length, piece_length) > TARGET_PIECES_COUNT
+    {
+        piece_length *= 2;
+    }
+    piece_length
+}
+
+/// Collects regular files of directory with paths relative to it.
+fn collect_files(
+    dir: &Path,
+    path: &mut Vec<String>,
+    files: &mut Vec<(Vec<String>, usize)>,
+) -> Result<(), RsbtError> {
+    for entry in std_fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let name = entry.file_name().into_string().map_err(|name| {
+            RsbtError::CreateTorrent(format!("file name {:?} is not valid utf-8", name))
+        })?;
+        path.push(name);
+        if file_type.is_dir() {
+            collect_files(&entry.path(), path, files)?;
+        } else if file_type.is_file() {
+            files.push((path.clone(), entry.metadata()?.len() as usize));
+        } else {
+            debug!("skip {:?}", entry.path());
+        }
+        path.pop();
+    }
+    Ok(())
+}
+
+/// Reads files as continuous data and returns concatenated SHA-1 hashes of pieces.
+fn hash_pieces(
+    files: Vec<(PathBuf, usize)>,
+    length: usize,
+    piece_length: usize,
+    threads: usize,
+) -> Result<Vec<u8>, RsbtError> {
+    let pieces_count = count_parts(length, piece_length);
+    let threads = threads.max(1).min(pieces_count.max(1));
+    let pieces_per_thread = count_parts(pieces_count, threads);
+    let files = Arc::new(files);
+
+    let workers: Vec<_> = (0..threads)
+        .map(|thread_index| {
+            let files = files.clone();
+            let first = pieces_count.min(thread_index * pieces_per_thread);
+            let last = pieces_count.min(first + pieces_per_thread);
+            thread::spawn(move || -> Result<Vec<u8>, std::io::Error> {
+                let mut reader = FilesReader::new(&files);
+                let mut hashes = Vec::with_capacity((last - first) * SHA1_SIZE);
+                let mut piece = vec![];
+                for index in first..last {
+                    let begin = index * piece_length;
+                    piece.resize(piece_length.min(length - begin), 0);
+                    reader.read_exact_at(begin, &mut piece)?;
+                    hashes.extend_from_slice(&Sha1::digest(&piece));
+                }
+                Ok(hashes)
+            })
+        })
+        .collect();
+
+    let mut pieces = Vec::with_capacity(pieces_count * SHA1_SIZE);
+    for worker in workers {
+        let hashes = worker
+            .join()
+            .map_err(|_| RsbtError::CreateTorrent("hashing thread panicked".into()))??;
+        pieces.extend(hashes);
+    }
+    Ok(pieces)
+}
+
+/// Reads files, as they were one continuous file.
+struct FilesReader<'a> {
+    files: &'a [(PathBuf, usize)],
+    open: Option<(usize, File)>,
+}
+
+impl<'a> FilesReader<'a> {
+    fn new(files: &'a [(PathBuf, usize)]) -> Self {
+        Self { files, open: None }
+    }
+
+    fn read_exact_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), std::io::Error> {
+        let mut file_begin = 0;
+        let mut filled = 0;
+        for (index, (path, file_length)) in self.files.iter().enumerate() {
+            let file_end = file_begin + file_length;
+            let position = offset + filled;
+            if filled < buf.len() && position < file_end {
+                let chunk = (file_end - position).min(buf.len() - filled);
+                let reopen = match &self.open {
+                    Some((open_index, _)) => *open_index != index,
+                    None => true,
+                };
+                if reopen {
+                    self.open = Some((index, File::open(path)?));
+                }
+                let file = &mut self.open.as_mut().unwrap().1;
+                file.seek(SeekFrom::Start((position - file_begin) as u64))?;
+                file.read_exact(&mut buf[filled..filled + chunk])?;
+                filled += chunk;
+            }
+            file_begin = file_end;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::torrent::parse_torrent;
+
+    #[test]
+    fn check_auto_piece_length() {
+        assert_eq!(auto_piece_length(0), MIN_PIECE_LENGTH);
+        assert_eq!(
+            auto_piece_length(BLOCK_SIZE * TARGET_PIECES_COUNT),
+            BLOCK_SIZE
+        );
+        assert_eq!(
+            auto_piece_length(BLOCK_SIZE * TARGET_PIECES_COUNT + 1),
+            BLOCK_SIZE * 2
+        );
+        assert_eq!(auto_piece_length(usize::MAX / 2), MAX_PIECE_LENGTH);
+    }
+
+    #[test]
+    fn create_directory_torrent() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = dir.path().join("data");
+        std_fs::create_dir_all(data.join("sub")).unwrap();
+        let a: Vec<u8> = (0..BLOCK_SIZE + 100).map(|x| x as u8).collect();
+        let b = vec![7; BLOCK_SIZE * 2];
+        std_fs::write(data.join("sub").join("b"), &b).unwrap();
+        std_fs::write(data.join("a"), &a).unwrap();
+        std_fs::write(data.join("empty"), b"").unwrap();
+
+        let options = TorrentCreateOptions {
+            path: data.clone(),
+            announce: vec![
+                "http://a/announce, udp://b:6969".parse().unwrap(),
+                "http://c/announce".parse().unwrap(),
+            ],
+            web_seed: vec!["http://seed/".into()],
+            private: true,
+            piece_length: Some(BLOCK_SIZE),
+            comment: Some("dataset".into()),
+            threads: Some(2),
+            ..Default::default()
+        };
+        let created = create_torrent(&options).unwrap();
+        assert_eq!(created.name, "data");
+        assert_eq!(created.save_to, data.canonicalize().unwrap());
+
+        let torrent = parse_torrent(&created.data).unwrap();
+        assert_eq!(torrent.announce_url, "http://a/announce");
+        assert_eq!(
+            torrent.announce_list,
+            Some(vec![
+                vec!["http://a/announce".into(), "udp://b:6969".into()],
+                vec!["http://c/announce".into()],
+            ])
+        );
+        let info = torrent.info().unwrap();
+        assert_eq!(
+            info.files
+                .iter()
+                .map(|x| (x.path.clone(), x.length))
+                .collect::<Vec<_>>(),
+            vec![
+                (PathBuf::from("a"), a.len()),
+                (PathBuf::from("empty"), 0),
+                (PathBuf::from("sub").join("b"), b.len()),
+            ]
+        );
+        let content = [a, b].concat();
+        assert_eq!(info.pieces.len(), 4);
+        for (piece, data) in info.pieces.iter().zip(content.chunks(BLOCK_SIZE)) {
+            assert!(piece.verify(data));
+        }
+
+        let single = create_torrent(&TorrentCreateOptions {
+            path: data.join("a"),
+            announce: vec!["http://a/announce".parse().unwrap()],
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(single.save_to, data.canonicalize().unwrap());
+        let info = parse_torrent(&single.data).unwrap().info().unwrap();
+        assert_eq!(info.files[0].path, PathBuf::from("a"));
+        assert_eq!(info.pieces.len(), 2);
+
+        for options in &[
+            TorrentCreateOptions {
+                path: data.clone(),
+                announce: vec!["http://a/announce".parse().unwrap()],
+                piece_length: Some(BLOCK_SIZE + 1),
+                ..Default::default()
+            },
+            TorrentCreateOptions {
+                path: data.join("sub").join("missing"),
+                announce: vec!["http://a/announce".parse().unwrap()],
+                ..Default::default()
+            },
+            TorrentCreateOptions {
+                path: data.clone(),
+                ..Default::default()
+            },
+        ] {
+            assert!(create_torrent(options).is_err());
+        }
+    }
+
+    #[test]
+    fn shared_path_is_limited_to_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let download = dir.path().join("download");
+        let share = dir.path().join("share");
+        let other = dir.path().join("other");
+        for path in &[&download, &share, &other] {
+            std_fs::create_dir_all(path.join("files")).unwrap();
+        }
+        let roots = vec![download.clone(), share.clone()];
+
+        assert_eq!(
+            shared_path("files", &roots).unwrap(),
+            download.join("files").canonicalize().unwrap()
+        );
+        assert_eq!(
+            shared_path(share.join("files"), &roots).unwrap(),
+            share.join("files").canonicalize().unwrap()
+        );
+        assert!(matches!(
+            shared_path("../other/files", &roots),
+            Err(RsbtError::PathNotShared(_))
+        ));
+        assert!(matches!(
+            shared_path("files/../../other", &roots),
+            Err(RsbtError::PathNotShared(_))
+        ));
+        assert!(matches!(
+            shared_path(other.join("files"), &roots),
+            Err(RsbtError::PathNotShared(_))
+        ));
+        assert!(matches!(
+            shared_path("/", &roots),
+            Err(RsbtError::PathNotShared(_))
+        ));
+
+        assert_eq!(
+            shared_path("new/dir", &roots).unwrap(),
+            download.canonicalize().unwrap().join("new").join("dir")
+        );
+        assert!(matches!(
+            shared_path("new/../../other", &roots),
+            Err(RsbtError::PathNotShared(_))
+        ));
+        assert!(matches!(
+            shared_path(other.join("new"), &roots),
+            Err(RsbtError::PathNotShared(_))
+        ));
+    }
+}
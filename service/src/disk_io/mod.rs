@@ -0,0 +1,313 @@
+use super::*;
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    fmt,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{Condvar, Mutex},
+    thread,
+};
+use tokio::sync::Semaphore;
+
+/// Default count of jobs, which may be queued or executed at once.
+pub const DEFAULT_DISK_JOB_CAPACITY: usize = 256;
+
+/// Part of capacity reserved for reads, so peer requests and streaming are not blocked by
+/// flood of writes and hash checks.
+const READ_RESERVED_SHARE: usize = 4;
+
+/// Kind of disk job, jobs with higher priority are executed first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DiskJobPriority {
+    /// Hash check of existing data
+    HashCheck,
+    /// Write of downloaded data
+    Write,
+    /// Read for peer requests and streaming
+    Read,
+}
+
+/// Shared pool of disk I/O threads.
+///
+/// All torrents put their reads, writes and hash checks into one queue, so count of threads
+/// does not depend on count of torrents. Queue is bounded: when all slots are taken, jobs
+/// wait for a free slot before they are queued.
+pub struct DiskIo {
+    threads: usize,
+    queue: Arc<DiskJobQueue>,
+    slots: Arc<Semaphore>,
+    read_slots: Arc<Semaphore>,
+}
+
+#[derive(Default)]
+struct DiskJobQueue {
+    jobs: Mutex<DiskJobs>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct DiskJobs {
+    heap: BinaryHeap<DiskJob>,
+    sequence: u64,
+    closed: bool,
+}
+
+struct DiskJob {
+    priority: DiskJobPriority,
+    sequence: u64,
+    job: Box<dyn FnOnce() + Send>,
+}
+
+/// Slot taken by queued or executed job, it is released when job is finished.
+struct DiskJobSlot(Arc<Semaphore>);
+
+impl Drop for DiskJobSlot {
+    fn drop(&mut self) {
+        self.0.add_permits(1);
+    }
+}
+
+impl PartialEq for DiskJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for DiskJob {}
+
+impl PartialOrd for DiskJob {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DiskJob {
+    /// Higher priority first, then older job first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl DiskIo {
+    /// Starts pool with `threads` workers, at least one.
+    pub fn new(threads: usize) -> Self {
+        Self::with_capacity(threads, DEFAULT_DISK_JOB_CAPACITY)
+    }
+
+    /// Starts pool with `threads` workers, which accepts up to `capacity` jobs at once.
+    ///
+    /// Reads may use all slots, other jobs cannot take slots reserved for reads.
+    pub fn with_capacity(threads: usize, capacity: usize) -> Self {
+        let threads = threads.max(1);
+        let reserved = (capacity / READ_RESERVED_SHARE).max(1);
+        let shared = capacity.saturating_sub(reserved).max(1);
+        let queue = Arc::new(DiskJobQueue::default());
+        for index in 0..threads {
+            let queue = queue.clone();
+            if let Err(err) = thread::Builder::new()
+                .name(format!("rsbt-disk-io-{}", index))
+                .spawn(move || disk_io_worker(queue))
+            {
+                error!("cannot start disk I/O thread: {}", err);
+            }
+        }
+        Self {
+            threads,
+            queue,
+            slots: Arc::new(Semaphore::new(shared)),
+            read_slots: Arc::new(Semaphore::new(reserved)),
+        }
+    }
+
+    /// Count of worker threads.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Count of jobs waiting in queue.
+    pub fn queue_depth(&self) -> usize {
+        self.queue.jobs.lock().unwrap().heap.len()
+    }
+
+    /// Runs blocking `f` in pool and waits for result.
+    ///
+    /// Waits for a free slot first, if queue is full.
+    pub async fn run<T, F>(&self, priority: DiskJobPriority, f: F) -> Result<T, RsbtError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let slot = self.slot(priority).await;
+        let (sender, receiver) = oneshot::channel();
+        self.push(
+            priority,
+            Box::new(move || {
+                let result = f();
+                drop(slot);
+                if sender.send(result).is_err() {
+                    debug!("disk job result is not awaited anymore");
+                }
+            }),
+        );
+        receiver.await.map_err(|_| RsbtError::DiskIoJob)
+    }
+
+    /// Takes a free slot, reads fall back to reserved slots when shared slots are taken.
+    async fn slot(&self, priority: DiskJobPriority) -> DiskJobSlot {
+        if priority == DiskJobPriority::Read {
+            if let Ok(permit) = self.slots.try_acquire() {
+                permit.forget();
+                return DiskJobSlot(self.slots.clone());
+            }
+            self.read_slots.acquire().await.forget();
+            return DiskJobSlot(self.read_slots.clone());
+        }
+        self.slots.acquire().await.forget();
+        DiskJobSlot(self.slots.clone())
+    }
+
+    fn push(&self, priority: DiskJobPriority, job: Box<dyn FnOnce() + Send>) {
+        let mut jobs = self.queue.jobs.lock().unwrap();
+        jobs.sequence += 1;
+        let sequence = jobs.sequence;
+        jobs.heap.push(DiskJob {
+            priority,
+            sequence,
+            job,
+        });
+        self.queue.available.notify_one();
+    }
+}
+
+impl Drop for DiskIo {
+    fn drop(&mut self) {
+        self.queue.jobs.lock().unwrap().closed = true;
+        self.queue.available.notify_all();
+    }
+}
+
+impl fmt::Debug for DiskIo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DiskIo")
+            .field("threads", &self.threads)
+            .field("queue_depth", &self.queue_depth())
+            .finish()
+    }
+}
+
+/// Executes jobs until pool is dropped and queue is empty.
+fn disk_io_worker(queue: Arc<DiskJobQueue>) {
+    loop {
+        let job = {
+            let mut jobs = queue.jobs.lock().unwrap();
+            loop {
+                if let Some(job) = jobs.heap.pop() {
+                    break job;
+                }
+                if jobs.closed {
+                    return;
+                }
+                jobs = queue.available.wait(jobs).unwrap();
+            }
+        };
+        if catch_unwind(AssertUnwindSafe(job.job)).is_err() {
+            error!("disk job panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{poll, FutureExt};
+    use std::sync::mpsc;
+
+    /// Job, which signals that it is started and then waits until it is released.
+    fn blocking_job() -> (
+        impl FnOnce() + Send + 'static,
+        mpsc::Receiver<()>,
+        mpsc::Sender<()>,
+    ) {
+        let (started_sender, started_receiver) = mpsc::channel();
+        let (block_sender, block_receiver) = mpsc::channel();
+        let job = move || {
+            started_sender.send(()).unwrap();
+            block_receiver.recv().unwrap()
+        };
+        (job, started_receiver, block_sender)
+    }
+
+    #[test]
+    fn jobs_are_executed_by_priority() {
+        let disk_io = DiskIo::new(1);
+        let (job, started, release) = blocking_job();
+        disk_io.push(DiskJobPriority::Read, Box::new(job));
+        started.recv().unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        for (priority, name) in &[
+            (DiskJobPriority::HashCheck, "check 1"),
+            (DiskJobPriority::Write, "write 1"),
+            (DiskJobPriority::Read, "read 1"),
+            (DiskJobPriority::HashCheck, "check 2"),
+            (DiskJobPriority::Read, "read 2"),
+        ] {
+            let sender = sender.clone();
+            let name = *name;
+            disk_io.push(*priority, Box::new(move || sender.send(name).unwrap()));
+        }
+        assert_eq!(disk_io.queue_depth(), 5);
+
+        release.send(()).unwrap();
+        let order: Vec<_> = receiver.iter().take(5).collect();
+        assert_eq!(
+            order,
+            vec!["read 1", "read 2", "write 1", "check 1", "check 2"]
+        );
+        assert_eq!(disk_io.queue_depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn run_returns_result() {
+        let disk_io = DiskIo::new(2);
+
+        assert_eq!(
+            disk_io.run(DiskJobPriority::Write, || 2 + 2).await.unwrap(),
+            4
+        );
+        assert!(disk_io
+            .run(DiskJobPriority::Read, || -> u32 { panic!("failed job") })
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn run_waits_for_free_slot() {
+        // one shared slot and one slot reserved for reads
+        let disk_io = DiskIo::with_capacity(1, 2);
+        let (job, started, release) = blocking_job();
+        let mut blocked = disk_io.run(DiskJobPriority::Write, job).boxed();
+        assert!(poll!(&mut blocked).is_pending());
+        started.recv().unwrap();
+        assert_eq!(disk_io.queue_depth(), 0);
+        assert_eq!(disk_io.slots.available_permits(), 0);
+
+        let mut write = disk_io.run(DiskJobPriority::Write, || "write").boxed();
+        assert!(poll!(&mut write).is_pending());
+        assert_eq!(disk_io.queue_depth(), 0, "write must wait for free slot");
+
+        let mut read = disk_io.run(DiskJobPriority::Read, || "read").boxed();
+        assert!(poll!(&mut read).is_pending());
+        assert_eq!(disk_io.queue_depth(), 1, "read must take reserved slot");
+        assert_eq!(disk_io.read_slots.available_permits(), 0);
+
+        release.send(()).unwrap();
+        blocked.await.unwrap();
+        assert_eq!(read.await.unwrap(), "read");
+        assert_eq!(write.await.unwrap(), "write");
+        assert_eq!(disk_io.slots.available_permits(), 1);
+        assert_eq!(disk_io.read_slots.available_permits(), 1);
+    }
+}
@@ -19,6 +19,79 @@ pub enum TryFromBencode {
     NotUtf8(std::str::Utf8Error),
     #[fail(display = "not valid ip {}", _0)]
     NotValidIp(std::net::AddrParseError),
+    #[fail(display = "integer {} is out of range", _0)]
+    OutOfRange(i64),
+    #[fail(display = "missing field {}", _0)]
+    MissingField(&'static str),
+}
+
+/// Error of bencode serialization and deserialization with serde.
+#[derive(Debug)]
+pub enum BencodeError {
+    /// Data is not valid bencode
+    Parse,
+    /// Value cannot be represented in bencode, e.g. floats
+    Unsupported(&'static str),
+    Message(String),
+}
+
+impl std::fmt::Display for BencodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BencodeError::Parse => write!(f, "invalid bencode"),
+            BencodeError::Unsupported(value) => write!(f, "unsupported in bencode: {}", value),
+            BencodeError::Message(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for BencodeError {}
+
+impl serde::ser::Error for BencodeError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        BencodeError::Message(msg.to_string())
+    }
+}
+
+impl serde::de::Error for BencodeError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        BencodeError::Message(msg.to_string())
+    }
+}
+
+/// Reasons to reject info block of torrent.
+#[derive(Debug, Fail)]
+pub enum TorrentInfoError {
+    #[fail(display = "torrent has neither length nor files")]
+    NoFiles,
+    #[fail(display = "invalid piece length {}", _0)]
+    InvalidPieceLength(i64),
+    #[fail(display = "invalid file length {}", _0)]
+    InvalidFileLength(i64),
+    #[fail(display = "empty file path")]
+    EmptyPath,
+    #[fail(display = "unsafe file path {:?}", _0)]
+    UnsafePath(Vec<String>),
+    #[fail(display = "symlink {:?} has no target", _0)]
+    InvalidSymlink(Vec<String>),
+    #[fail(display = "duplicate file path {:?}", _0)]
+    DuplicatePath(std::path::PathBuf),
+    #[fail(display = "torrent has no pieces")]
+    NoPieces,
+    #[fail(display = "pieces do not match length of torrent")]
+    InvalidPieces,
+    #[fail(display = "torrent is too large")]
+    TooLarge,
+    #[fail(display = "unsupported meta version {}", _0)]
+    UnsupportedMetaVersion(i64),
+    #[fail(display = "invalid file tree at {:?}", _0)]
+    InvalidFileTree(Vec<String>),
+    #[fail(display = "invalid piece layers")]
+    InvalidPieceLayers,
+    #[fail(display = "piece layer of file {:?} is missing or does not match", _0)]
+    PieceLayerMismatch(std::path::PathBuf),
+    #[fail(display = "v1 and v2 files of hybrid torrent differ")]
+    HybridMismatch,
 }
 
 #[derive(Debug, Fail)]
@@ -27,6 +100,8 @@ pub enum RsbtError {
     IO(std::io::Error),
     #[fail(display = "try from bencode {}", _0)]
     TryFromBencode(TryFromBencode),
+    #[fail(display = "bencode {}", _0)]
+    Bencode(BencodeError),
     #[fail(display = "convert {}", _0)]
     Convert(std::convert::Infallible),
     #[fail(display = "convert integer {}", _0)]
@@ -85,6 +160,31 @@ pub enum RsbtError {
     TorrentActionNotSupported,
     #[fail(display = "elapsed {}", _0)]
     Elapsed(tokio::time::Elapsed),
+    #[fail(display = "invalid time of day {}, expected HH:MM", _0)]
+    InvalidTimeOfDay(String),
+    #[fail(display = "disk I/O job failed")]
+    DiskIoJob,
+    #[fail(
+        display = "not enough disk space: {} bytes required, {} bytes available",
+        required, available
+    )]
+    NotEnoughSpace { required: u64, available: u64 },
+    #[fail(display = "invalid category {}, expected single directory name", _0)]
+    InvalidCategory(String),
+    #[fail(display = "files of torrent are already moving")]
+    StorageMoveInProgress,
+    #[fail(display = "invalid torrent info: {}", _0)]
+    InvalidTorrentInfo(TorrentInfoError),
+    #[fail(display = "cannot create torrent: {}", _0)]
+    CreateTorrent(String),
+    #[fail(display = "path {:?} is outside of shared directories", _0)]
+    PathNotShared(std::path::PathBuf),
+    #[fail(display = "torrent stopped: {}", _0)]
+    TorrentStopped(String),
+    #[fail(display = "{} of {} pieces failed hash check", _0, _1)]
+    HashCheckFailed(usize, usize),
+    #[fail(display = "piece {} failed hash check", _0)]
+    PieceHashMismatch(usize),
 }
 
 macro_rules! from_rsbt_error {
@@ -99,6 +199,8 @@ macro_rules! from_rsbt_error {
 
 from_rsbt_error!(hyper::Error, HTTPClient);
 from_rsbt_error!(TryFromBencode, TryFromBencode);
+from_rsbt_error!(BencodeError, Bencode);
+from_rsbt_error!(TorrentInfoError, InvalidTorrentInfo);
 from_rsbt_error!(std::io::Error, IO);
 from_rsbt_error!(std::convert::Infallible, Convert);
 from_rsbt_error!(std::num::TryFromIntError, ConvertInt);
@@ -0,0 +1,47 @@
+//! Entry points of fuzz targets in `service/fuzz`, they are enabled by `fuzzing` feature.
+//!
+//! Every function feeds untrusted data to parsers and ignores results, fuzzer looks for
+//! panics, hangs and excessive allocations.
+use crate::{
+    parser::parse_handshake,
+    types::{
+        message::MessageCodec,
+        torrent::{parse_torrent, TrackerAnnounce},
+        udp_tracker::UdpTrackerCodec,
+        BencodeBlob,
+    },
+};
+use bytes::BytesMut;
+use std::convert::TryFrom;
+use tokio_util::codec::Decoder;
+
+/// Parses bencode, torrent with its info and tracker response.
+pub fn bencode(data: &[u8]) {
+    if let Ok(blob) = BencodeBlob::try_from(data) {
+        let _ = BencodeBlob::from(blob.value);
+    }
+    if let Ok(torrent) = parse_torrent(data) {
+        let _ = torrent.info();
+    }
+    let _ = TrackerAnnounce::try_from(data);
+}
+
+/// Parses peer handshake.
+pub fn handshake(data: &[u8]) {
+    let _ = parse_handshake(data);
+}
+
+/// Decodes peer messages, until data is incomplete or invalid.
+pub fn message_codec(data: &[u8]) {
+    decode_all(MessageCodec, data);
+}
+
+/// Decodes UDP tracker responses, until data is incomplete or invalid.
+pub fn udp_tracker_codec(data: &[u8]) {
+    decode_all(UdpTrackerCodec, data);
+}
+
+fn decode_all<D: Decoder>(mut decoder: D, data: &[u8]) {
+    let mut buf = BytesMut::from(data);
+    while let Ok(Some(_)) = decoder.decode(&mut buf) {}
+}
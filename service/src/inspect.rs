@@ -0,0 +1,277 @@
+use super::*;
+use crate::types::{
+    info::{TorrentInfo, TorrentInfoRaw, TorrentVersion},
+    torrent::Torrent,
+};
+use flat_storage::map_pieces_to_files;
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+/// Summary of `.torrent` file.
+#[derive(Serialize, Debug)]
+pub struct TorrentInspection {
+    pub name: String,
+    /// SHA-1 info hash in hex, none for v2 torrents
+    pub info_hash: Option<String>,
+    /// SHA-256 info hash in hex, none for v1 torrents
+    pub info_hash_v2: Option<String>,
+    pub version: String,
+    /// Tracker tiers, `announce` is the only tier if torrent has no `announce-list`
+    pub trackers: Vec<Vec<String>>,
+    pub piece_length: usize,
+    pub pieces: usize,
+    pub length: usize,
+    /// Files with their lengths, pad files are not listed
+    pub files: Vec<TorrentInspectionFile>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TorrentInspectionFile {
+    pub path: PathBuf,
+    pub length: usize,
+}
+
+/// Result of verification of torrent data on disk.
+#[derive(Serialize, Debug)]
+pub struct TorrentVerification {
+    pub pieces: usize,
+    pub valid_pieces: usize,
+    /// Files, which have missing or corrupt pieces
+    pub files: Vec<FileVerification>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FileVerification {
+    pub path: PathBuf,
+    /// Pieces of file with data which is absent, e.g. file does not exist or is too short
+    pub missing: Vec<usize>,
+    /// Pieces of file with data which does not match the hash
+    pub corrupt: Vec<usize>,
+}
+
+impl TorrentVerification {
+    /// Checks if all pieces are present and valid.
+    pub fn is_complete(&self) -> bool {
+        self.valid_pieces == self.pieces
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// Collects name, info hashes, trackers and files of torrent.
+pub fn inspect_torrent(torrent: &Torrent) -> Result<TorrentInspection, RsbtError> {
+    let raw: TorrentInfoRaw = torrent.info.clone().try_into()?;
+    let info = torrent.info()?;
+    let trackers = match &torrent.announce_list {
+        Some(announce_list) if !announce_list.is_empty() => announce_list.clone(),
+        _ => vec![vec![torrent.announce_url.clone()]],
+    };
+    Ok(TorrentInspection {
+        name: raw.name,
+        info_hash: if info.version == TorrentVersion::V2 {
+            None
+        } else {
+            Some(hex(&torrent.info_sha1_hash()))
+        },
+        info_hash_v2: if info.version == TorrentVersion::V1 {
+            None
+        } else {
+            Some(hex(&torrent.info_sha256_hash()))
+        },
+        version: format!("{:?}", info.version).to_lowercase(),
+        trackers,
+        piece_length: info.piece_length,
+        pieces: info.pieces.len(),
+        length: info.length,
+        files: info
+            .files
+            .iter()
+            .filter(|file| !file.pad)
+            .map(|file| TorrentInspectionFile {
+                path: file.path.clone(),
+                length: file.length,
+            })
+            .collect(),
+    })
+}
+
+/// Hashes data of torrent in `dir`, where files are laid out as in `save_to` directory.
+///
+/// Files are only read, so it is safe to verify data of running torrents.
+pub fn verify_torrent<P: AsRef<Path>>(
+    info: &TorrentInfo,
+    dir: P,
+) -> Result<TorrentVerification, RsbtError> {
+    let mapping = map_pieces_to_files(info.piece_length, &info.files);
+    let mut files: Vec<FileVerification> = info
+        .files
+        .iter()
+        .map(|file| FileVerification {
+            path: file.path.clone(),
+            missing: vec![],
+            corrupt: vec![],
+        })
+        .collect();
+    let mut reader = FileReader {
+        dir: dir.as_ref(),
+        current: None,
+    };
+    let mut valid_pieces = 0;
+
+    for (index, (piece, piece_mapping)) in info.pieces.iter().zip(&mapping).enumerate() {
+        let mut data = vec![0; piece_mapping.size()];
+        let mut missing = vec![];
+        for file_block in &piece_mapping.0 {
+            let file = &info.files[file_block.file_index];
+            if file.pad || file_block.size == 0 {
+                continue;
+            }
+            let buf = &mut data[file_block.offset..file_block.offset + file_block.size];
+            if !reader.read(
+                file_block.file_index,
+                &file.path,
+                file_block.file_offset,
+                buf,
+            )? {
+                missing.push(file_block.file_index);
+            }
+        }
+
+        if !missing.is_empty() {
+            for file_index in missing {
+                files[file_index].missing.push(index);
+            }
+        } else if piece.verify(&data) {
+            valid_pieces += 1;
+        } else {
+            for file_block in &piece_mapping.0 {
+                if !info.files[file_block.file_index].pad {
+                    files[file_block.file_index].corrupt.push(index);
+                }
+            }
+        }
+    }
+
+    Ok(TorrentVerification {
+        pieces: info.pieces.len(),
+        valid_pieces,
+        files: files
+            .into_iter()
+            .filter(|file| !file.missing.is_empty() || !file.corrupt.is_empty())
+            .collect(),
+    })
+}
+
+/// Reads files one by one, as pieces go through files in order.
+struct FileReader<'a> {
+    dir: &'a Path,
+    current: Option<(usize, Option<File>)>,
+}
+
+impl<'a> FileReader<'a> {
+    /// Fills `buf` from `offset` of file, returns false if file does not exist or is shorter.
+    fn read(
+        &mut self,
+        file_index: usize,
+        path: &Path,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<bool, RsbtError> {
+        match &self.current {
+            Some((current, _)) if *current == file_index => (),
+            _ => {
+                let file = match File::open(self.dir.join(path)) {
+                    Ok(file) => Some(file),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                    Err(err) => return Err(err.into()),
+                };
+                self.current = Some((file_index, file));
+            }
+        }
+        let file = match &mut self.current {
+            Some((_, Some(file))) => file,
+            _ => return Ok(false),
+        };
+        file.seek(SeekFrom::Start(offset as u64))?;
+        match file.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        creator::{create_torrent, TorrentCreateOptions},
+        types::torrent::parse_torrent,
+    };
+    use std::fs;
+
+    #[test]
+    fn inspect_and_verify_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(&data).unwrap();
+        let a = vec![1; BLOCK_SIZE + 100];
+        let b = vec![2; BLOCK_SIZE * 2];
+        fs::write(data.join("a"), &a).unwrap();
+        fs::write(data.join("b"), &b).unwrap();
+
+        let created = create_torrent(&TorrentCreateOptions {
+            path: data.clone(),
+            announce: vec!["http://a/announce".parse().unwrap()],
+            piece_length: Some(BLOCK_SIZE),
+            ..Default::default()
+        })
+        .unwrap();
+        let torrent = parse_torrent(&created.data).unwrap();
+
+        let inspection = inspect_torrent(&torrent).unwrap();
+        assert_eq!(inspection.name, "data");
+        assert_eq!(
+            inspection.info_hash.as_deref().map(str::len),
+            Some(SHA1_SIZE * 2)
+        );
+        assert_eq!(inspection.info_hash_v2, None);
+        assert_eq!(inspection.trackers, vec![vec!["http://a/announce"]]);
+        assert_eq!(inspection.piece_length, BLOCK_SIZE);
+        assert_eq!(inspection.pieces, 4);
+        assert_eq!(inspection.files.len(), 2);
+
+        let info = torrent.info().unwrap();
+        let verification = verify_torrent(&info, &data).unwrap();
+        assert!(verification.is_complete());
+        assert!(verification.files.is_empty());
+
+        let mut b = b;
+        b[BLOCK_SIZE] = 0;
+        fs::write(data.join("b"), &b).unwrap();
+        fs::write(data.join("a"), &a[..BLOCK_SIZE]).unwrap();
+        let verification = verify_torrent(&info, &data).unwrap();
+        assert_eq!(verification.valid_pieces, 2);
+        let files: Vec<_> = verification
+            .files
+            .iter()
+            .map(|x| (x.path.clone(), x.missing.clone(), x.corrupt.clone()))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                (PathBuf::from("a"), vec![1], vec![]),
+                (PathBuf::from("b"), vec![], vec![2]),
+            ]
+        );
+
+        let verification = verify_torrent(&info, dir.path()).unwrap();
+        assert_eq!(verification.valid_pieces, 0);
+        assert_eq!(verification.files[0].missing, vec![0, 1]);
+        assert_eq!(verification.files[1].missing, vec![1, 2, 3]);
+    }
+}
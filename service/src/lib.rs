@@ -5,10 +5,9 @@ use futures::{
 };
 use http_body::Body;
 use hyper::Client;
-use log::{debug, error};
+use log::{debug, error, warn};
 use percent_encoding::{percent_encode, percent_encode_byte, NON_ALPHANUMERIC};
 use serde::{Deserialize, Serialize};
-use sha1::{Digest, Sha1};
 use std::{
     collections::HashMap,
     convert::TryInto,
@@ -35,19 +34,29 @@ use uuid::Uuid;
 
 pub mod announce;
 pub mod app;
+mod bandwidth;
+pub mod creator;
+mod disk_io;
 mod errors;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod inspect;
 mod messages;
 mod parser;
 mod storage;
 pub mod types;
 
-pub use errors::RsbtError;
-pub use storage::{TorrentPiece, TorrentStorage};
+pub use bandwidth::Bandwidth;
+pub use disk_io::{DiskIo, DiskJobPriority, DEFAULT_DISK_JOB_CAPACITY};
+pub use errors::{BencodeError, RsbtError, TorrentInfoError};
+pub use storage::{ReadCache, TorrentPiece, TorrentStorage, TorrentStorageLocation};
 
 pub(crate) use flat_storage::{bit_by_index, index_in_bitarray};
 
 pub(crate) const SHA1_SIZE: usize = 20;
 
+pub(crate) const SHA256_SIZE: usize = 32;
+
 pub(crate) const BLOCK_SIZE: usize = 1 << 14;
 
 pub(crate) const PEER_ID: [u8; 20] = *b"-rs0001-zzzzxxxxyyyy";
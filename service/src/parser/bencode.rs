@@ -31,16 +31,21 @@ named!(
     })
 );
 
+/// Maximum nesting of lists and dictionaries, deeper data is rejected to bound recursion.
+const MAX_BENCODE_DEPTH: usize = 64;
+
+named!(string_length<i64>, verify!(integer, |len: &i64| *len >= 0));
+
 named!(
     bencode_string<BencodeValue>,
-    do_parse!(len: integer >> char!(':') >> s: take!(len) >> (BencodeValue::String(s.into())))
+    do_parse!(
+        len: string_length >> char!(':') >> s: take!(len) >> (BencodeValue::String(s.into()))
+    )
 );
 
 named!(
-    bencode_string_s<String>,
-    do_parse!(
-        len: integer >> char!(':') >> s: map_res!(take!(len), std::str::from_utf8) >> (s.into())
-    )
+    bencode_key<Vec<u8>>,
+    do_parse!(len: string_length >> char!(':') >> s: take!(len) >> (s.into()))
 );
 
 named!(
@@ -48,33 +53,41 @@ named!(
     delimited!(char!('i'), map!(integer, BencodeValue::Integer), char!('e'))
 );
 
-named!(
-    bencode_list<BencodeValue>,
+named_args!(
+    bencode_list(depth: usize)<BencodeValue>,
     delimited!(
         char!('l'),
-        map!(many0!(parser_bencode), |x: Vec<BencodeBlob>| {
+        map!(many0!(call!(parser_bencode, depth)), |x: Vec<BencodeBlob>| {
             BencodeValue::List(x)
         }),
         char!('e')
     )
 );
 
-named!(
-    bencode_dictionary<BencodeValue>,
+named_args!(
+    bencode_dictionary(depth: usize)<BencodeValue>,
     delimited!(
         char!('d'),
         map!(
-            many0!(tuple!(bencode_string_s, parser_bencode)),
+            many0!(tuple!(bencode_key, call!(parser_bencode, depth))),
             BencodeValue::Dictionary
         ),
         char!('e')
     )
 );
 
-named!(
-    parser_bencode<BencodeBlob>,
+named_args!(
+    bencode_container(depth: usize)<BencodeValue>,
+    preceded!(
+        verify!(value!(depth), |depth: &usize| *depth < MAX_BENCODE_DEPTH),
+        alt!(call!(bencode_list, depth + 1) | call!(bencode_dictionary, depth + 1))
+    )
+);
+
+named_args!(
+    parser_bencode(depth: usize)<BencodeBlob>,
     recognize_map!(
-        alt!(bencode_string | bencode_integer | bencode_list | bencode_dictionary),
+        alt!(bencode_string | bencode_integer | call!(bencode_container, depth)),
         |i: &[u8], r| BencodeBlob {
             source: i.to_vec(),
             value: r
@@ -83,7 +96,9 @@ named!(
 );
 
 pub fn parse_bencode(bytes: &[u8]) -> Result<BencodeBlob, RsbtError> {
-    parser_bencode(bytes).map(|x| x.1).map_err(RsbtError::from)
+    parser_bencode(bytes, 0)
+        .map(|x| x.1)
+        .map_err(RsbtError::from)
 }
 
 #[cfg(test)]
@@ -121,7 +136,7 @@ mod tests {
     #[test]
     fn check_bencode_list() {
         assert_eq!(
-            bencode_list(b"l5:UTF-8i3ee"),
+            bencode_list(b"l5:UTF-8i3ee", 0),
             Ok((
                 &vec![][..],
                 BencodeValue::List(vec![
@@ -135,7 +150,7 @@ mod tests {
     #[test]
     fn check_bencode_dictionary() {
         assert_eq!(
-            bencode_dictionary(b"d3:cow3:moo4:spam4:eggse"),
+            bencode_dictionary(b"d3:cow3:moo4:spam4:eggse", 0),
             Ok((
                 &vec![][..],
                 BencodeValue::Dictionary(
@@ -156,7 +171,7 @@ mod tests {
         );
 
         assert_eq!(
-            bencode_dictionary(b"d4:spaml1:a1:bee"),
+            bencode_dictionary(b"d4:spaml1:a1:bee", 0),
             Ok((
                 &vec![][..],
                 BencodeValue::Dictionary(
@@ -177,8 +192,25 @@ mod tests {
         );
 
         assert_eq!(
-            bencode_dictionary(b"de"),
+            bencode_dictionary(b"de", 0),
             Ok((&vec![][..], BencodeValue::Dictionary(vec![])))
         );
     }
+
+    #[test]
+    fn check_bencode_limits() {
+        assert!(parse_bencode(b"-1:").is_err());
+        assert!(parse_bencode(b"99999999999999999999:").is_err());
+        assert!(parse_bencode(b"5:abc").is_err());
+        assert!(parse_bencode(b"i1").is_err());
+
+        let nested = |depth| {
+            let mut data = vec![b'l'; depth];
+            data.extend(vec![b'e'; depth]);
+            data
+        };
+        assert!(parse_bencode(&nested(MAX_BENCODE_DEPTH)).is_ok());
+        assert!(parse_bencode(&nested(MAX_BENCODE_DEPTH + 1)).is_err());
+        assert!(parse_bencode(&nested(100_000)).is_err());
+    }
 }
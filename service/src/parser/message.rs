@@ -1,6 +1,41 @@
-use crate::types::message::Message;
+use crate::types::message::{HashRequest, Message};
 use nom::number::streaming::*;
 use nom::*;
+use std::convert::TryInto;
+
+named!(
+    merkle_hash<[u8; 32]>,
+    map_res!(take!(32), |x: &[u8]| x.try_into())
+);
+
+/// Splits data into hashes, data must be a multiple of hash size.
+fn merkle_hashes(data: &[u8]) -> Vec<[u8; 32]> {
+    data.chunks_exact(32)
+        .map(|x| {
+            let mut hash = [0; 32];
+            hash.copy_from_slice(x);
+            hash
+        })
+        .collect()
+}
+
+named!(
+    hash_request<HashRequest>,
+    do_parse!(
+        pieces_root: merkle_hash
+            >> base_layer: be_u32
+            >> index: be_u32
+            >> length: be_u32
+            >> proof_layers: be_u32
+            >> (HashRequest {
+                pieces_root,
+                base_layer,
+                index,
+                length,
+                proof_layers
+            })
+    )
+);
 
 named!(
     pub parser_message<Message>,
@@ -14,7 +49,7 @@ named!(
                     2 => value!(Message::Interested) |
                     3 => value!(Message::NotInterested)
                 ) >> (m)) |
-                _ => do_parse!(id: be_u8 >> m: switch!(value!(id),
+                _ => do_parse!(id: be_u8 >> m: map_opt!(switch!(value!(id),
                     4 => cond!(len == 5, map!(be_u32, |x| Message::Have { piece_index: x})) |
                     5 => map!(take!(len - 1), |x| Some(Message::Bitfield(x.into()))) |
                     6 => cond!(len == 13, do_parse!(index: be_u32 >> begin: be_u32 >> length: be_u32 >> (Message::Request {
@@ -26,8 +61,13 @@ named!(
                     8 => cond!(len == 13, do_parse!(index: be_u32 >> begin: be_u32 >> length: be_u32 >> (Message::Cancel {
                         index, begin, length
                     }))) |
-                    9 => cond!(len == 3, map!(be_u16, |x| Message::Port(x)))
-                ) >> (m.unwrap()))
+                    9 => cond!(len == 3, map!(be_u16, |x| Message::Port(x))) |
+                    21 => cond!(len == 49, map!(hash_request, Message::HashRequest)) |
+                    22 => cond!(len >= 49 && (len - 49) % 32 == 0, do_parse!(request: hash_request >> hashes: map!(take!(len - 49), merkle_hashes) >> (Message::Hashes {
+                        request, hashes
+                    }))) |
+                    23 => cond!(len == 49, map!(hash_request, Message::HashReject))
+                ), |m: Option<Message>| m) >> (m))
             )
             >> (m)
     )
@@ -119,4 +159,53 @@ mod tests {
     fn message_port() {
         parse(&[0, 0, 0, 3, 9, 0, 101], Message::Port(101));
     }
+
+    #[test]
+    fn message_hashes() {
+        let mut buf = vec![0, 0, 0, 113, 22];
+        buf.extend_from_slice(&[7; 32]);
+        buf.extend_from_slice(&[0, 0, 0, 2, 0, 0, 0, 4, 0, 0, 0, 2, 0, 0, 0, 1]);
+        buf.extend_from_slice(&[1; 32]);
+        buf.extend_from_slice(&[2; 32]);
+        let request = HashRequest {
+            pieces_root: [7; 32],
+            base_layer: 2,
+            index: 4,
+            length: 2,
+            proof_layers: 1,
+        };
+        parse(
+            &buf,
+            Message::Hashes {
+                request: request.clone(),
+                hashes: vec![[1; 32], [2; 32]],
+            },
+        );
+
+        buf.truncate(53);
+        buf[3] = 49;
+        buf[4] = 21;
+        parse(&buf, Message::HashRequest(request.clone()));
+        buf[4] = 23;
+        parse(&buf, Message::HashReject(request));
+    }
+
+    #[test]
+    fn message_malformed() {
+        assert!(!parser_message(&[0, 0, 0, 2, 4, 0])
+            .unwrap_err()
+            .is_incomplete());
+        assert!(!parser_message(&[0, 0, 0, 3, 6, 0, 0])
+            .unwrap_err()
+            .is_incomplete());
+        assert!(!parser_message(&[0, 0, 0, 1, 99])
+            .unwrap_err()
+            .is_incomplete());
+        assert!(!parser_message(&[0, 0, 0, 50, 22])
+            .unwrap_err()
+            .is_incomplete());
+        assert!(parser_message(&[0xff, 0xff, 0xff, 0xf1, 22])
+            .unwrap_err()
+            .is_incomplete());
+    }
 }
@@ -0,0 +1,114 @@
+use super::*;
+use crate::types::info::TorrentInfoFileAttributes;
+use flat_storage::FlatStorageFile;
+use std::fs;
+
+/// Applies attributes of completed torrent files: sets executable bit and creates symlinks.
+///
+/// Files which are not on disk (e.g. skipped) are ignored, existing symlinks are kept, so
+/// attributes can be applied again.
+pub(crate) fn apply_attributes(
+    download_path: &Path,
+    files: &[FlatStorageFile],
+    attributes: &[TorrentInfoFileAttributes],
+) -> Result<(), std::io::Error> {
+    for (file, attributes) in files.iter().zip(attributes) {
+        let file_path = download_path.join(&file.path);
+        if let Some(target) = &attributes.symlink {
+            if fs::symlink_metadata(&file_path).is_err() {
+                debug!("creating symlink {:?} to {:?}", file_path, target);
+                if let Some(parent) = file_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                symlink(target, &file_path)?;
+            }
+        } else if attributes.executable && !file.pad && file_path.is_file() {
+            debug!("setting executable {:?}", file_path);
+            set_executable(&file_path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn symlink(target: &Path, path: &Path) -> Result<(), std::io::Error> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+pub(crate) fn symlink(target: &Path, path: &Path) -> Result<(), std::io::Error> {
+    std::os::windows::fs::symlink_file(target, path)
+}
+
+/// Allows to execute file for everyone who can read it.
+#[cfg(unix)]
+fn set_executable(path: &Path) -> Result<(), std::io::Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    permissions.set_mode(mode | (mode & 0o444) >> 2);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(windows)]
+fn set_executable(_: &Path) -> Result<(), std::io::Error> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn check_apply_attributes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |path: &str, pad| FlatStorageFile {
+            path: path.into(),
+            length: 0,
+            pad,
+        };
+        let files = vec![
+            file("bin/run", false),
+            file("bin/link", false),
+            file(".pad/0", true),
+        ];
+        let attributes = vec![
+            TorrentInfoFileAttributes {
+                executable: true,
+                ..Default::default()
+            },
+            TorrentInfoFileAttributes {
+                symlink: Some("run".into()),
+                ..Default::default()
+            },
+            TorrentInfoFileAttributes {
+                executable: true,
+                ..Default::default()
+            },
+        ];
+        fs::create_dir(dir.path().join("bin")).unwrap();
+        fs::write(dir.path().join("bin/run"), b"#!/bin/sh").unwrap();
+        fs::set_permissions(
+            dir.path().join("bin/run"),
+            fs::Permissions::from_mode(0o640),
+        )
+        .unwrap();
+
+        apply_attributes(dir.path(), &files, &attributes).unwrap();
+        apply_attributes(dir.path(), &files, &attributes).unwrap();
+
+        let mode = fs::metadata(dir.path().join("bin/run"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o750);
+        assert_eq!(
+            fs::read_link(dir.path().join("bin/link")).unwrap(),
+            PathBuf::from("run")
+        );
+        assert_eq!(fs::read(dir.path().join("bin/link")).unwrap(), b"#!/bin/sh");
+        assert!(!dir.path().join(".pad").exists());
+    }
+}
@@ -0,0 +1,295 @@
+use super::*;
+use attributes::symlink;
+use flat_storage::FlatStorageFile;
+use std::{
+    ffi::OsString,
+    fs,
+    io::{Read, Write},
+    path::Component,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Suffix of files which are not downloaded completely.
+const PART_SUFFIX: &str = ".part";
+
+/// Suffix of file copy while it is moved to other file system.
+const MOVING_SUFFIX: &str = ".moving";
+
+/// Size of buffer for file copy.
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Where torrent files are kept while downloading, and where they are moved when completed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TorrentStorageLocation {
+    /// Directory of completed files
+    pub complete: PathBuf,
+    /// Directory of files in progress
+    pub incomplete: PathBuf,
+    /// Files in progress are named with `.part` suffix
+    pub part_suffix: bool,
+}
+
+impl TorrentStorageLocation {
+    /// Location from configuration, completed files are saved into `category` directory
+    /// of `save_to`, or of download path if torrent has no own save path.
+    pub fn new(
+        properties: &Properties,
+        save_to: Option<&Path>,
+        category: Option<&str>,
+    ) -> Result<Self, RsbtError> {
+        let save_to = save_to.unwrap_or(&properties.save_to);
+        let complete = match category {
+            Some(category) => {
+                let mut components = Path::new(category).components();
+                match (components.next(), components.next()) {
+                    (Some(Component::Normal(_)), None) => save_to.join(category),
+                    _ => return Err(RsbtError::InvalidCategory(category.into())),
+                }
+            }
+            None => save_to.to_path_buf(),
+        };
+        Ok(Self {
+            incomplete: properties
+                .incomplete_dir
+                .clone()
+                .unwrap_or_else(|| complete.clone()),
+            complete,
+            part_suffix: properties.part_suffix,
+        })
+    }
+
+    /// Location where files are never moved.
+    pub fn direct<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            complete: path.as_ref().to_path_buf(),
+            incomplete: path.as_ref().to_path_buf(),
+            part_suffix: false,
+        }
+    }
+
+    /// Checks if files must be moved on completion.
+    pub fn needs_move(&self) -> bool {
+        self.part_suffix || self.complete != self.incomplete
+    }
+
+    /// Checks if files are found at complete location already, e.g. when torrent is added on
+    /// top of downloaded files.
+    pub(crate) fn files_moved(&self, files: &[FlatStorageFile]) -> bool {
+        if !self.needs_move() {
+            return false;
+        }
+        let (download_path, files) = self.storage_files(files, true);
+        files
+            .iter()
+            .any(|file| download_path.join(&file.path).is_file())
+    }
+
+    /// Download path and files as they are stored, before or after move.
+    pub(crate) fn storage_files(
+        &self,
+        files: &[FlatStorageFile],
+        moved: bool,
+    ) -> (PathBuf, Vec<FlatStorageFile>) {
+        if moved || !self.part_suffix {
+            let download_path = if moved {
+                &self.complete
+            } else {
+                &self.incomplete
+            };
+            return (download_path.clone(), files.to_vec());
+        }
+        let files = files
+            .iter()
+            .map(|file| FlatStorageFile {
+                path: with_suffix(&file.path, PART_SUFFIX),
+                length: file.length,
+                pad: file.pad,
+            })
+            .collect();
+        (self.incomplete.clone(), files)
+    }
+
+    /// Moves files in progress to complete location, see [`relocate_files`].
+    pub(crate) fn move_files(&self, files: &[FlatStorageFile]) -> Result<(), std::io::Error> {
+        let (incomplete_path, incomplete_files) = self.storage_files(files, false);
+        relocate_files(
+            &incomplete_path,
+            &self.complete,
+            &incomplete_files,
+            files,
+            &AtomicU64::new(0),
+        )
+    }
+}
+
+/// Moves `files` stored in `from` directory to `to` directory, with names from `to_files`.
+///
+/// Every file is renamed, or copied and renamed if it is on other file system, so target
+/// file appears only with full content. Files which are not found are treated as already
+/// moved, so interrupted move is finished by calling it again. Count of moved bytes is
+/// added to `moved`.
+pub(crate) fn relocate_files(
+    from: &Path,
+    to: &Path,
+    files: &[FlatStorageFile],
+    to_files: &[FlatStorageFile],
+    moved: &AtomicU64,
+) -> Result<(), std::io::Error> {
+    for (file, to_file) in files.iter().zip(to_files) {
+        let file_from = from.join(&file.path);
+        let file_to = to.join(&to_file.path);
+        if file_from == file_to {
+            continue;
+        }
+        match fs::symlink_metadata(&file_from) {
+            Ok(metadata) if !metadata.is_dir() => (),
+            _ => {
+                debug!("file {:?} is missing or already moved", file_from);
+                continue;
+            }
+        }
+        debug!("moving {:?} to {:?}", file_from, file_to);
+        move_file(&file_from, &file_to, moved)?;
+        remove_empty_dirs(&file_from, from);
+    }
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path.as_os_str());
+    path.push(suffix);
+    path.into()
+}
+
+fn move_file(from: &Path, to: &Path, moved: &AtomicU64) -> Result<(), std::io::Error> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(from, to) {
+        Ok(()) => {
+            moved.fetch_add(fs::symlink_metadata(to)?.len(), Ordering::Relaxed);
+        }
+        Err(err) if fs::symlink_metadata(from)?.file_type().is_symlink() => {
+            debug!("cannot rename {:?}, creating symlink again: {}", from, err);
+            symlink(&fs::read_link(from)?, to)?;
+            fs::remove_file(from)?;
+        }
+        Err(err) => {
+            debug!("cannot rename {:?}, copying: {}", from, err);
+            let moving = with_suffix(to, MOVING_SUFFIX);
+            copy_file(from, &moving, moved)?;
+            fs::rename(&moving, to)?;
+            fs::remove_file(from)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies file and syncs copy to disk, counting copied bytes in `copied`.
+fn copy_file(from: &Path, to: &Path, copied: &AtomicU64) -> Result<(), std::io::Error> {
+    let mut reader = fs::File::open(from)?;
+    let mut writer = fs::File::create(to)?;
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..count])?;
+        copied.fetch_add(count as u64, Ordering::Relaxed);
+    }
+    writer.set_permissions(reader.metadata()?.permissions())?;
+    writer.sync_all()
+}
+
+/// Removes directories of moved file, which became empty, up to `root`.
+fn remove_empty_dirs(file: &Path, root: &Path) {
+    for dir in file.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Settings;
+
+    #[test]
+    fn check_move_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let location = TorrentStorageLocation {
+            complete: dir.path().join("complete").join("movies"),
+            incomplete: dir.path().join("incomplete"),
+            part_suffix: true,
+        };
+        let files = vec![
+            FlatStorageFile {
+                path: PathBuf::from("torrent").join("a"),
+                length: 1,
+                pad: false,
+            },
+            FlatStorageFile {
+                path: PathBuf::from("torrent").join("b"),
+                length: 2,
+                pad: false,
+            },
+        ];
+
+        let (download_path, storage_files) = location.storage_files(&files, false);
+        assert_eq!(download_path, location.incomplete);
+        assert_eq!(
+            storage_files[0].path,
+            PathBuf::from("torrent").join("a.part")
+        );
+        fs::create_dir_all(download_path.join("torrent")).unwrap();
+        for file in &storage_files {
+            fs::write(download_path.join(&file.path), vec![1; file.length]).unwrap();
+        }
+        assert!(!location.files_moved(&files));
+
+        fs::remove_file(location.incomplete.join(&storage_files[1].path)).unwrap();
+        fs::create_dir_all(location.complete.join("torrent")).unwrap();
+        fs::write(location.complete.join(&files[1].path), vec![1; 2]).unwrap();
+        assert!(location.files_moved(&files));
+
+        location.move_files(&files).unwrap();
+        location.move_files(&files).unwrap();
+
+        let moved = AtomicU64::new(0);
+        let other = dir.path().join("other");
+        relocate_files(&location.complete, &other, &files, &files, &moved).unwrap();
+        assert_eq!(moved.load(Ordering::Relaxed), 3);
+        assert!(!location.complete.join("torrent").exists());
+        relocate_files(&other, &location.complete, &files, &files, &moved).unwrap();
+
+        assert_eq!(location.storage_files(&files, true).0, location.complete);
+        for file in &files {
+            assert_eq!(
+                fs::read(location.complete.join(&file.path)).unwrap(),
+                vec![1; file.length]
+            );
+        }
+        assert!(!location.incomplete.join("torrent").exists());
+        assert!(location.incomplete.exists());
+    }
+
+    #[test]
+    fn check_category() {
+        let properties = Properties::from((Settings::default(), PathBuf::from("config")));
+        let location = TorrentStorageLocation::new(&properties, None, Some("movies")).unwrap();
+        assert_eq!(location.complete, properties.save_to.join("movies"));
+        assert!(!location.needs_move());
+
+        let save_to = PathBuf::from("media");
+        let location =
+            TorrentStorageLocation::new(&properties, Some(&save_to), Some("movies")).unwrap();
+        assert_eq!(location.complete, save_to.join("movies"));
+        assert_eq!(location.incomplete, location.complete);
+
+        assert!(TorrentStorageLocation::new(&properties, None, Some("../movies")).is_err());
+        assert!(TorrentStorageLocation::new(&properties, None, Some("/movies")).is_err());
+        assert!(TorrentStorageLocation::new(&properties, None, Some("")).is_err());
+    }
+}
@@ -1,34 +1,51 @@
 use super::*;
-use crate::types::Properties;
+use crate::types::{info::TorrentInfo, Preallocation, Properties, StorageBackend};
 use app::{
     download_torrent::{DownloadTorrentEvent, DownloadTorrentEventQueryPiece},
-    RequestResponse, RsbtFileView, TorrentProcess,
+    FilePriority, RequestResponse, RsbtFileView, TorrentProcess,
 };
 use byteorder::{BigEndian, ReadBytesExt};
 use bytes::Bytes;
 use failure::ResultExt;
-use flat_storage::FlatStorage;
-use flat_storage_mmap::{FileInfo, MmapFlatStorage};
-use futures::future::BoxFuture;
-use std::{
-    io::{Read, Write},
-    thread,
+use flat_storage::FileInfo;
+use flat_storage::{FlatStorage, FlatStorageFile};
+use flat_storage_file::FileFlatStorage;
+use flat_storage_memory::MemoryFlatStorage;
+use flat_storage_mmap::MmapFlatStorage;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use flat_storage_uring::UringFlatStorage;
+use futures::{
+    future::{self, BoxFuture, Either},
+    stream,
 };
+use std::io::{Read, Write};
 use std::{
     pin::Pin,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     task::{Context, Poll, Waker},
 };
 use tokio::{
     fs::{self, File},
-    runtime::Builder,
     sync::{oneshot, watch},
-    task::spawn_blocking,
 };
 
+mod attributes;
+mod location;
+mod read_cache;
+mod state;
+
+use attributes::apply_attributes;
+use location::relocate_files;
+pub use location::TorrentStorageLocation;
+pub use read_cache::ReadCache;
+pub use state::TorrentStorageState;
+
 #[derive(Debug)]
 pub struct TorrentStorage {
-    pub handle: std::thread::JoinHandle<Result<(), RsbtError>>,
+    pub handle: JoinHandle<Result<(), RsbtError>>,
     torrent_process: Arc<TorrentProcess>,
     sender: Sender<TorrentStorageMessage>,
     pub receiver: watch::Receiver<TorrentStorageState>,
@@ -39,11 +56,22 @@ enum TorrentStorageMessage {
         index: usize,
         sender: oneshot::Sender<Result<Option<TorrentPiece>, RsbtError>>,
     },
-    SavePiece {
+    LoadBlock {
+        index: usize,
+        begin: usize,
+        length: usize,
+        sender: oneshot::Sender<Result<Option<Vec<u8>>, RsbtError>>,
+    },
+    SaveBlock {
         index: usize,
-        data: Vec<u8>,
+        begin: usize,
+        block: Vec<u8>,
         sender: oneshot::Sender<Result<(), RsbtError>>,
     },
+    CompletePiece {
+        index: usize,
+        sender: oneshot::Sender<Result<TorrentPiece, RsbtError>>,
+    },
     Delete {
         files: bool,
         sender: oneshot::Sender<Result<(), RsbtError>>,
@@ -53,67 +81,34 @@ enum TorrentStorageMessage {
         file_id: usize,
         sender: oneshot::Sender<Result<FileInfo, RsbtError>>,
     },
+    Priorities {
+        priorities: Vec<FilePriority>,
+        sender: oneshot::Sender<Result<(), RsbtError>>,
+    },
+    Recheck(oneshot::Sender<Result<(), RsbtError>>),
+    RecheckProgress(u32),
+    RecheckDone(Result<Vec<u8>, RsbtError>),
+    Move {
+        location: TorrentStorageLocation,
+        sender: oneshot::Sender<Result<(), RsbtError>>,
+    },
+    MoveProgress(u64),
+    MoveDone(Result<(), RsbtError>),
+    /// Saves state and notifies watchers, if state was changed since last save
+    SaveState,
 }
 
-#[derive(Clone, Debug)]
-pub struct TorrentStorageState {
-    pub downloaded: Vec<u8>,
-    pub bytes_write: u64,
-    pub bytes_read: u64,
-    pub pieces_left: u32,
-}
+/// How often recheck progress is reported, in pieces.
+const RECHECK_PROGRESS_STEP: u32 = 16;
 
-const TORRENT_STORAGE_FORMAT_VERSION: u8 = 0;
+/// How many pieces are read into cache ahead of sequential reads.
+const READ_AHEAD_PIECES: usize = 2;
 
-impl TorrentStorageState {
-    fn from_reader(mut rdr: impl Read) -> Result<Self, RsbtError> {
-        let version = rdr.read_u8()?;
-        if version != TORRENT_STORAGE_FORMAT_VERSION {
-            return Err(RsbtError::StorageVersion(version));
-        }
-        let bytes_write: u64 = rdr.read_u64::<BigEndian>()?;
-        let bytes_read = rdr.read_u64::<BigEndian>()?;
-        let pieces_left = rdr.read_u32::<BigEndian>()?;
-        let mut downloaded = vec![];
-        rdr.read_to_end(&mut downloaded)?;
-        Ok(Self {
-            downloaded,
-            bytes_write,
-            bytes_read,
-            pieces_left,
-        })
-    }
+/// How often changed state is saved and broadcast while pieces are written and read.
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(5);
 
-    async fn write_to_file(&self, mut f: File) -> Result<(), RsbtError> {
-        f.write_u8(TORRENT_STORAGE_FORMAT_VERSION).await?;
-        f.write_u64(self.bytes_write.try_into()?).await?;
-        f.write_u64(self.bytes_read.try_into()?).await?;
-        f.write_u32(self.pieces_left.try_into()?).await?;
-        f.write_all(&self.downloaded).await?;
-        Ok(())
-    }
-
-    async fn save<P: AsRef<Path>>(&self, state_file: P) -> Result<(), RsbtError> {
-        let state_file_ref = &state_file.as_ref();
-        let f = File::create(&state_file).await.with_context(|err| {
-            format!(
-                "cannot create state file {}: {}",
-                state_file_ref.to_string_lossy(),
-                err
-            )
-        })?;
-        self.write_to_file(f)
-            .await
-            .with_context(|err| {
-                format!(
-                    "cannot write state file {}: {}",
-                    state_file.as_ref().to_string_lossy(),
-                    err
-                )
-            })
-            .map_err(|x| x.into())
-    }
-}
+/// How often progress is reported while files are moved to other location.
+const MOVE_PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
 
 #[derive(Debug)]
 pub struct RsbtFileDownloadStream {
@@ -142,6 +137,22 @@ impl std::fmt::Debug for RsbtFileDownloadState {
     }
 }
 
+impl RsbtFileDownloadStream {
+    /// Limits stream to bytes `start..end` of file.
+    ///
+    /// Must be called before stream is polled, range is clamped to file size.
+    pub fn with_range(mut self, start: usize, end: usize) -> Self {
+        let end = end.min(self.size);
+        let start = start.min(end);
+        let piece_length = self.torrent_process.info.piece_length;
+        let offset = self.piece * piece_length + self.piece_offset + start;
+        self.piece = offset / piece_length;
+        self.piece_offset = offset % piece_length;
+        self.left = end - start;
+        self
+    }
+}
+
 impl Stream for RsbtFileDownloadStream {
     type Item = Result<Bytes, RsbtError>;
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
@@ -255,6 +266,11 @@ async fn cleanup_storage_state<P: AsRef<Path>>(
         fs::remove_file(&torrent_storage_state_file).await?;
     }
 
+    let temp_state_file = state::temp_state_file(&torrent_storage_state_file);
+    if temp_state_file.is_file() {
+        fs::remove_file(&temp_state_file).await?;
+    }
+
     Ok(())
 }
 
@@ -262,6 +278,7 @@ async fn prepare_storage_state<P: AsRef<Path>>(
     properties: Arc<Properties>,
     torrent_name: P,
     torrent_process: Arc<TorrentProcess>,
+    location: &TorrentStorageLocation,
 ) -> Result<(PathBuf, TorrentStorageState), RsbtError> {
     let storage_torrent_file = properties.storage.join(torrent_name.as_ref());
 
@@ -291,16 +308,31 @@ async fn prepare_storage_state<P: AsRef<Path>>(
                     err
                 )
             })?;
-        let state = TorrentStorageState::from_reader(data.as_slice())?;
+        let mut state = TorrentStorageState::from_reader(data.as_slice())?;
         debug!("loaded state: {:?}", state);
+        let (download_path, files) =
+            location.storage_files(&torrent_process.info.files, state.moved);
+        if state.files_modified(&download_path, &files) {
+            debug!("torrent files were modified, recheck is required");
+            state.checking = Some(0);
+        }
         state
     } else {
         debug!("creating new state in: {:?}", torrent_storage_state_file);
+        let moved = location.files_moved(&torrent_process.info.files);
+        let existing_files = moved || {
+            let (download_path, files) = location.storage_files(&torrent_process.info.files, false);
+            files
+                .iter()
+                .any(|file| download_path.join(&file.path).is_file())
+        };
+        if existing_files {
+            debug!("torrent added on top of existing files, recheck is required");
+        }
         let state = TorrentStorageState {
-            downloaded: vec![],
-            bytes_write: 0,
-            bytes_read: 0,
-            pieces_left: torrent_process.info.pieces.len() as u32,
+            checking: if existing_files { Some(0) } else { None },
+            moved,
+            ..TorrentStorageState::new(torrent_process.info.pieces.len())
         };
         state.save(&torrent_storage_state_file).await?;
         state
@@ -313,31 +345,154 @@ impl TorrentStorage {
         properties: Arc<Properties>,
         torrent_name: P,
         torrent_process: Arc<TorrentProcess>,
+        priorities: &[FilePriority],
+        backend: StorageBackend,
+        preallocation: Preallocation,
+        location: TorrentStorageLocation,
     ) -> Result<Self, RsbtError> {
-        let (state_file, state) = prepare_storage_state(
+        debug!("creating {:?} storage", backend);
+        let info_process = torrent_process.clone();
+        match backend {
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            StorageBackend::Uring if flat_storage_uring::is_supported() => {
+                Self::with_storage(
+                    properties,
+                    torrent_name,
+                    torrent_process,
+                    priorities,
+                    location,
+                    move |state, download_path, files| {
+                        let info = &info_process.info;
+                        Ok(UringFlatStorage::create(
+                            download_path,
+                            info.pieces.len(),
+                            info.piece_length,
+                            files,
+                            &state.downloaded,
+                            &state.skipped_files(info),
+                            preallocation,
+                        )?)
+                    },
+                )
+                .await
+            }
+            StorageBackend::Mmap | StorageBackend::Uring => {
+                if backend == StorageBackend::Uring {
+                    warn!("io_uring is not supported, mmap storage is used");
+                }
+                Self::with_storage(
+                    properties,
+                    torrent_name,
+                    torrent_process,
+                    priorities,
+                    location,
+                    move |state, download_path, files| {
+                        let info = &info_process.info;
+                        Ok(MmapFlatStorage::create(
+                            download_path,
+                            info.pieces.len(),
+                            info.piece_length,
+                            files,
+                            &state.downloaded,
+                            &state.skipped_files(info),
+                            preallocation,
+                        )?)
+                    },
+                )
+                .await
+            }
+            StorageBackend::File => {
+                let file_handles = properties.file_handles.clone();
+                Self::with_storage(
+                    properties,
+                    torrent_name,
+                    torrent_process,
+                    priorities,
+                    location,
+                    move |state, download_path, files| {
+                        let info = &info_process.info;
+                        Ok(FileFlatStorage::create(
+                            download_path,
+                            info.pieces.len(),
+                            info.piece_length,
+                            files,
+                            &state.downloaded,
+                            &state.skipped_files(info),
+                            preallocation,
+                            file_handles.clone(),
+                        )?)
+                    },
+                )
+                .await
+            }
+            StorageBackend::Memory => {
+                Self::with_storage(
+                    properties,
+                    torrent_name,
+                    torrent_process,
+                    priorities,
+                    TorrentStorageLocation::direct(&location.complete),
+                    move |state, _, files| {
+                        let info = &info_process.info;
+                        state.clear_pieces(info.pieces.len());
+                        Ok(MemoryFlatStorage::create(info.piece_length, files))
+                    },
+                )
+                .await
+            }
+        }
+    }
+
+    /// Creates storage over any flat storage implementation.
+    ///
+    /// Flat storage is created with `create` in disk I/O pool from loaded state, which it may
+    /// adjust (e.g. forget downloaded pieces which are not stored anymore), download path and
+    /// files at current `location`. It is created again when completed files are moved.
+    pub async fn with_storage<P, S, F>(
+        properties: Arc<Properties>,
+        torrent_name: P,
+        torrent_process: Arc<TorrentProcess>,
+        priorities: &[FilePriority],
+        location: TorrentStorageLocation,
+        create: F,
+    ) -> Result<Self, RsbtError>
+    where
+        P: AsRef<Path>,
+        S: FlatStorage + Send + Sync + 'static,
+        F: Fn(&mut TorrentStorageState, &Path, Vec<FlatStorageFile>) -> Result<S, RsbtError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let (state_file, mut state) = prepare_storage_state(
             properties.clone(),
             torrent_name.as_ref(),
             torrent_process.clone(),
+            &location,
         )
         .await?;
+        if !priorities.is_empty() {
+            state.priorities = priorities.to_vec();
+        }
         let (sender, channel_receiver) = mpsc::channel(DEFAULT_CHANNEL_BUFFER);
+        let loop_sender = sender.clone();
 
         let (watch_sender, receiver) = watch::channel(state.clone());
 
-        let thread_torrent_process = torrent_process.clone();
-
-        let thread_torrent_name = PathBuf::from(torrent_name.as_ref());
+        let storage_loop = StorageLoop {
+            properties,
+            torrent_process: torrent_process.clone(),
+            torrent_name: PathBuf::from(torrent_name.as_ref()),
+            location,
+            state,
+            state_file,
+            loop_sender,
+            channel_receiver,
+            watch_sender,
+        };
 
-        let handle = thread::spawn(move || {
-            if let Err(err) = torrent_storage_message_loop(
-                properties,
-                thread_torrent_process,
-                thread_torrent_name,
-                state,
-                state_file,
-                channel_receiver,
-                watch_sender,
-            ) {
+        let handle = tokio::spawn(async move {
+            if let Err(err) = torrent_storage_message_loop(storage_loop, create).await {
                 error!("torrent storage loop failure: {}", err);
                 Err(err)
             } else {
@@ -364,25 +519,77 @@ impl TorrentStorage {
         receiver.await?
     }
 
-    pub async fn save(&self, index: usize, data: Vec<u8>) -> Result<(), RsbtError> {
-        self.message(|sender| TorrentStorageMessage::SavePiece {
+    /// Writes downloaded block of piece, `begin` is offset inside of piece.
+    pub async fn save_block(
+        &self,
+        index: usize,
+        begin: usize,
+        block: Vec<u8>,
+    ) -> Result<(), RsbtError> {
+        self.message(|sender| TorrentStorageMessage::SaveBlock {
             index,
-            data,
+            begin,
+            block,
             sender,
         })
         .await
     }
 
+    /// Verifies piece with all blocks written and marks it as downloaded.
+    ///
+    /// Returns piece data, or `PieceHashMismatch` error if hash does not match. In this case
+    /// blocks of piece are forgotten and piece must be downloaded again.
+    pub async fn complete_piece(&self, index: usize) -> Result<TorrentPiece, RsbtError> {
+        self.message(|sender| TorrentStorageMessage::CompletePiece { index, sender })
+            .await
+    }
+
     pub async fn load(&self, index: usize) -> Result<Option<TorrentPiece>, RsbtError> {
         self.message(|sender| TorrentStorageMessage::LoadPiece { index, sender })
             .await
     }
 
+    /// Reads part of piece, `begin` is offset inside of piece.
+    pub async fn load_block(
+        &self,
+        index: usize,
+        begin: usize,
+        length: usize,
+    ) -> Result<Option<Vec<u8>>, RsbtError> {
+        self.message(|sender| TorrentStorageMessage::LoadBlock {
+            index,
+            begin,
+            length,
+            sender,
+        })
+        .await
+    }
+
     pub async fn delete(&self, files: bool) -> Result<(), RsbtError> {
         self.message(|sender| TorrentStorageMessage::Delete { files, sender })
             .await
     }
 
+    /// Saves download priorities of torrent files in state.
+    pub async fn set_priorities(&self, priorities: Vec<FilePriority>) -> Result<(), RsbtError> {
+        self.message(|sender| TorrentStorageMessage::Priorities { priorities, sender })
+            .await
+    }
+
+    /// Starts hash recheck of all pieces, progress is reported with `checking` in state.
+    pub async fn recheck(&self) -> Result<(), RsbtError> {
+        self.message(TorrentStorageMessage::Recheck).await
+    }
+
+    /// Starts move of files to other location, progress is reported with `moving` in state.
+    ///
+    /// When files are moved, storage is opened at new location and torrent gets
+    /// [`DownloadTorrentEvent::StorageMoved`] event.
+    pub async fn move_to(&self, location: TorrentStorageLocation) -> Result<(), RsbtError> {
+        self.message(|sender| TorrentStorageMessage::Move { location, sender })
+            .await
+    }
+
     pub async fn files(&self) -> Result<Vec<RsbtFileView>, RsbtError> {
         self.message(TorrentStorageMessage::Files).await
     }
@@ -404,76 +611,322 @@ impl TorrentStorage {
     }
 }
 
-fn torrent_storage_message_loop(
+/// Storage loop state, which does not depend on storage backend.
+struct StorageLoop {
     properties: Arc<Properties>,
     torrent_process: Arc<TorrentProcess>,
     torrent_name: PathBuf,
-    mut state: TorrentStorageState,
+    location: TorrentStorageLocation,
+    state: TorrentStorageState,
     state_file: PathBuf,
-    mut channel_receiver: Receiver<TorrentStorageMessage>,
+    loop_sender: Sender<TorrentStorageMessage>,
+    channel_receiver: Receiver<TorrentStorageMessage>,
     watch_sender: watch::Sender<TorrentStorageState>,
-) -> Result<(), RsbtError> {
-    let info = &torrent_process.info;
-    let mut rt = Builder::new().basic_scheduler().enable_io().build()?;
-    let mmap_storage = Arc::new(MmapFlatStorage::create(
-        properties.save_to.clone(),
-        info.pieces.len(),
-        info.piece_length,
-        info.files.clone(),
-        &state.downloaded,
-    )?);
-    rt.block_on(async move {
-        while let Some(message) = channel_receiver.next().await {
+}
+
+async fn torrent_storage_message_loop<S, F>(
+    storage_loop: StorageLoop,
+    create: F,
+) -> Result<(), RsbtError>
+where
+    S: FlatStorage + Send + Sync + 'static,
+    F: Fn(&mut TorrentStorageState, &Path, Vec<FlatStorageFile>) -> Result<S, RsbtError>
+        + Send
+        + Sync
+        + 'static,
+{
+    let create = Arc::new(create);
+    let StorageLoop { state, .. } = storage_loop;
+    let (state, flat_storage) = open_storage(
+        &storage_loop.properties.disk_io,
+        create.clone(),
+        storage_loop.location.clone(),
+        storage_loop.torrent_process.info.files.clone(),
+        state,
+    )
+    .await?;
+    StorageLoop {
+        state,
+        ..storage_loop
+    }
+    .run(flat_storage, create)
+    .await
+}
+
+/// Creates flat storage at current location of files.
+///
+/// Move to other location and move of completed torrent files to complete location are
+/// finished first, if they were interrupted by crash.
+async fn open_storage<S, F>(
+    disk_io: &DiskIo,
+    create: Arc<F>,
+    location: TorrentStorageLocation,
+    files: Vec<FlatStorageFile>,
+    mut state: TorrentStorageState,
+) -> Result<(TorrentStorageState, Arc<S>), RsbtError>
+where
+    S: FlatStorage + Send + Sync + 'static,
+    F: Fn(&mut TorrentStorageState, &Path, Vec<FlatStorageFile>) -> Result<S, RsbtError>
+        + Send
+        + Sync
+        + 'static,
+{
+    disk_io
+        .run(DiskJobPriority::Write, move || {
+            if let Some(moving_from) = &state.moving_from {
+                let (download_path, storage_files) = location.storage_files(&files, state.moved);
+                debug!(
+                    "finishing move from {:?} to {:?}",
+                    moving_from, download_path
+                );
+                relocate_files(
+                    moving_from,
+                    &download_path,
+                    &storage_files,
+                    &storage_files,
+                    &AtomicU64::new(0),
+                )?;
+                state.moving_from = None;
+            }
+            if state.completed.is_some() && !state.moved && location.needs_move() {
+                location.move_files(&files)?;
+                state.moved = true;
+            }
+            let (download_path, files) = location.storage_files(&files, state.moved);
+            let flat_storage = create(&mut state, &download_path, files)?;
+            Ok((state, Arc::new(flat_storage)))
+        })
+        .await?
+}
+
+impl StorageLoop {
+    async fn run<S, F>(self, mut flat_storage: Arc<S>, create: Arc<F>) -> Result<(), RsbtError>
+    where
+        S: FlatStorage + Send + Sync + 'static,
+        F: Fn(&mut TorrentStorageState, &Path, Vec<FlatStorageFile>) -> Result<S, RsbtError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let StorageLoop {
+            properties,
+            torrent_process,
+            torrent_name,
+            mut location,
+            mut state,
+            state_file,
+            loop_sender,
+            channel_receiver,
+            watch_sender,
+        } = self;
+        let info = &torrent_process.info;
+        let recheck_process = torrent_process.clone();
+        let disk_io = properties.disk_io.clone();
+        let read_cache = properties.read_cache.clone();
+        let hash_id = torrent_process.hash_id;
+        let mut last_read = None;
+        if let Err(err) = watch_sender.broadcast(state.clone()) {
+            error!("cannot notify watchers: {}", err);
+        }
+
+        update_files(&mut state, &location, &info.files);
+        if let Err(err) = state.save(&state_file).await {
+            error!("cannot save state: {}", err);
+        }
+
+        let mut saved_while_checking = vec![];
+        let mut dirty = false;
+        if state.checking.is_some() {
+            tokio::spawn(recheck_pieces(
+                disk_io.clone(),
+                flat_storage.clone(),
+                recheck_process.clone(),
+                loop_sender.clone(),
+            ));
+        }
+
+        let ticks =
+            tokio::time::interval(STATE_SAVE_INTERVAL).map(|_| TorrentStorageMessage::SaveState);
+        let mut messages = stream::select(channel_receiver, ticks);
+
+        while let Some(message) = messages.next().await {
+            let message = if state.moving.is_some() {
+                match reject_while_moving(message) {
+                    Some(message) => message,
+                    None => continue,
+                }
+            } else {
+                message
+            };
             match message {
-                TorrentStorageMessage::SavePiece {
+                TorrentStorageMessage::SaveBlock {
                     index,
-                    data,
+                    begin,
+                    block,
                     sender,
                 } => {
-                    let (block_index, bit) = index_in_bitarray(index);
+                    if bit_by_index(index, &state.downloaded).is_some() {
+                        debug!(
+                            "piece {} is downloaded already, block {} dropped",
+                            index, begin
+                        );
+                        if sender.send(Ok(())).is_err() {
+                            error!("cannot send oneshot");
+                        }
+                        continue;
+                    }
+                    let len = block.len();
+
+                    match write_block(&disk_io, &flat_storage, index, begin, block).await {
+                        Ok(()) => read_cache.remove(hash_id, index),
+                        Err(err) => {
+                            error!("cannot write block: {}", err);
+                            if sender.send(Err(err)).is_err() {
+                                error!("cannot send block with oneshot message");
+                            }
+                            continue;
+                        }
+                    }
 
-                    let storage = mmap_storage.clone();
-                    let len = data.len();
+                    let blocks_count = info.sizes(index).1;
+                    let blocks = state
+                        .partial
+                        .entry(index)
+                        .or_insert_with(|| vec![0; count_parts(blocks_count, 8)]);
+                    let (block_index, bit) = index_in_bitarray(begin / BLOCK_SIZE);
+                    if let Some(blocks_byte) = blocks.get_mut(block_index) {
+                        *blocks_byte |= bit;
+                    }
+                    state.bytes_write += len as u64;
+                    dirty = true;
+
+                    if sender.send(Ok(())).is_err() {
+                        error!("cannot send oneshot");
+                    }
+                }
+                TorrentStorageMessage::CompletePiece { index, sender } => {
+                    let storage = flat_storage.clone();
+                    let process = recheck_process.clone();
 
-                    match spawn_blocking(move || storage.write_piece(index, data)).await? {
-                        Ok(()) => (),
+                    let piece = match disk_io
+                        .run(DiskJobPriority::Write, move || {
+                            verified_piece(storage.as_ref(), &process, index)
+                        })
+                        .await?
+                    {
+                        Ok(piece) => piece,
                         Err(err) => {
-                            error!("cannot write piece: {}", err);
-                            if sender.send(Err(err.into())).is_err() {
+                            error!("cannot verify piece: {}", err);
+                            if sender.send(Err(err)).is_err() {
                                 error!("cannot send piece with oneshot message");
                             }
                             continue;
                         }
-                    }
+                    };
 
-                    while state.downloaded.len() <= index {
+                    read_cache.remove(hash_id, index);
+                    state.partial.remove(&index);
+                    dirty = true;
+                    let (block_index, bit) = index_in_bitarray(index);
+                    while state.downloaded.len() <= block_index {
                         state.downloaded.push(0);
                     }
+                    let piece = match piece {
+                        Some(piece) => piece,
+                        None => {
+                            error!("piece {} hash mismatch", index);
+                            if state.downloaded[block_index] & bit != 0 {
+                                state.downloaded[block_index] &= !bit;
+                                state.pieces_left += 1;
+                                flat_storage.update_saved(&state.downloaded);
+                                state.update_completed();
+                                if let Err(err) = watch_sender.broadcast(state.clone()) {
+                                    error!("cannot notify watchers: {}", err);
+                                }
+                            }
+                            if sender
+                                .send(Err(RsbtError::PieceHashMismatch(index)))
+                                .is_err()
+                            {
+                                error!("cannot send oneshot");
+                            }
+                            continue;
+                        }
+                    };
                     if state.downloaded[block_index] & bit == 0 {
                         state.pieces_left -= 1;
-                        state.bytes_write += len as u64;
+                        flat_storage.piece_saved(index);
                     }
                     state.downloaded[block_index] |= bit;
+                    if state.checking.is_some() {
+                        saved_while_checking.push(index);
+                    }
+                    state.update_completed();
 
-                    if let Err(err) = state.save(&state_file).await {
-                        error!("cannot save state: {}", err);
+                    if state.pieces_left == 0 {
+                        if let Err(err) = flush_storage(&disk_io, flat_storage.clone()).await {
+                            error!("cannot flush storage: {}", err);
+                        }
+                        if !state.moved && location.needs_move() {
+                            flat_storage = move_storage(
+                                &disk_io,
+                                create.clone(),
+                                &location,
+                                &info.files,
+                                &mut state,
+                                flat_storage,
+                            )
+                            .await;
+                        }
+                        apply_file_attributes(&disk_io, &location, info, state.moved).await;
+                        dirty = false;
+                        if let Err(err) = save_state(
+                            &disk_io,
+                            flat_storage.clone(),
+                            &location,
+                            &info.files,
+                            &mut state,
+                            &state_file,
+                        )
+                        .await
+                        {
+                            error!("cannot save state: {}", err);
+                        }
                     }
                     if let Err(err) = watch_sender.broadcast(state.clone()) {
                         error!("cannot notify watchers: {}", err);
                     }
 
-                    if sender.send(Ok(())).is_err() {
+                    if sender.send(Ok(piece)).is_err() {
                         error!("cannot send oneshot");
                     }
                 }
                 TorrentStorageMessage::LoadPiece { index, sender } => {
-                    let storage = mmap_storage.clone();
+                    let piece = if bit_by_index(index, &state.downloaded).is_some() {
+                        let sequential = last_read.map(|last| last + 1) == Some(index);
+                        last_read = Some(index);
+                        cached_piece(
+                            &read_cache,
+                            &disk_io,
+                            &flat_storage,
+                            &torrent_process,
+                            index,
+                            sequential,
+                            &state.downloaded,
+                        )
+                        .await
+                        .map(|piece| piece.map(|piece| TorrentPiece(piece.to_vec())))
+                    } else {
+                        read_piece(&disk_io, &flat_storage, &torrent_process, index)
+                            .await
+                            .map(|piece| piece.map(TorrentPiece))
+                    };
 
-                    let piece = match spawn_blocking(move || storage.read_piece(index)).await? {
-                        Ok(data) => data.map(TorrentPiece),
+                    let piece = match piece {
+                        Ok(piece) => piece,
                         Err(err) => {
                             error!("cannot read piece: {}", err);
-                            if sender.send(Err(err.into())).is_err() {
+                            if sender.send(Err(err)).is_err() {
                                 error!("cannot send piece with oneshot message");
                             }
                             continue;
@@ -482,29 +935,71 @@ fn torrent_storage_message_loop(
 
                     if let Some(piece) = &piece {
                         state.bytes_read += piece.as_ref().len() as u64;
+                        dirty = true;
                     }
 
-                    if let Err(err) = state.save(&state_file).await {
-                        error!("cannot save state: {}", err);
+                    if sender.send(Ok(piece)).is_err() {
+                        error!("cannot send piece with oneshot message");
                     }
-                    if let Err(err) = watch_sender.broadcast(state.clone()) {
-                        error!("cannot notify watchers: {}", err);
+                }
+                TorrentStorageMessage::LoadBlock {
+                    index,
+                    begin,
+                    length,
+                    sender,
+                } => {
+                    let block = if bit_by_index(index, &state.downloaded).is_some() {
+                        let sequential = last_read.map(|last| last + 1) == Some(index);
+                        last_read = Some(index);
+                        cached_piece(
+                            &read_cache,
+                            &disk_io,
+                            &flat_storage,
+                            &torrent_process,
+                            index,
+                            sequential,
+                            &state.downloaded,
+                        )
+                        .await
+                        .map(|piece| {
+                            piece.and_then(|piece| {
+                                piece.get(begin..begin + length).map(|block| block.to_vec())
+                            })
+                        })
+                    } else {
+                        read_block(&disk_io, &flat_storage, index, begin, length).await
+                    };
+
+                    let block = match block {
+                        Ok(block) => block,
+                        Err(err) => {
+                            error!("cannot read block: {}", err);
+                            if sender.send(Err(err)).is_err() {
+                                error!("cannot send block with oneshot message");
+                            }
+                            continue;
+                        }
+                    };
+
+                    if let Some(block) = &block {
+                        state.bytes_read += block.len() as u64;
+                        dirty = true;
                     }
 
-                    if sender.send(Ok(piece)).is_err() {
-                        error!("cannot send piece with oneshot message");
+                    if sender.send(Ok(block)).is_err() {
+                        error!("cannot send block with oneshot message");
                     }
                 }
                 TorrentStorageMessage::Delete { files, sender } => {
+                    read_cache.remove_torrent(hash_id);
                     let mut result = cleanup_storage_state(properties.clone(), torrent_name).await;
                     if files {
-                        let storage = mmap_storage.clone();
-                        result = spawn_blocking(move || {
-                            storage
-                                .delete_files(properties.save_to.clone())
-                                .map_err(RsbtError::from)
-                        })
-                        .await?;
+                        let storage = flat_storage.clone();
+                        result = disk_io
+                            .run(DiskJobPriority::Write, move || {
+                                storage.delete_files().map_err(RsbtError::from)
+                            })
+                            .await?;
                     }
                     if sender.send(result).is_err() {
                         error!("cannot send delete result with oneshot message");
@@ -512,10 +1007,10 @@ fn torrent_storage_message_loop(
                     break;
                 }
                 TorrentStorageMessage::Files(sender) => {
-                    let storage = mmap_storage.clone();
-                    let saved = spawn_blocking(move || storage.saved())
-                        .await
-                        .map_err(RsbtError::from);
+                    let storage = flat_storage.clone();
+                    let saved = disk_io
+                        .run(DiskJobPriority::Read, move || storage.saved())
+                        .await;
                     let files_view = saved.map(|saved| {
                         saved
                             .into_iter()
@@ -526,6 +1021,7 @@ fn torrent_storage_message_loop(
                                 name: info.path.to_string_lossy().into(),
                                 saved,
                                 size: info.length,
+                                priority: FilePriority::default(),
                             })
                             .collect()
                     });
@@ -534,27 +1030,609 @@ fn torrent_storage_message_loop(
                     }
                 }
                 TorrentStorageMessage::FileInfo { file_id, sender } => {
-                    let storage = mmap_storage.clone();
-                    let file_info = spawn_blocking(move || storage.file_info(file_id))
+                    let storage = flat_storage.clone();
+                    let file_info = disk_io
+                        .run(DiskJobPriority::Read, move || storage.file_info(file_id))
                         .await
-                        .map_err(RsbtError::from)
                         .map_or_else(
                             |x| Err(x),
                             |v| v.ok_or_else(|| RsbtError::TorrentFileNotFound(file_id)),
-                        );
+                        )
+                        .map(|mut file_info| {
+                            file_info.file.path = info.files[file_id].path.clone();
+                            file_info
+                        });
 
                     if sender.send(file_info).is_err() {
                         error!("cannot send files result with oneshot message");
                     }
                 }
+                TorrentStorageMessage::Priorities { priorities, sender } => {
+                    state.priorities = priorities;
+                    dirty = false;
+                    let result = save_state(
+                        &disk_io,
+                        flat_storage.clone(),
+                        &location,
+                        &info.files,
+                        &mut state,
+                        &state_file,
+                    )
+                    .await;
+                    if let Err(err) = watch_sender.broadcast(state.clone()) {
+                        error!("cannot notify watchers: {}", err);
+                    }
+                    if sender.send(result).is_err() {
+                        error!("cannot send priorities result with oneshot message");
+                    }
+                }
+                TorrentStorageMessage::Recheck(sender) => {
+                    if state.checking.is_none() {
+                        read_cache.remove_torrent(hash_id);
+                        state.checking = Some(0);
+                        saved_while_checking.clear();
+                        tokio::spawn(recheck_pieces(
+                            disk_io.clone(),
+                            flat_storage.clone(),
+                            recheck_process.clone(),
+                            loop_sender.clone(),
+                        ));
+                        if let Err(err) = watch_sender.broadcast(state.clone()) {
+                            error!("cannot notify watchers: {}", err);
+                        }
+                    }
+                    if sender.send(Ok(())).is_err() {
+                        error!("cannot send recheck result with oneshot message");
+                    }
+                }
+                TorrentStorageMessage::Move {
+                    location: new_location,
+                    sender,
+                } => {
+                    if state.moving.is_some() {
+                        if sender.send(Err(RsbtError::StorageMoveInProgress)).is_err() {
+                            error!("cannot send move result with oneshot message");
+                        }
+                        continue;
+                    }
+                    if let Err(err) = flush_storage(&disk_io, flat_storage.clone()).await {
+                        error!("cannot flush storage before move: {}", err);
+                        if sender.send(Err(err)).is_err() {
+                            error!("cannot send move result with oneshot message");
+                        }
+                        continue;
+                    }
+                    let (download_path, _) = location.storage_files(&info.files, state.moved);
+                    let moving_from = state.moving_from.clone().unwrap_or(download_path);
+                    let (moving_to, files) = new_location.storage_files(&info.files, state.moved);
+                    debug!("moving files from {:?} to {:?}", moving_from, moving_to);
+                    location = new_location;
+                    state.moving_from = Some(moving_from.clone());
+                    state.moving = Some(0);
+                    let result = state.save(&state_file).await;
+                    if result.is_ok() {
+                        dirty = false;
+                        tokio::spawn(relocate_storage(
+                            disk_io.clone(),
+                            moving_from,
+                            moving_to,
+                            files,
+                            loop_sender.clone(),
+                        ));
+                    } else {
+                        state.moving = None;
+                    }
+                    if let Err(err) = watch_sender.broadcast(state.clone()) {
+                        error!("cannot notify watchers: {}", err);
+                    }
+                    if sender.send(result).is_err() {
+                        error!("cannot send move result with oneshot message");
+                    }
+                }
+                TorrentStorageMessage::MoveProgress(moved) => {
+                    state.moving = Some(moved);
+                    if let Err(err) = watch_sender.broadcast(state.clone()) {
+                        error!("cannot notify watchers: {}", err);
+                    }
+                }
+                TorrentStorageMessage::MoveDone(result) => {
+                    state.moving = None;
+                    let result = match result {
+                        Ok(()) => {
+                            state.moving_from = None;
+                            read_cache.remove_torrent(hash_id);
+                            open_storage(
+                                &disk_io,
+                                create.clone(),
+                                location.clone(),
+                                info.files.clone(),
+                                state.clone(),
+                            )
+                            .await
+                            .map(|(opened_state, opened_storage)| {
+                                state = opened_state;
+                                flat_storage = opened_storage;
+                                update_files(&mut state, &location, &info.files);
+                            })
+                        }
+                        Err(err) => Err(err),
+                    };
+                    if let Err(err) = &result {
+                        error!("cannot move files: {}", err);
+                    }
+                    dirty = false;
+                    if let Err(err) = state.save(&state_file).await {
+                        error!("cannot save state: {}", err);
+                    }
+                    if let Err(err) = watch_sender.broadcast(state.clone()) {
+                        error!("cannot notify watchers: {}", err);
+                    }
+                    if let Err(err) = torrent_process
+                        .broker_sender
+                        .clone()
+                        .send(DownloadTorrentEvent::StorageMoved(result))
+                        .await
+                    {
+                        error!("cannot send storage moved event: {}", err);
+                    }
+                }
+                TorrentStorageMessage::RecheckProgress(checked) => {
+                    state.checking = Some(checked);
+                    if let Err(err) = watch_sender.broadcast(state.clone()) {
+                        error!("cannot notify watchers: {}", err);
+                    }
+                }
+                TorrentStorageMessage::RecheckDone(result) => {
+                    state.checking = None;
+                    match result {
+                        Ok(mut downloaded) => {
+                            for index in saved_while_checking.drain(..) {
+                                let (block_index, bit) = index_in_bitarray(index);
+                                downloaded[block_index] |= bit;
+                            }
+                            let pieces_count = info.pieces.len();
+                            let pieces_downloaded = (0..pieces_count)
+                                .filter(|&index| bit_by_index(index, &downloaded).is_some())
+                                .count();
+                            state.pieces_left = (pieces_count - pieces_downloaded) as u32;
+                            state.downloaded = downloaded;
+                            state.update_completed();
+                            flat_storage.update_saved(&state.downloaded);
+                            if state.pieces_left == 0 {
+                                if !state.moved && location.needs_move() {
+                                    if let Err(err) =
+                                        flush_storage(&disk_io, flat_storage.clone()).await
+                                    {
+                                        error!("cannot flush storage: {}", err);
+                                    }
+                                    flat_storage = move_storage(
+                                        &disk_io,
+                                        create.clone(),
+                                        &location,
+                                        &info.files,
+                                        &mut state,
+                                        flat_storage,
+                                    )
+                                    .await;
+                                }
+                                apply_file_attributes(&disk_io, &location, info, state.moved).await;
+                            }
+                            debug!("recheck done, pieces left: {}", state.pieces_left);
+
+                            dirty = false;
+                            if let Err(err) = save_state(
+                                &disk_io,
+                                flat_storage.clone(),
+                                &location,
+                                &info.files,
+                                &mut state,
+                                &state_file,
+                            )
+                            .await
+                            {
+                                error!("cannot save state: {}", err);
+                            }
+                        }
+                        Err(err) => error!("cannot recheck pieces: {}", err),
+                    }
+                    if let Err(err) = watch_sender.broadcast(state.clone()) {
+                        error!("cannot notify watchers: {}", err);
+                    }
+                }
+                TorrentStorageMessage::SaveState => {
+                    if dirty {
+                        dirty = false;
+                        if let Err(err) = save_state(
+                            &disk_io,
+                            flat_storage.clone(),
+                            &location,
+                            &info.files,
+                            &mut state,
+                            &state_file,
+                        )
+                        .await
+                        {
+                            error!("cannot save state: {}", err);
+                        }
+                        if let Err(err) = watch_sender.broadcast(state.clone()) {
+                            error!("cannot notify watchers: {}", err);
+                        }
+                    }
+                }
             }
         }
+        flush_storage(&disk_io, flat_storage).await?;
         Ok::<(), RsbtError>(())
-    })?;
+    }
+}
+
+/// Flushes written data and saves state with files as they are on disk after flush.
+///
+/// Downloaded and partially downloaded pieces are only saved by this function, so they are
+/// never saved before their data reaches disk.
+async fn save_state<S: FlatStorage + Send + Sync + 'static>(
+    disk_io: &DiskIo,
+    storage: Arc<S>,
+    location: &TorrentStorageLocation,
+    files: &[FlatStorageFile],
+    state: &mut TorrentStorageState,
+    state_file: &Path,
+) -> Result<(), RsbtError> {
+    flush_storage(disk_io, storage).await?;
+    update_files(state, location, files);
+    state.save(state_file).await
+}
+
+/// Remembers files as they are on disk at current location.
+fn update_files(
+    state: &mut TorrentStorageState,
+    location: &TorrentStorageLocation,
+    files: &[FlatStorageFile],
+) {
+    let (download_path, files) = location.storage_files(files, state.moved);
+    state.update_files(&download_path, &files);
+}
+
+/// Moves files of completed torrent and creates flat storage at complete location.
+///
+/// If move fails, files stay at current location with current storage, and move is retried
+/// on next start.
+async fn move_storage<S, F>(
+    disk_io: &DiskIo,
+    create: Arc<F>,
+    location: &TorrentStorageLocation,
+    files: &[FlatStorageFile],
+    state: &mut TorrentStorageState,
+    flat_storage: Arc<S>,
+) -> Arc<S>
+where
+    S: FlatStorage + Send + Sync + 'static,
+    F: Fn(&mut TorrentStorageState, &Path, Vec<FlatStorageFile>) -> Result<S, RsbtError>
+        + Send
+        + Sync
+        + 'static,
+{
+    debug!("moving completed files to {:?}", location.complete);
+    match open_storage(
+        disk_io,
+        create,
+        location.clone(),
+        files.to_vec(),
+        state.clone(),
+    )
+    .await
+    {
+        Ok((moved_state, moved_storage)) => {
+            *state = moved_state;
+            update_files(state, location, files);
+            moved_storage
+        }
+        Err(err) => {
+            error!("cannot move completed files: {}", err);
+            flat_storage
+        }
+    }
+}
+
+/// Applies attributes of files of completed torrent, when files are at complete location.
+async fn apply_file_attributes(
+    disk_io: &DiskIo,
+    location: &TorrentStorageLocation,
+    info: &TorrentInfo,
+    moved: bool,
+) {
+    if !moved && location.needs_move()
+        || info
+            .attributes
+            .iter()
+            .all(|x| !x.executable && x.symlink.is_none())
+    {
+        return;
+    }
+    let (download_path, files) = location.storage_files(&info.files, moved);
+    let attributes = info.attributes.clone();
+    let result = disk_io
+        .run(DiskJobPriority::Write, move || {
+            apply_attributes(&download_path, &files, &attributes)
+        })
+        .await;
+    match result {
+        Ok(Ok(())) => (),
+        Ok(Err(err)) => error!("cannot apply file attributes: {}", err),
+        Err(err) => error!("cannot apply file attributes: {}", err),
+    }
+}
+
+/// Writes block with asynchronous write of storage if possible, otherwise in disk I/O pool.
+async fn write_block<S: FlatStorage + Send + Sync + 'static>(
+    disk_io: &DiskIo,
+    storage: &Arc<S>,
+    index: usize,
+    begin: usize,
+    block: Vec<u8>,
+) -> Result<(), RsbtError> {
+    match storage.submit_write_block(index, begin, block) {
+        Ok(write) => Ok(write.await?),
+        Err(block) => {
+            let storage = storage.clone();
+            Ok(disk_io
+                .run(DiskJobPriority::Write, move || {
+                    storage.write_block(index, begin, &block)
+                })
+                .await??)
+        }
+    }
+}
+
+/// Reads block with asynchronous read of storage if possible, otherwise in disk I/O pool.
+async fn read_block<S: FlatStorage + Send + Sync + 'static>(
+    disk_io: &DiskIo,
+    storage: &Arc<S>,
+    index: usize,
+    begin: usize,
+    length: usize,
+) -> Result<Option<Vec<u8>>, RsbtError> {
+    if let Some(read) = storage.submit_read_block(index, begin, length) {
+        return Ok(read.await?);
+    }
+    let storage = storage.clone();
+    Ok(disk_io
+        .run(DiskJobPriority::Read, move || {
+            storage.read_block(index, begin, length)
+        })
+        .await??)
+}
+
+/// Reads whole piece with asynchronous read of storage if possible, otherwise in disk I/O pool.
+async fn read_piece<S: FlatStorage + Send + Sync + 'static>(
+    disk_io: &DiskIo,
+    storage: &Arc<S>,
+    torrent_process: &TorrentProcess,
+    index: usize,
+) -> Result<Option<Vec<u8>>, RsbtError> {
+    let piece_length = torrent_process.info.sizes(index).0;
+    if let Some(read) = storage.submit_read_block(index, 0, piece_length) {
+        return Ok(read.await?);
+    }
+    let storage = storage.clone();
+    Ok(disk_io
+        .run(DiskJobPriority::Read, move || storage.read_piece(index))
+        .await??)
+}
+
+/// Reads downloaded piece through read cache.
+///
+/// If pieces are read sequentially, following downloaded pieces are read into cache in
+/// background.
+async fn cached_piece<S: FlatStorage + Send + Sync + 'static>(
+    read_cache: &Arc<ReadCache>,
+    disk_io: &Arc<DiskIo>,
+    storage: &Arc<S>,
+    torrent_process: &Arc<TorrentProcess>,
+    index: usize,
+    sequential: bool,
+    downloaded: &[u8],
+) -> Result<Option<Arc<Vec<u8>>>, RsbtError> {
+    let hash_id = torrent_process.hash_id;
+    if sequential {
+        for next in index + 1..=index + READ_AHEAD_PIECES {
+            if bit_by_index(next, downloaded).is_none() || read_cache.contains(hash_id, next) {
+                continue;
+            }
+            tokio::spawn(read_ahead(
+                read_cache.clone(),
+                disk_io.clone(),
+                storage.clone(),
+                torrent_process.clone(),
+                next,
+            ));
+        }
+    }
+
+    if let Some(piece) = read_cache.get(hash_id, index) {
+        return Ok(Some(piece));
+    }
+
+    let piece = read_piece(disk_io, storage, torrent_process, index)
+        .await?
+        .map(Arc::new);
+    if let Some(piece) = &piece {
+        read_cache.insert(hash_id, index, piece.clone());
+    }
+    Ok(piece)
+}
+
+async fn read_ahead<S: FlatStorage + Send + Sync + 'static>(
+    read_cache: Arc<ReadCache>,
+    disk_io: Arc<DiskIo>,
+    storage: Arc<S>,
+    torrent_process: Arc<TorrentProcess>,
+    index: usize,
+) {
+    match read_piece(&disk_io, &storage, &torrent_process, index).await {
+        Ok(Some(piece)) => read_cache.insert(torrent_process.hash_id, index, Arc::new(piece)),
+        Ok(None) => (),
+        Err(err) => error!("cannot read ahead piece {}: {}", index, err),
+    }
+}
+
+/// Answers requests, which access files of storage, with error while files are moved.
+///
+/// Other messages are returned back to be processed.
+fn reject_while_moving(message: TorrentStorageMessage) -> Option<TorrentStorageMessage> {
+    use RsbtError::StorageMoveInProgress;
+    let sent = match message {
+        TorrentStorageMessage::LoadPiece { sender, .. } => {
+            sender.send(Err(StorageMoveInProgress)).is_ok()
+        }
+        TorrentStorageMessage::CompletePiece { sender, .. } => {
+            sender.send(Err(StorageMoveInProgress)).is_ok()
+        }
+        TorrentStorageMessage::LoadBlock { sender, .. } => {
+            sender.send(Err(StorageMoveInProgress)).is_ok()
+        }
+        TorrentStorageMessage::SaveBlock { sender, .. }
+        | TorrentStorageMessage::Recheck(sender) => sender.send(Err(StorageMoveInProgress)).is_ok(),
+        TorrentStorageMessage::FileInfo { sender, .. } => {
+            sender.send(Err(StorageMoveInProgress)).is_ok()
+        }
+        message => return Some(message),
+    };
+    if !sent {
+        error!("cannot send rejected request result with oneshot message");
+    }
+    None
+}
+
+/// Moves files in disk I/O pool and reports progress until files are moved.
+async fn relocate_storage(
+    disk_io: Arc<DiskIo>,
+    from: PathBuf,
+    to: PathBuf,
+    files: Vec<FlatStorageFile>,
+    mut sender: Sender<TorrentStorageMessage>,
+) {
+    let moved = Arc::new(AtomicU64::new(0));
+    let job_moved = moved.clone();
+    let mut job = disk_io
+        .run(DiskJobPriority::Write, move || {
+            relocate_files(&from, &to, &files, &files, &job_moved)
+        })
+        .boxed();
+    let result = loop {
+        match future::select(job, delay_for(MOVE_PROGRESS_INTERVAL)).await {
+            Either::Left((result, _)) => break result.and_then(|result| Ok(result?)),
+            Either::Right((_, pending_job)) => {
+                job = pending_job;
+                let moved = moved.load(Ordering::Relaxed);
+                if let Err(err) = sender
+                    .send(TorrentStorageMessage::MoveProgress(moved))
+                    .await
+                {
+                    error!("cannot send move progress: {}", err);
+                }
+            }
+        }
+    };
+    if let Err(err) = sender.send(TorrentStorageMessage::MoveDone(result)).await {
+        error!("cannot send move result: {}", err);
+    }
+}
+
+/// Hashes all pieces and sends rebuilt downloaded pieces to storage loop.
+async fn recheck_pieces<S: FlatStorage + Send + Sync + 'static>(
+    disk_io: Arc<DiskIo>,
+    storage: Arc<S>,
+    torrent_process: Arc<TorrentProcess>,
+    mut sender: Sender<TorrentStorageMessage>,
+) {
+    let result = check_pieces(disk_io, storage, torrent_process, sender.clone()).await;
+    if let Err(err) = sender
+        .send(TorrentStorageMessage::RecheckDone(result))
+        .await
+    {
+        error!("cannot send recheck result: {}", err);
+    }
+}
+
+async fn check_pieces<S: FlatStorage + Send + Sync + 'static>(
+    disk_io: Arc<DiskIo>,
+    storage: Arc<S>,
+    torrent_process: Arc<TorrentProcess>,
+    mut sender: Sender<TorrentStorageMessage>,
+) -> Result<Vec<u8>, RsbtError> {
+    let pieces_count = torrent_process.info.pieces.len();
+    let mut downloaded = vec![0; count_parts(pieces_count, 8)];
+
+    let mut checks = stream::iter(0..pieces_count)
+        .map(|index| {
+            let storage = storage.clone();
+            let torrent_process = torrent_process.clone();
+            let disk_io = disk_io.clone();
+            async move {
+                disk_io
+                    .run(DiskJobPriority::HashCheck, move || {
+                        check_piece(storage.as_ref(), &torrent_process, index)
+                    })
+                    .await
+            }
+        })
+        .buffer_unordered(disk_io.threads());
 
+    let mut checked = 0;
+    while let Some(result) = checks.next().await {
+        let (index, valid) = result??;
+        if valid {
+            let (block_index, bit) = index_in_bitarray(index);
+            downloaded[block_index] |= bit;
+        }
+        checked += 1;
+        if checked % RECHECK_PROGRESS_STEP == 0 {
+            sender
+                .send(TorrentStorageMessage::RecheckProgress(checked))
+                .await?;
+        }
+    }
+
+    Ok(downloaded)
+}
+
+fn check_piece<S: FlatStorage + Send + Sync + 'static>(
+    storage: &S,
+    torrent_process: &TorrentProcess,
+    index: usize,
+) -> Result<(usize, bool), RsbtError> {
+    let valid = verified_piece(storage, torrent_process, index)?.is_some();
+    Ok((index, valid))
+}
+
+/// Flushes written data, so state never references blocks lost on system crash.
+///
+/// State must not be saved if flush fails.
+async fn flush_storage<S: FlatStorage + Send + Sync + 'static>(
+    disk_io: &DiskIo,
+    storage: Arc<S>,
+) -> Result<(), RsbtError> {
+    disk_io
+        .run(DiskJobPriority::Write, move || storage.flush())
+        .await??;
     Ok(())
 }
 
+/// Reads piece and returns it only if hash matches.
+fn verified_piece<S: FlatStorage + Send + Sync + 'static>(
+    storage: &S,
+    torrent_process: &TorrentProcess,
+    index: usize,
+) -> Result<Option<TorrentPiece>, RsbtError> {
+    if let Some(data) = storage.read_piece(index)? {
+        if torrent_process.info.pieces[index].verify(&data) {
+            return Ok(Some(TorrentPiece(data)));
+        }
+    }
+    Ok(None)
+}
+
 #[derive(Debug, Clone)]
 pub struct TorrentPiece(Vec<u8>);
 
@@ -564,3 +1642,81 @@ impl Deref for TorrentPiece {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        creator::{create_torrent, TorrentCreateOptions},
+        types::{info::TorrentVersion, torrent::parse_torrent, Settings},
+    };
+
+    #[tokio::test]
+    async fn corrupt_blocks_are_not_completed() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..BLOCK_SIZE * 2).map(|x| (x / 7) as u8).collect();
+        let data_file = dir.path().join("data");
+        std::fs::write(&data_file, &data).unwrap();
+        let created = create_torrent(&TorrentCreateOptions {
+            path: data_file,
+            announce: vec!["http://a/announce".parse().unwrap()],
+            piece_length: Some(BLOCK_SIZE),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let properties = Arc::new(Properties::from((
+            Settings::default(),
+            dir.path().join("config"),
+        )));
+        std::fs::create_dir_all(&properties.storage).unwrap();
+        let torrent = parse_torrent(&created.data).unwrap();
+        let (broker_sender, _broker_receiver) = mpsc::channel(DEFAULT_CHANNEL_BUFFER);
+        let torrent_process = Arc::new(TorrentProcess {
+            info: torrent.info().unwrap(),
+            hash_id: torrent.info_hash(TorrentVersion::V1),
+            hash_id_v2: None,
+            torrent,
+            handshake: vec![],
+            broker_sender,
+            bandwidth: properties.bandwidth.clone(),
+            download_bucket: Default::default(),
+            upload_bucket: Default::default(),
+        });
+        let storage = TorrentStorage::new(
+            properties.clone(),
+            "data.torrent",
+            torrent_process,
+            &[],
+            StorageBackend::Memory,
+            Preallocation::default(),
+            TorrentStorageLocation::direct(&properties.save_to),
+        )
+        .await
+        .unwrap();
+
+        let (first, second) = data.split_at(BLOCK_SIZE);
+        storage.save_block(0, 0, first.to_vec()).await.unwrap();
+        let piece = storage.complete_piece(0).await.unwrap();
+        assert_eq!(piece.as_ref(), first);
+
+        // duplicate block of completed piece from another peer is dropped
+        storage.save_block(0, 0, vec![0; BLOCK_SIZE]).await.unwrap();
+        let piece = storage.complete_piece(0).await.unwrap();
+        assert_eq!(piece.as_ref(), first);
+        assert_eq!(storage.receiver.borrow().pieces_left, 1);
+
+        storage.save_block(1, 0, vec![0; BLOCK_SIZE]).await.unwrap();
+        match storage.complete_piece(1).await {
+            Err(RsbtError::PieceHashMismatch(1)) => (),
+            other => panic!("unexpected result of corrupt piece: {:?}", other),
+        }
+        assert_eq!(storage.receiver.borrow().pieces_left, 1);
+        assert!(storage.receiver.borrow().partial.get(&1).is_none());
+
+        storage.save_block(1, 0, second.to_vec()).await.unwrap();
+        let piece = storage.complete_piece(1).await.unwrap();
+        assert_eq!(piece.as_ref(), second);
+        assert_eq!(storage.receiver.borrow().pieces_left, 0);
+    }
+}
@@ -0,0 +1,180 @@
+use super::*;
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+type ReadCacheKey = ([u8; SHA1_SIZE], usize);
+
+/// Cache of recently read pieces shared by all torrents.
+///
+/// Peers request pieces in small blocks, so piece is read from disk once and following blocks
+/// are served from memory. Least recently used pieces are evicted when size exceeds capacity.
+pub struct ReadCache {
+    capacity: usize,
+    pieces: Mutex<ReadCachePieces>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct ReadCachePieces {
+    pieces: HashMap<ReadCacheKey, (Arc<Vec<u8>>, u64)>,
+    used: BTreeMap<u64, ReadCacheKey>,
+    size: usize,
+    tick: u64,
+}
+
+impl ReadCache {
+    /// Creates cache with `capacity` in bytes, zero disables cache.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            pieces: Mutex::new(ReadCachePieces::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns cached piece and counts hit or miss.
+    pub fn get(&self, hash_id: [u8; SHA1_SIZE], index: usize) -> Option<Arc<Vec<u8>>> {
+        let mut pieces = self.pieces.lock().unwrap();
+        pieces.tick += 1;
+        let tick = pieces.tick;
+        let ReadCachePieces {
+            pieces: cached,
+            used,
+            ..
+        } = &mut *pieces;
+        if let Some((piece, last_used)) = cached.get_mut(&(hash_id, index)) {
+            used.remove(last_used);
+            used.insert(tick, (hash_id, index));
+            *last_used = tick;
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            Some(piece.clone())
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+
+    /// Checks if piece is cached without counting hit or miss.
+    pub fn contains(&self, hash_id: [u8; SHA1_SIZE], index: usize) -> bool {
+        self.pieces
+            .lock()
+            .unwrap()
+            .pieces
+            .contains_key(&(hash_id, index))
+    }
+
+    /// Puts piece into cache, evicting least recently used pieces.
+    pub fn insert(&self, hash_id: [u8; SHA1_SIZE], index: usize, piece: Arc<Vec<u8>>) {
+        if piece.len() > self.capacity {
+            return;
+        }
+        let mut pieces = self.pieces.lock().unwrap();
+        pieces.remove(&(hash_id, index));
+        while pieces.size + piece.len() > self.capacity {
+            let oldest = match pieces.used.keys().next() {
+                Some(&tick) => pieces.used[&tick],
+                None => break,
+            };
+            pieces.remove(&oldest);
+        }
+        pieces.tick += 1;
+        let tick = pieces.tick;
+        pieces.size += piece.len();
+        pieces.used.insert(tick, (hash_id, index));
+        pieces.pieces.insert((hash_id, index), (piece, tick));
+    }
+
+    /// Forgets piece, it must be called when piece data changes.
+    pub fn remove(&self, hash_id: [u8; SHA1_SIZE], index: usize) {
+        self.pieces.lock().unwrap().remove(&(hash_id, index));
+    }
+
+    /// Forgets all pieces of torrent.
+    pub fn remove_torrent(&self, hash_id: [u8; SHA1_SIZE]) {
+        let mut pieces = self.pieces.lock().unwrap();
+        let keys: Vec<_> = pieces
+            .pieces
+            .keys()
+            .filter(|(id, _)| *id == hash_id)
+            .cloned()
+            .collect();
+        for key in keys {
+            pieces.remove(&key);
+        }
+    }
+
+    /// Cache capacity in bytes.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Size of cached pieces in bytes.
+    pub fn size(&self) -> usize {
+        self.pieces.lock().unwrap().size
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+impl ReadCachePieces {
+    fn remove(&mut self, key: &ReadCacheKey) {
+        if let Some((piece, last_used)) = self.pieces.remove(key) {
+            self.used.remove(&last_used);
+            self.size -= piece.len();
+        }
+    }
+}
+
+impl std::fmt::Debug for ReadCache {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("ReadCache")
+            .field("capacity", &self.capacity)
+            .field("size", &self.size())
+            .field("hits", &self.hits())
+            .field("misses", &self.misses())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_pieces_are_evicted() {
+        let cache = ReadCache::new(300);
+        let torrent = [1; SHA1_SIZE];
+        let other_torrent = [2; SHA1_SIZE];
+
+        cache.insert(torrent, 0, Arc::new(vec![0; 100]));
+        cache.insert(torrent, 1, Arc::new(vec![1; 100]));
+        cache.insert(other_torrent, 0, Arc::new(vec![2; 100]));
+        assert_eq!(cache.size(), 300);
+
+        assert_eq!(cache.get(torrent, 0), Some(Arc::new(vec![0; 100])));
+        cache.insert(torrent, 2, Arc::new(vec![3; 100]));
+        assert!(cache.get(torrent, 1).is_none());
+        assert!(cache.contains(torrent, 0));
+        assert!(cache.contains(other_torrent, 0));
+        assert_eq!(cache.size(), 300);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+        cache.remove(other_torrent, 0);
+        assert_eq!(cache.size(), 200);
+        cache.remove_torrent(torrent);
+        assert_eq!(cache.size(), 0);
+
+        cache.insert(torrent, 3, Arc::new(vec![0; 301]));
+        assert!(!cache.contains(torrent, 3));
+    }
+}
@@ -0,0 +1,472 @@
+use super::*;
+use byteorder::WriteBytesExt;
+use chrono::Utc;
+use flat_storage::FlatStorageFile;
+use std::{collections::BTreeMap, ffi::OsString, time::UNIX_EPOCH};
+
+/// Current format of state file.
+///
+/// Version 0 contained only statistics and downloaded pieces, version 1 had no move flag,
+/// version 2 had no source of move in progress, they are migrated on load and saved as current
+/// version on next save.
+const TORRENT_STORAGE_FORMAT_VERSION: u8 = 3;
+
+const TEMP_EXTENSION: &str = ".tmp";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TorrentStorageState {
+    pub downloaded: Vec<u8>,
+    pub bytes_write: u64,
+    pub bytes_read: u64,
+    pub pieces_left: u32,
+    /// Downloaded blocks bitmap of partially downloaded pieces, by piece index
+    pub partial: BTreeMap<usize, Vec<u8>>,
+    /// Download priorities of torrent files, by file id
+    pub priorities: Vec<FilePriority>,
+    /// Torrent files as they were on disk when state was saved
+    pub files: Vec<TorrentStorageFileState>,
+    /// Unix timestamp when torrent was added
+    pub added: i64,
+    /// Unix timestamp when torrent was downloaded completely
+    pub completed: Option<i64>,
+    /// Pieces already checked while hash recheck is running
+    pub checking: Option<u32>,
+    /// Files were moved to complete location
+    pub moved: bool,
+    /// Directory files are moved from, while they are moved to other location
+    pub moving_from: Option<PathBuf>,
+    /// Bytes already moved while files are moved to other location
+    pub moving: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TorrentStorageFileState {
+    /// File size in bytes, 0 for missing file
+    pub size: u64,
+    /// Modification time in nanoseconds since unix epoch, none for missing file
+    pub modified: Option<u64>,
+}
+
+impl TorrentStorageState {
+    pub(crate) fn new(pieces_count: usize) -> Self {
+        Self {
+            downloaded: vec![],
+            bytes_write: 0,
+            bytes_read: 0,
+            pieces_left: pieces_count as u32,
+            partial: BTreeMap::new(),
+            priorities: vec![],
+            files: vec![],
+            added: Utc::now().timestamp(),
+            completed: None,
+            checking: None,
+            moved: false,
+            moving_from: None,
+            moving: None,
+        }
+    }
+
+    pub(crate) fn from_reader(mut rdr: impl Read) -> Result<Self, RsbtError> {
+        let version = rdr.read_u8()?;
+        match version {
+            0 => Self::from_reader_v0(rdr),
+            1..=TORRENT_STORAGE_FORMAT_VERSION => Self::from_reader_v1(rdr, version),
+            _ => Err(RsbtError::StorageVersion(version)),
+        }
+    }
+
+    fn from_reader_v0(mut rdr: impl Read) -> Result<Self, RsbtError> {
+        let bytes_write = rdr.read_u64::<BigEndian>()?;
+        let bytes_read = rdr.read_u64::<BigEndian>()?;
+        let pieces_left = rdr.read_u32::<BigEndian>()?;
+        let mut downloaded = vec![];
+        rdr.read_to_end(&mut downloaded)?;
+        Ok(Self {
+            downloaded,
+            bytes_write,
+            bytes_read,
+            pieces_left,
+            ..Self::new(0)
+        })
+    }
+
+    /// Reads version 1 and following versions, which only add fields at the end.
+    fn from_reader_v1(mut rdr: impl Read, version: u8) -> Result<Self, RsbtError> {
+        let bytes_write = rdr.read_u64::<BigEndian>()?;
+        let bytes_read = rdr.read_u64::<BigEndian>()?;
+        let pieces_left = rdr.read_u32::<BigEndian>()?;
+        let added = rdr.read_i64::<BigEndian>()?;
+        let completed = read_option(&mut rdr, |rdr| rdr.read_i64::<BigEndian>())?;
+        let downloaded = read_bytes(&mut rdr)?;
+
+        let priorities_count = rdr.read_u32::<BigEndian>()?;
+        let mut priorities = vec![];
+        for _ in 0..priorities_count {
+            priorities.push(priority_from_u8(rdr.read_u8()?));
+        }
+
+        let files_count = rdr.read_u32::<BigEndian>()?;
+        let mut files = vec![];
+        for _ in 0..files_count {
+            let size = rdr.read_u64::<BigEndian>()?;
+            let modified = read_option(&mut rdr, |rdr| rdr.read_u64::<BigEndian>())?;
+            files.push(TorrentStorageFileState { size, modified });
+        }
+
+        let partial_count = rdr.read_u32::<BigEndian>()?;
+        let mut partial = BTreeMap::new();
+        for _ in 0..partial_count {
+            let piece = rdr.read_u32::<BigEndian>()?;
+            partial.insert(piece as usize, read_bytes(&mut rdr)?);
+        }
+
+        let moved = version >= 2 && rdr.read_u8()? != 0;
+        let moving_from = if version >= 3 && rdr.read_u8()? != 0 {
+            let path = read_bytes(&mut rdr)?;
+            Some(PathBuf::from(String::from_utf8_lossy(&path).into_owned()))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            downloaded,
+            bytes_write,
+            bytes_read,
+            pieces_left,
+            partial,
+            priorities,
+            files,
+            added,
+            completed,
+            checking: None,
+            moved,
+            moving_from,
+            moving: None,
+        })
+    }
+
+    pub(crate) fn write_to(&self, mut wrt: impl Write) -> Result<(), RsbtError> {
+        wrt.write_u8(TORRENT_STORAGE_FORMAT_VERSION)?;
+        wrt.write_u64::<BigEndian>(self.bytes_write)?;
+        wrt.write_u64::<BigEndian>(self.bytes_read)?;
+        wrt.write_u32::<BigEndian>(self.pieces_left)?;
+        wrt.write_i64::<BigEndian>(self.added)?;
+        write_option(&mut wrt, self.completed, |wrt, x| {
+            wrt.write_i64::<BigEndian>(x)
+        })?;
+        write_bytes(&mut wrt, &self.downloaded)?;
+
+        wrt.write_u32::<BigEndian>(self.priorities.len().try_into()?)?;
+        for &priority in &self.priorities {
+            wrt.write_u8(priority_to_u8(priority))?;
+        }
+
+        wrt.write_u32::<BigEndian>(self.files.len().try_into()?)?;
+        for file in &self.files {
+            wrt.write_u64::<BigEndian>(file.size)?;
+            write_option(&mut wrt, file.modified, |wrt, x| {
+                wrt.write_u64::<BigEndian>(x)
+            })?;
+        }
+
+        wrt.write_u32::<BigEndian>(self.partial.len().try_into()?)?;
+        for (&piece, blocks) in &self.partial {
+            wrt.write_u32::<BigEndian>(piece.try_into()?)?;
+            write_bytes(&mut wrt, blocks)?;
+        }
+
+        wrt.write_u8(self.moved as u8)?;
+        match &self.moving_from {
+            Some(moving_from) => {
+                wrt.write_u8(1)?;
+                write_bytes(&mut wrt, moving_from.to_string_lossy().as_bytes())?;
+            }
+            None => wrt.write_u8(0)?,
+        }
+
+        Ok(())
+    }
+
+    /// Saves state atomically: writes temporary file, syncs it, renames over state file and
+    /// syncs directory, so rename survives system crash.
+    pub(crate) async fn save<P: AsRef<Path>>(&self, state_file: P) -> Result<(), RsbtError> {
+        let state_file = state_file.as_ref();
+        let temp_file = temp_state_file(state_file);
+
+        let mut data = vec![];
+        self.write_to(&mut data)?;
+
+        write_synced(&temp_file, &data).await.with_context(|err| {
+            format!(
+                "cannot write state file {}: {}",
+                temp_file.to_string_lossy(),
+                err
+            )
+        })?;
+
+        fs::rename(&temp_file, state_file)
+            .await
+            .with_context(|err| {
+                format!(
+                    "cannot replace state file {}: {}",
+                    state_file.to_string_lossy(),
+                    err
+                )
+            })?;
+
+        sync_parent_dir(state_file).await.with_context(|err| {
+            format!(
+                "cannot sync directory of state file {}: {}",
+                state_file.to_string_lossy(),
+                err
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// Remembers sizes and modification times of torrent files.
+    pub(crate) fn update_files(&mut self, download_path: &Path, files: &[FlatStorageFile]) {
+        self.files = files_state(download_path, files);
+    }
+
+    /// Checks if torrent files were changed since state was saved.
+    ///
+    /// State migrated from previous format has no files and is never treated as modified.
+    pub(crate) fn files_modified(&self, download_path: &Path, files: &[FlatStorageFile]) -> bool {
+        !self.files.is_empty() && self.files != files_state(download_path, files)
+    }
+
+    /// Download priorities of files as skipped flags, by file id.
+    ///
+    /// Symlinks are skipped too, they are created on completion.
+    pub(crate) fn skipped_files(&self, info: &TorrentInfo) -> Vec<bool> {
+        info.attributes
+            .iter()
+            .enumerate()
+            .map(|(file_id, attributes)| {
+                attributes.symlink.is_some()
+                    || self.priorities.get(file_id) == Some(&FilePriority::Skip)
+            })
+            .collect()
+    }
+
+    /// Forgets all downloaded and partially downloaded pieces.
+    pub(crate) fn clear_pieces(&mut self, pieces_count: usize) {
+        self.downloaded.clear();
+        self.partial.clear();
+        self.pieces_left = pieces_count as u32;
+        self.completed = None;
+        self.checking = None;
+    }
+
+    /// Sets or clears completion time after downloaded pieces were changed.
+    pub(crate) fn update_completed(&mut self) {
+        if self.pieces_left == 0 {
+            if self.completed.is_none() {
+                self.completed = Some(Utc::now().timestamp());
+            }
+        } else {
+            self.completed = None;
+        }
+    }
+}
+
+pub(crate) fn temp_state_file(state_file: &Path) -> PathBuf {
+    let mut temp_file = OsString::from(state_file.as_os_str());
+    temp_file.push(TEMP_EXTENSION);
+    temp_file.into()
+}
+
+async fn write_synced(file_path: &Path, data: &[u8]) -> Result<(), std::io::Error> {
+    let mut f = File::create(file_path).await?;
+    f.write_all(data).await?;
+    f.sync_all().await
+}
+
+#[cfg(unix)]
+async fn sync_parent_dir(file_path: &Path) -> Result<(), std::io::Error> {
+    match file_path.parent() {
+        Some(dir) if dir != Path::new("") => File::open(dir).await?.sync_all().await,
+        _ => File::open(".").await?.sync_all().await,
+    }
+}
+
+/// Directories cannot be opened as files on other systems, so they are not synced.
+#[cfg(not(unix))]
+async fn sync_parent_dir(_file_path: &Path) -> Result<(), std::io::Error> {
+    Ok(())
+}
+
+fn files_state(download_path: &Path, files: &[FlatStorageFile]) -> Vec<TorrentStorageFileState> {
+    files
+        .iter()
+        .map(
+            |file| match std::fs::metadata(download_path.join(&file.path)) {
+                Ok(metadata) => TorrentStorageFileState {
+                    size: metadata.len(),
+                    modified: metadata
+                        .modified()
+                        .ok()
+                        .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+                        .map(|x| x.as_nanos() as u64),
+                },
+                Err(_) => TorrentStorageFileState {
+                    size: 0,
+                    modified: None,
+                },
+            },
+        )
+        .collect()
+}
+
+fn priority_to_u8(priority: FilePriority) -> u8 {
+    match priority {
+        FilePriority::Skip => 0,
+        FilePriority::Low => 1,
+        FilePriority::Normal => 2,
+        FilePriority::High => 3,
+    }
+}
+
+fn priority_from_u8(value: u8) -> FilePriority {
+    match value {
+        0 => FilePriority::Skip,
+        1 => FilePriority::Low,
+        3 => FilePriority::High,
+        _ => FilePriority::Normal,
+    }
+}
+
+fn read_bytes(rdr: &mut impl Read) -> Result<Vec<u8>, RsbtError> {
+    let len = rdr.read_u32::<BigEndian>()? as usize;
+    let mut data = vec![];
+    rdr.take(len as u64).read_to_end(&mut data)?;
+    if data.len() != len {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(data)
+}
+
+fn write_bytes(wrt: &mut impl Write, data: &[u8]) -> Result<(), RsbtError> {
+    wrt.write_u32::<BigEndian>(data.len().try_into()?)?;
+    wrt.write_all(data)?;
+    Ok(())
+}
+
+fn read_option<R: Read, T>(
+    rdr: &mut R,
+    f: impl FnOnce(&mut R) -> Result<T, std::io::Error>,
+) -> Result<Option<T>, RsbtError> {
+    Ok(if rdr.read_u8()? != 0 {
+        Some(f(rdr)?)
+    } else {
+        None
+    })
+}
+
+fn write_option<W: Write, T>(
+    wrt: &mut W,
+    value: Option<T>,
+    f: impl FnOnce(&mut W, T) -> Result<(), std::io::Error>,
+) -> Result<(), RsbtError> {
+    match value {
+        Some(value) => {
+            wrt.write_u8(1)?;
+            f(wrt, value)?;
+        }
+        None => wrt.write_u8(0)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_state_roundtrip() {
+        let mut partial = BTreeMap::new();
+        partial.insert(3, vec![0b1010_0000]);
+        let state = TorrentStorageState {
+            downloaded: vec![0b1100_0000, 1],
+            bytes_write: 1024,
+            bytes_read: 512,
+            pieces_left: 13,
+            partial,
+            priorities: vec![FilePriority::High, FilePriority::Skip],
+            files: vec![
+                TorrentStorageFileState {
+                    size: 100,
+                    modified: Some(1_590_000_000_000_000_000),
+                },
+                TorrentStorageFileState {
+                    size: 0,
+                    modified: None,
+                },
+            ],
+            added: 1_590_000_000,
+            completed: Some(1_590_000_100),
+            checking: None,
+            moved: true,
+            moving_from: Some(PathBuf::from("old").join("path")),
+            moving: None,
+        };
+
+        let mut data = vec![];
+        state.write_to(&mut data).unwrap();
+        assert_eq!(data[0], TORRENT_STORAGE_FORMAT_VERSION);
+
+        let loaded = TorrentStorageState::from_reader(data.as_slice()).unwrap();
+        assert_eq!(loaded, state);
+
+        assert!(TorrentStorageState::from_reader(&data[..data.len() - 1]).is_err());
+
+        let v1_len = data.len() - 1 - 1 - 4 - "old/path".len();
+        data[0] = 1;
+        let loaded = TorrentStorageState::from_reader(&data[..v1_len]).unwrap();
+        assert_eq!(
+            loaded,
+            TorrentStorageState {
+                moved: false,
+                moving_from: None,
+                ..state
+            }
+        );
+    }
+
+    #[test]
+    fn check_state_v0_migration() {
+        let mut data = vec![0];
+        data.extend_from_slice(&1024u64.to_be_bytes());
+        data.extend_from_slice(&512u64.to_be_bytes());
+        data.extend_from_slice(&13u32.to_be_bytes());
+        data.extend_from_slice(&[0b1100_0000, 1]);
+
+        let state = TorrentStorageState::from_reader(data.as_slice()).unwrap();
+        assert_eq!(state.downloaded, vec![0b1100_0000, 1]);
+        assert_eq!(state.bytes_write, 1024);
+        assert_eq!(state.bytes_read, 512);
+        assert_eq!(state.pieces_left, 13);
+        assert!(state.partial.is_empty());
+        assert!(state.files.is_empty());
+        assert_eq!(state.completed, None);
+
+        assert!(TorrentStorageState::from_reader(&[4u8][..]).is_err());
+    }
+
+    #[tokio::test]
+    async fn check_state_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("a.torrent.state");
+        let state = TorrentStorageState::new(3);
+
+        state.save(&state_file).await.unwrap();
+        state.save(&state_file).await.unwrap();
+
+        assert!(!temp_state_file(&state_file).exists());
+        let data = std::fs::read(&state_file).unwrap();
+        let loaded = TorrentStorageState::from_reader(data.as_slice()).unwrap();
+        assert_eq!(loaded, state);
+    }
+}
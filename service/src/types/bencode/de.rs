@@ -0,0 +1,235 @@
+use super::*;
+use crate::errors::BencodeError;
+use serde::de::{
+    self, value::StringDeserializer, DeserializeOwned, DeserializeSeed, IntoDeserializer,
+    Unexpected, Visitor,
+};
+use serde::forward_to_deserialize_any;
+
+/// Deserializes value from bencode data.
+///
+/// Strings are byte strings, they are accepted as `String` if they are valid utf-8, booleans
+/// are integers 0 and 1.
+pub fn from_bencode<T: DeserializeOwned>(data: &[u8]) -> Result<T, BencodeError> {
+    let blob = parse_bencode(data).map_err(|_| BencodeError::Parse)?;
+    from_bencode_blob(blob)
+}
+
+/// Deserializes value from parsed bencode.
+pub fn from_bencode_blob<T: DeserializeOwned>(blob: BencodeBlob) -> Result<T, BencodeError> {
+    T::deserialize(BencodeDeserializer(blob))
+}
+
+struct BencodeDeserializer(BencodeBlob);
+
+impl BencodeDeserializer {
+    fn key(key: Vec<u8>) -> Self {
+        BencodeDeserializer(BencodeValue::String(key).into())
+    }
+}
+
+fn unexpected(value: &BencodeValue) -> Unexpected<'_> {
+    match value {
+        BencodeValue::String(s) => Unexpected::Bytes(s),
+        BencodeValue::Integer(i) => Unexpected::Signed(*i),
+        BencodeValue::List(_) => Unexpected::Seq,
+        BencodeValue::Dictionary(_) => Unexpected::Map,
+    }
+}
+
+impl<'de> de::Deserializer<'de> for BencodeDeserializer {
+    type Error = BencodeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0.value {
+            BencodeValue::String(s) => visitor.visit_byte_buf(s),
+            BencodeValue::Integer(i) => visitor.visit_i64(i),
+            BencodeValue::List(items) => visitor.visit_seq(ListAccess(items.into_iter())),
+            BencodeValue::Dictionary(entries) => visitor.visit_map(DictionaryAccess {
+                entries: entries.into_iter(),
+                value: None,
+            }),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0.value {
+            BencodeValue::Integer(0) => visitor.visit_bool(false),
+            BencodeValue::Integer(1) => visitor.visit_bool(true),
+            ref value => Err(de::Error::invalid_type(unexpected(value), &visitor)),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0.value {
+            BencodeValue::String(s) => match String::from_utf8(s) {
+                Ok(s) => visitor.visit_string(s),
+                Err(err) => Err(de::Error::invalid_value(
+                    Unexpected::Bytes(err.as_bytes()),
+                    &visitor,
+                )),
+            },
+            ref value => Err(de::Error::invalid_type(unexpected(value), &visitor)),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if name == RAW_BLOB {
+            visitor.visit_byte_buf(self.0.source)
+        } else {
+            visitor.visit_newtype_struct(self)
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.0.value {
+            BencodeValue::String(_) => {
+                let variant: String = de::Deserialize::deserialize(self)?;
+                let variant: StringDeserializer<BencodeError> = variant.into_deserializer();
+                visitor.visit_enum(variant)
+            }
+            BencodeValue::Dictionary(mut entries) if entries.len() == 1 => {
+                let (variant, value) = entries.remove(0);
+                visitor.visit_enum(VariantAccess { variant, value })
+            }
+            ref value => Err(de::Error::invalid_type(unexpected(value), &visitor)),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 bytes byte_buf unit_struct seq tuple
+        tuple_struct map struct identifier
+    }
+}
+
+struct ListAccess(std::vec::IntoIter<BencodeBlob>);
+
+impl<'de> de::SeqAccess<'de> for ListAccess {
+    type Error = BencodeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        match self.0.next() {
+            Some(item) => seed.deserialize(BencodeDeserializer(item)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct DictionaryAccess {
+    entries: std::vec::IntoIter<(Vec<u8>, BencodeBlob)>,
+    value: Option<BencodeBlob>,
+}
+
+impl<'de> de::MapAccess<'de> for DictionaryAccess {
+    type Error = BencodeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(BencodeDeserializer::key(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| BencodeError::Message("value without key".into()))?;
+        seed.deserialize(BencodeDeserializer(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+/// Enum variant with data, which is a dictionary with single entry.
+struct VariantAccess {
+    variant: Vec<u8>,
+    value: BencodeBlob,
+}
+
+impl<'de> de::EnumAccess<'de> for VariantAccess {
+    type Error = BencodeError;
+    type Variant = BencodeDeserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let variant = seed.deserialize(BencodeDeserializer::key(self.variant))?;
+        Ok((variant, BencodeDeserializer(self.value)))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for BencodeDeserializer {
+    type Error = BencodeError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Self::Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
@@ -0,0 +1,432 @@
+use super::*;
+use crate::parser::parse_bencode;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryInto;
+use std::fmt;
+use std::net::IpAddr;
+
+mod de;
+mod ser;
+
+pub use de::{from_bencode, from_bencode_blob};
+pub use ser::to_bencode;
+
+/// Name of newtype, which passes source of [`BencodeBlob`] through serde as is.
+const RAW_BLOB: &str = "$rsbt::BencodeBlob";
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct BencodeBlob {
+    pub source: Vec<u8>,
+    pub value: BencodeValue,
+}
+
+impl TryFrom<Vec<u8>> for BencodeBlob {
+    type Error = RsbtError;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        parse_bencode(&value)
+    }
+}
+
+impl TryFrom<&[u8]> for BencodeBlob {
+    type Error = RsbtError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        parse_bencode(value)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum BencodeValue {
+    String(Vec<u8>),
+    Integer(i64),
+    List(Vec<BencodeBlob>),
+    /// Dictionary keys are byte strings, e.g. `piece layers` are keyed by hashes
+    Dictionary(Vec<(Vec<u8>, BencodeBlob)>),
+}
+
+macro_rules! blanket_blob_value {
+    ($type:ty) => {
+        impl TryFrom<BencodeBlob> for $type {
+            type Error = TryFromBencode;
+
+            fn try_from(value: BencodeBlob) -> Result<Self, Self::Error> {
+                BencodeValue::from(value).try_into()
+            }
+        }
+    };
+}
+
+impl TryFrom<BencodeValue> for Vec<u8> {
+    type Error = TryFromBencode;
+
+    fn try_from(value: BencodeValue) -> Result<Self, Self::Error> {
+        match value {
+            BencodeValue::String(s) => Ok(s),
+            _ => Err(TryFromBencode::NotString),
+        }
+    }
+}
+blanket_blob_value!(Vec<u8>);
+
+impl TryFrom<BencodeValue> for String {
+    type Error = TryFromBencode;
+
+    fn try_from(value: BencodeValue) -> Result<Self, Self::Error> {
+        match value {
+            BencodeValue::String(s) => Ok(std::str::from_utf8(&s)?.into()),
+            _ => Err(TryFromBencode::NotString),
+        }
+    }
+}
+blanket_blob_value!(String);
+
+impl TryFrom<BencodeValue> for i64 {
+    type Error = TryFromBencode;
+
+    fn try_from(value: BencodeValue) -> Result<Self, Self::Error> {
+        match value {
+            BencodeValue::Integer(s) => Ok(s),
+            _ => Err(TryFromBencode::NotInteger),
+        }
+    }
+}
+blanket_blob_value!(i64);
+
+impl TryFrom<BencodeValue> for u16 {
+    type Error = TryFromBencode;
+
+    fn try_from(value: BencodeValue) -> Result<Self, Self::Error> {
+        value
+            .try_into()
+            .and_then(|x: i64| x.try_into().map_err(|_| TryFromBencode::OutOfRange(x)))
+    }
+}
+blanket_blob_value!(u16);
+
+impl TryFrom<BencodeValue> for Vec<BencodeBlob> {
+    type Error = TryFromBencode;
+
+    fn try_from(value: BencodeValue) -> Result<Self, Self::Error> {
+        match value {
+            BencodeValue::List(s) => Ok(s),
+            _ => Err(TryFromBencode::NotList),
+        }
+    }
+}
+blanket_blob_value!(Vec<BencodeBlob>);
+
+impl TryFrom<BencodeValue> for Vec<(Vec<u8>, BencodeBlob)> {
+    type Error = TryFromBencode;
+
+    fn try_from(value: BencodeValue) -> Result<Self, Self::Error> {
+        match value {
+            BencodeValue::Dictionary(s) => Ok(s),
+            _ => Err(TryFromBencode::NotDictionary),
+        }
+    }
+}
+blanket_blob_value!(Vec<(Vec<u8>, BencodeBlob)>);
+
+impl TryFrom<BencodeValue> for Vec<(String, BencodeBlob)> {
+    type Error = TryFromBencode;
+
+    fn try_from(value: BencodeValue) -> Result<Self, Self::Error> {
+        let dictionary: Vec<(Vec<u8>, BencodeBlob)> = value.try_into()?;
+        dictionary
+            .into_iter()
+            .map(|(key, value)| {
+                String::from_utf8(key)
+                    .map(|key| (key, value))
+                    .map_err(|err| err.utf8_error().into())
+            })
+            .collect()
+    }
+}
+blanket_blob_value!(Vec<(String, BencodeBlob)>);
+
+impl TryFrom<BencodeValue> for Vec<String> {
+    type Error = TryFromBencode;
+
+    fn try_from(value: BencodeValue) -> Result<Self, Self::Error> {
+        match value {
+            BencodeValue::List(s) => s.into_iter().map(TryInto::try_into).collect(),
+            _ => Err(TryFromBencode::NotList),
+        }
+    }
+}
+blanket_blob_value!(Vec<String>);
+
+impl TryFrom<BencodeValue> for Vec<Vec<String>> {
+    type Error = TryFromBencode;
+
+    fn try_from(value: BencodeValue) -> Result<Self, Self::Error> {
+        value
+            .try_into()
+            .and_then(|list: Vec<BencodeBlob>| list.into_iter().map(TryInto::try_into).collect())
+    }
+}
+blanket_blob_value!(Vec<Vec<String>>);
+
+impl TryFrom<BencodeValue> for IpAddr {
+    type Error = TryFromBencode;
+
+    fn try_from(value: BencodeValue) -> Result<Self, Self::Error> {
+        value
+            .try_into()
+            .and_then(|s: String| s.parse().map_err(TryFromBencode::from))
+    }
+}
+blanket_blob_value!(IpAddr);
+
+impl From<BencodeBlob> for BencodeValue {
+    fn from(blob: BencodeBlob) -> Self {
+        blob.value
+    }
+}
+
+impl From<BencodeValue> for BencodeBlob {
+    /// Encodes value canonically, dictionary keys are sorted. Nested blobs are written as is,
+    /// so source of parsed values (e.g. info of torrent) is kept.
+    fn from(mut value: BencodeValue) -> Self {
+        let mut source = vec![];
+        match &mut value {
+            BencodeValue::String(s) => {
+                source.extend_from_slice(format!("{}:", s.len()).as_bytes());
+                source.extend_from_slice(s);
+            }
+            BencodeValue::Integer(i) => source.extend_from_slice(format!("i{}e", i).as_bytes()),
+            BencodeValue::List(l) => {
+                source.push(b'l');
+                for item in l.iter() {
+                    source.extend_from_slice(&item.source);
+                }
+                source.push(b'e');
+            }
+            BencodeValue::Dictionary(d) => {
+                d.sort_by(|a, b| a.0.cmp(&b.0));
+                source.push(b'd');
+                for (key, item) in d.iter() {
+                    source.extend_from_slice(format!("{}:", key.len()).as_bytes());
+                    source.extend_from_slice(key);
+                    source.extend_from_slice(&item.source);
+                }
+                source.push(b'e');
+            }
+        }
+        BencodeBlob { source, value }
+    }
+}
+
+impl Serialize for BencodeBlob {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(RAW_BLOB, &RawBytes(&self.source))
+    }
+}
+
+impl<'de> Deserialize<'de> for BencodeBlob {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BlobVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for BlobVisitor {
+            type Value = BencodeBlob;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("bencode value")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                parse_bencode(v).map_err(|_| E::custom("invalid bencode"))
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(RAW_BLOB, BlobVisitor)
+    }
+}
+
+struct RawBytes<'a>(&'a [u8]);
+
+impl Serialize for RawBytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// Byte strings for `#[serde(with = "...")]`, `Vec<u8>` is a list of integers otherwise.
+pub mod bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("byte string")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                Ok(v.to_vec())
+            }
+
+            fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+                Ok(v)
+            }
+        }
+
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+impl From<std::str::Utf8Error> for TryFromBencode {
+    fn from(value: std::str::Utf8Error) -> Self {
+        TryFromBencode::NotUtf8(value)
+    }
+}
+
+impl From<std::net::AddrParseError> for TryFromBencode {
+    fn from(value: std::net::AddrParseError) -> Self {
+        TryFromBencode::NotValidIp(value)
+    }
+}
+
+macro_rules! try_from_bencode {
+    ($type:ty,
+        $(normal: ($($normal_key:expr => $normal_field:ident),*)$(,)*)*
+        $(optional: ($($optional_key:expr => $optional_field:ident),*)$(,)*)*
+        $(bencode: ($($bencode_key:expr => $bencode_field:ident),*)$(,)*)*
+        $(raw: ($($raw:ident),*))*
+        $(failure: $($failure_key:expr),*)*
+        ) => {
+        impl TryFrom<BencodeBlob> for $type {
+
+            type Error = RsbtError;
+
+            fn try_from(value: BencodeBlob) -> Result<Self, Self::Error> {
+                let _source = value.source.clone();
+                let dictionary: Vec<(String, BencodeBlob)> = value.try_into()?;
+
+                $($(let mut $normal_field = None;)*)*
+                $($(let mut $optional_field = None;)*)*
+                $($(let mut $bencode_field = None;)*)*
+
+                for (key, value) in dictionary {
+                    match key.as_str() {
+                        $($($normal_key => $normal_field = Some(value.try_into()?),)*)*
+                        $($($optional_key => $optional_field = Some(value.try_into()?),)*)*
+                        $($($bencode_key => $bencode_field = Some(value),)*)*
+                        $($($failure_key => return Err(RsbtError::FailureReason(value.try_into()?)),)*)*
+                        _ => (),
+                    }
+                }
+
+                Ok(Self {
+                    $($($raw: _source.to_vec(),)*)*
+                    $($($normal_field: $normal_field
+                        .ok_or(TryFromBencode::MissingField($normal_key))?,)*)*
+                    $($($optional_field,)*)*
+                    $($($bencode_field: $bencode_field
+                        .ok_or(TryFromBencode::MissingField($bencode_key))?,)*)*
+                })
+            }
+        }
+
+        impl TryFrom<Vec<u8>> for $type {
+            type Error = RsbtError;
+
+            fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+                let bencode: BencodeBlob = value.try_into()?;
+                bencode.try_into().map_err(RsbtError::from)
+            }
+        }
+
+        impl TryFrom<&[u8]> for $type {
+            type Error = RsbtError;
+
+            fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+                let bencode: BencodeBlob = value.try_into()?;
+                bencode.try_into().map_err(RsbtError::from)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Action {
+        Stop,
+        Limit(i64),
+        Move { to: String },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Sample {
+        name: String,
+        #[serde(rename = "piece length")]
+        piece_length: u32,
+        private: bool,
+        comment: Option<String>,
+        #[serde(with = "bytes")]
+        pieces: Vec<u8>,
+        tiers: Vec<Vec<String>>,
+        actions: Vec<Action>,
+        info: BencodeBlob,
+    }
+
+    #[test]
+    fn check_encode_sorted_dictionary() {
+        let blob: BencodeBlob = BencodeValue::Dictionary(vec![
+            (b"b".to_vec(), BencodeValue::Integer(-1).into()),
+            (b"a".to_vec(), BencodeValue::String(b"x".to_vec()).into()),
+        ])
+        .into();
+        assert_eq!(blob.source, b"d1:a1:x1:bi-1ee");
+        assert_eq!(parse_bencode(&blob.source).unwrap(), blob);
+    }
+
+    #[test]
+    fn check_serde_round_trip() {
+        let info = parse_bencode(b"d4:zzzzi1e4:aaaai2ee").unwrap();
+        let sample = Sample {
+            name: "name".into(),
+            piece_length: 16384,
+            private: true,
+            comment: None,
+            pieces: vec![0, 255],
+            tiers: vec![vec!["a".into(), "b".into()]],
+            actions: vec![
+                Action::Stop,
+                Action::Limit(5),
+                Action::Move { to: "dir".into() },
+            ],
+            info,
+        };
+
+        let data = to_bencode(&sample).unwrap();
+        assert_eq!(
+            data,
+            &b"d7:actionsl4:Stopd5:Limiti5eed4:Moved2:to3:direee\
+               4:infod4:zzzzi1e4:aaaai2ee4:name4:name12:piece lengthi16384e\
+               6:pieces2:\x00\xff7:privatei1e5:tiersll1:a1:beee"[..]
+        );
+        assert_eq!(from_bencode::<Sample>(&data).unwrap(), sample);
+    }
+
+    #[test]
+    fn check_serde_errors() {
+        assert!(to_bencode(&1.5f64).is_err());
+        assert!(to_bencode(&vec![None, Some(1)]).is_err());
+        assert!(from_bencode::<Sample>(b"d4:name4:namee").is_err());
+        assert!(from_bencode::<String>(b"2:\xff\xff").is_err());
+        assert!(from_bencode::<u8>(b"i256e").is_err());
+        assert!(from_bencode::<i64>(b"i1").is_err());
+    }
+}
@@ -0,0 +1,375 @@
+use super::*;
+use crate::errors::BencodeError;
+use serde::ser::{self, Serialize};
+
+/// Serializes value to bencode with sorted dictionary keys.
+///
+/// `None` and unit values are skipped in structs and maps, as bencode has no null.
+pub fn to_bencode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, BencodeError> {
+    value
+        .serialize(BencodeSerializer)?
+        .map(|blob| blob.source)
+        .ok_or(BencodeError::Unsupported("empty value"))
+}
+
+/// Serializes into blob, `None` is a missing value.
+struct BencodeSerializer;
+
+fn string(value: &[u8]) -> Option<BencodeBlob> {
+    Some(BencodeValue::String(value.to_vec()).into())
+}
+
+fn integer(value: i64) -> Option<BencodeBlob> {
+    Some(BencodeValue::Integer(value).into())
+}
+
+/// Wraps value of enum variant into dictionary with variant name as a key.
+fn variant(variant: &'static str, value: Option<BencodeBlob>) -> Option<BencodeBlob> {
+    let entries = value
+        .map(|value| vec![(variant.as_bytes().to_vec(), value)])
+        .unwrap_or_default();
+    Some(BencodeValue::Dictionary(entries).into())
+}
+
+impl ser::Serializer for BencodeSerializer {
+    type Ok = Option<BencodeBlob>;
+    type Error = BencodeError;
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeVariant<SerializeList>;
+    type SerializeMap = SerializeDictionary;
+    type SerializeStruct = SerializeDictionary;
+    type SerializeStructVariant = SerializeVariant<SerializeDictionary>;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        Ok(integer(v as i64))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        Ok(integer(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        Ok(integer(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        Ok(integer(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        Ok(integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        Ok(integer(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        Ok(integer(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        Ok(integer(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        v.try_into()
+            .map(integer)
+            .map_err(|_| BencodeError::Unsupported("integer larger than i64"))
+    }
+
+    fn serialize_f32(self, _: f32) -> Result<Self::Ok, Self::Error> {
+        Err(BencodeError::Unsupported("float"))
+    }
+
+    fn serialize_f64(self, _: f64) -> Result<Self::Ok, Self::Error> {
+        Err(BencodeError::Unsupported("float"))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        Ok(string(v.encode_utf8(&mut [0; 4]).as_bytes()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        Ok(string(v.as_bytes()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        Ok(string(v))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(string(variant.as_bytes()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        if name != RAW_BLOB {
+            return value.serialize(self);
+        }
+        match value.serialize(self)? {
+            Some(BencodeBlob {
+                value: BencodeValue::String(source),
+                ..
+            }) => parse_bencode(&source)
+                .map(Some)
+                .map_err(|_| BencodeError::Parse),
+            _ => Err(BencodeError::Unsupported("raw bencode")),
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(variant(name, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Ok(SerializeList(Vec::with_capacity(len.unwrap_or_default())))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Ok(SerializeDictionary {
+            entries: Vec::with_capacity(len.unwrap_or_default()),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+struct SerializeList(Vec<BencodeBlob>);
+
+impl SerializeList {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), BencodeError> {
+        let item = value
+            .serialize(BencodeSerializer)?
+            .ok_or(BencodeError::Unsupported("empty value in list"))?;
+        self.0.push(item);
+        Ok(())
+    }
+
+    fn finish(self) -> Option<BencodeBlob> {
+        Some(BencodeValue::List(self.0).into())
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Option<BencodeBlob>;
+    type Error = BencodeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Option<BencodeBlob>;
+    type Error = BencodeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Option<BencodeBlob>;
+    type Error = BencodeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.finish())
+    }
+}
+
+struct SerializeDictionary {
+    entries: Vec<(Vec<u8>, BencodeBlob)>,
+    key: Option<Vec<u8>>,
+}
+
+impl SerializeDictionary {
+    fn insert<T: Serialize + ?Sized>(
+        &mut self,
+        key: Vec<u8>,
+        value: &T,
+    ) -> Result<(), BencodeError> {
+        if let Some(value) = value.serialize(BencodeSerializer)? {
+            self.entries.push((key, value));
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Option<BencodeBlob> {
+        Some(BencodeValue::Dictionary(self.entries).into())
+    }
+}
+
+impl ser::SerializeMap for SerializeDictionary {
+    type Ok = Option<BencodeBlob>;
+    type Error = BencodeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> {
+        match key.serialize(BencodeSerializer)? {
+            Some(BencodeBlob {
+                value: BencodeValue::String(key),
+                ..
+            }) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(BencodeError::Unsupported(
+                "dictionary key, which is not a string",
+            )),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| BencodeError::Message("value without key".into()))?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStruct for SerializeDictionary {
+    type Ok = Option<BencodeBlob>;
+    type Error = BencodeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.insert(key.as_bytes().to_vec(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.finish())
+    }
+}
+
+/// Enum variant with data, which is a dictionary with single entry.
+struct SerializeVariant<T> {
+    variant: &'static str,
+    inner: T,
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeList> {
+    type Ok = Option<BencodeBlob>;
+    type Error = BencodeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.inner.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(variant(self.variant, self.inner.finish()))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeDictionary> {
+    type Ok = Option<BencodeBlob>;
+    type Error = BencodeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.inner.insert(key.as_bytes().to_vec(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(variant(self.variant, self.inner.finish()))
+    }
+}
@@ -1,7 +1,9 @@
 use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
-use std::net::IpAddr;
+use std::{net::IpAddr, str::FromStr};
+
+pub use flat_storage::Preallocation;
 
 const PEER_PORT: &str = "6881";
 const PEER_PORT_MAX: &str = "6889";
@@ -33,6 +35,67 @@ pub struct Config {
     /// Storage path
     #[structopt(long, env = "RSBT_PATH_STORAGE")]
     pub storage: Option<String>,
+    /// Directory for files in progress, they are moved to download path when completed
+    #[structopt(long, env = "RSBT_PATH_INCOMPLETE")]
+    pub incomplete_dir: Option<String>,
+    /// Directory, which files may be shared by creating torrents besides download path
+    #[structopt(long, env = "RSBT_PATH_SHARE")]
+    pub share_dir: Option<String>,
+    /// Keeps files in progress with `.part` suffix, until they are completed
+    #[structopt(long, env = "RSBT_PART_SUFFIX")]
+    pub part_suffix: Option<bool>,
+    /// Default storage backend for torrent data: mmap, file, memory or uring
+    #[structopt(long, env = "RSBT_STORAGE_BACKEND")]
+    pub storage_backend: Option<StorageBackend>,
+    /// Count of threads for disk reads, writes and hash checks, default is count of CPUs
+    #[structopt(long, env = "RSBT_DISK_IO_THREADS")]
+    pub disk_io_threads: Option<usize>,
+    /// Count of disk jobs queued at once, further jobs wait for a free slot, default is 256
+    #[structopt(long, env = "RSBT_DISK_IO_QUEUE")]
+    pub disk_io_queue: Option<usize>,
+    /// Size of read cache for seeding in MiB, 0 disables cache, default is 64
+    #[structopt(long, env = "RSBT_READ_CACHE_SIZE")]
+    pub read_cache_size: Option<usize>,
+    /// Default preallocation of torrent files: sparse, full or none
+    #[structopt(long, env = "RSBT_PREALLOCATION")]
+    pub preallocation: Option<Preallocation>,
+    /// Count of files kept open by file storage backend for all torrents, default is 64
+    #[structopt(long, env = "RSBT_MAX_OPEN_FILES")]
+    pub max_open_files: Option<usize>,
+}
+
+/// How torrent data is accessed on disk
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Every file is mapped into memory
+    Mmap,
+    /// Files are accessed with positional reads and writes, only recently used files are open
+    File,
+    /// Data is kept in memory only and lost on exit
+    Memory,
+    /// Files are accessed with io_uring, requires `io-uring` feature and falls back to mmap
+    Uring,
+}
+
+impl Default for StorageBackend {
+    fn default() -> Self {
+        StorageBackend::Mmap
+    }
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mmap" => Ok(StorageBackend::Mmap),
+            "file" => Ok(StorageBackend::File),
+            "memory" => Ok(StorageBackend::Memory),
+            "uring" => Ok(StorageBackend::Uring),
+            _ => Err(format!("unknown storage backend: {}", s)),
+        }
+    }
 }
 
 /// Global application settings
@@ -40,6 +103,8 @@ pub struct Config {
 pub struct Settings {
     pub config: Config,
     pub peers: Peers,
+    #[serde(default)]
+    pub limits: Limits,
 }
 
 #[derive(Default, Serialize, Deserialize, Debug)]
@@ -48,6 +113,43 @@ pub struct Peers {
     max_connections: Option<usize>,
 }
 
+/// Bandwidth limits in bytes per second
+///
+/// Missing (or zero) value means unlimited.
+#[derive(Default, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Limits {
+    /// Download rate for all torrents
+    pub download_rate: Option<u64>,
+    /// Upload rate for all torrents
+    pub upload_rate: Option<u64>,
+    /// Download rate for every single torrent
+    pub torrent_download_rate: Option<u64>,
+    /// Upload rate for every single torrent
+    pub torrent_upload_rate: Option<u64>,
+    /// Download rate for every single peer connection
+    pub peer_download_rate: Option<u64>,
+    /// Upload rate for every single peer connection
+    pub peer_upload_rate: Option<u64>,
+    /// Alternative global rates used during configured time of day
+    pub alternative: Option<AlternativeLimits>,
+}
+
+/// Alternative global bandwidth limits, active between `from` and `to`
+///
+/// Time of day is local time in `HH:MM` format. If `from` is later than `to`, period continues
+/// past midnight.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AlternativeLimits {
+    /// Download rate for all torrents
+    pub download_rate: Option<u64>,
+    /// Upload rate for all torrents
+    pub upload_rate: Option<u64>,
+    /// Start of period
+    pub from: String,
+    /// End of period
+    pub to: String,
+}
+
 impl Settings {
     pub fn override_with(self, config: Config) -> Self {
         Self { config, ..self }
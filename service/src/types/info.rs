@@ -1,9 +1,20 @@
 use super::*;
-use crate::count_parts;
+use crate::{
+    count_parts,
+    errors::TorrentInfoError,
+    types::merkle::{block_hashes, merkle_root, pad_hash, MerkleHash},
+};
 use flat_storage::FlatStorageFile as TorrentInfoFile;
 use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::{HashMap, HashSet},
+    iter,
+    path::{Component, Path, PathBuf},
+    slice,
+};
 
-use crate::{BLOCK_SIZE, SHA1_SIZE};
+use crate::{BLOCK_SIZE, SHA1_SIZE, SHA256_SIZE};
 
 /// Normalized info from torrent.
 #[derive(Debug, PartialEq, Clone, Deserialize)]
@@ -15,6 +26,34 @@ pub struct TorrentInfo {
     pub pieces: Vec<Piece>,
     pub length: usize,
     pub files: Vec<TorrentInfoFile>,
+    pub attributes: Vec<TorrentInfoFileAttributes>,
+    pub version: TorrentVersion,
+    /// Piece layers of v2 files, which are larger than piece, by pieces root
+    pub piece_layers: HashMap<MerkleHash, Vec<MerkleHash>>,
+}
+
+/// Protocol version of torrent (BEP 52).
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+pub enum TorrentVersion {
+    /// Pieces are verified with SHA-1 hashes
+    V1,
+    /// Files are verified with SHA-256 merkle trees, files are aligned to pieces
+    V2,
+    /// Both v1 and v2 info, v1 pieces are used to stay compatible with v1 peers
+    Hybrid,
+}
+
+/// Attributes of torrent file (BEP 47), pad files are marked in [`TorrentInfoFile`].
+#[derive(Debug, PartialEq, Clone, Default, Deserialize)]
+pub struct TorrentInfoFileAttributes {
+    /// File is executable
+    pub executable: bool,
+    /// File is hidden
+    pub hidden: bool,
+    /// File is a symlink to this path, relative to directory of symlink
+    pub symlink: Option<PathBuf>,
+    /// SHA-1 hash of file content
+    pub sha1: Option<[u8; SHA1_SIZE]>,
 }
 
 impl TorrentInfo {
@@ -43,35 +82,80 @@ impl TorrentInfo {
     }
 }
 
-impl From<TorrentInfoRaw> for TorrentInfo {
-    fn from(raw: TorrentInfoRaw) -> Self {
-        let pieces = raw
-            .pieces
-            .as_slice()
-            .chunks_exact(SHA1_SIZE)
-            .map(|x| Piece(x.try_into().unwrap()))
-            .collect();
+impl TryFrom<TorrentInfoRaw> for TorrentInfo {
+    type Error = TorrentInfoError;
 
-        let length = raw.len();
+    fn try_from(raw: TorrentInfoRaw) -> Result<Self, Self::Error> {
+        Self::from_raw(raw, None)
+    }
+}
 
-        let files = if let Some(length) = raw.length.map(|x| x.try_into().unwrap()) {
-            vec![TorrentInfoFile {
-                path: raw.name.into(),
-                length,
-            }]
-        } else if let Some(files) = raw.files {
-            files
-                .iter()
-                .map(|TorrentInfoFileRaw { path, length }| TorrentInfoFile {
-                    path: path.iter().collect(),
-                    length: *length as usize,
-                })
-                .collect()
+impl TorrentInfo {
+    /// Builds info from info block and `piece layers` of torrent.
+    ///
+    /// Version is taken from `meta version`: v2 torrents have only `file tree`, hybrid
+    /// torrents have also v1 `pieces` and files, which must describe the same files.
+    pub fn from_raw(
+        raw: TorrentInfoRaw,
+        piece_layers: Option<BencodeBlob>,
+    ) -> Result<Self, TorrentInfoError> {
+        if raw.piece_length <= 0 {
+            return Err(TorrentInfoError::InvalidPieceLength(raw.piece_length));
+        }
+        let piece_length = raw.piece_length as usize;
+
+        let version = match (raw.meta_version, &raw.pieces) {
+            (None, _) | (Some(1), _) => TorrentVersion::V1,
+            (Some(2), None) => TorrentVersion::V2,
+            (Some(2), Some(_)) => TorrentVersion::Hybrid,
+            (Some(meta_version), _) => {
+                return Err(TorrentInfoError::UnsupportedMetaVersion(meta_version))
+            }
+        };
+
+        let (files, attributes, pieces, piece_layers) = if version == TorrentVersion::V1 {
+            let (files, attributes) = files_v1(&raw)?;
+            (files, attributes, pieces_v1(&raw)?, HashMap::new())
         } else {
-            panic!();
+            if !piece_length.is_power_of_two() || piece_length < BLOCK_SIZE {
+                return Err(TorrentInfoError::InvalidPieceLength(raw.piece_length));
+            }
+            let file_tree = raw
+                .file_tree
+                .clone()
+                .ok_or_else(|| TorrentInfoError::InvalidFileTree(vec![]))?;
+            let piece_layers = match piece_layers {
+                Some(piece_layers) => parse_piece_layers(piece_layers)?,
+                None => HashMap::new(),
+            };
+            let ((files, attributes), pieces, piece_layers) =
+                files_v2(file_tree, piece_length, piece_layers)?;
+            if version == TorrentVersion::V2 {
+                (files, attributes, pieces, piece_layers)
+            } else {
+                let (v1_files, v1_attributes) = files_v1(&raw)?;
+                let real_files = |files: &[TorrentInfoFile]| -> Vec<(PathBuf, usize)> {
+                    files
+                        .iter()
+                        .filter(|x| !x.pad)
+                        .map(|x| (x.path.clone(), x.length))
+                        .collect()
+                };
+                if real_files(&files) != real_files(&v1_files) {
+                    return Err(TorrentInfoError::HybridMismatch);
+                }
+                (v1_files, v1_attributes, pieces_v1(&raw)?, piece_layers)
+            }
         };
+        check_unique_paths(&files)?;
 
-        let piece_length = raw.piece_length as usize;
+        let length = files
+            .iter()
+            .try_fold(0usize, |length, x| length.checked_add(x.length))
+            .ok_or(TorrentInfoError::TooLarge)?;
+        if pieces.len() != count_parts(length, piece_length) {
+            return Err(TorrentInfoError::InvalidPieces);
+        }
 
         let default_blocks_count = count_parts(piece_length, BLOCK_SIZE);
 
@@ -82,7 +166,7 @@ impl From<TorrentInfoRaw> for TorrentInfo {
 
         let last_piece_blocks_count = count_parts(last_piece_length, BLOCK_SIZE);
 
-        Self {
+        Ok(Self {
             piece_length,
             default_blocks_count,
             last_piece_length,
@@ -90,18 +174,364 @@ impl From<TorrentInfoRaw> for TorrentInfo {
             pieces,
             length,
             files,
+            attributes,
+            version,
+            piece_layers,
+        })
+    }
+}
+
+type InfoFiles = (Vec<TorrentInfoFile>, Vec<TorrentInfoFileAttributes>);
+
+type PieceLayers = HashMap<MerkleHash, Vec<MerkleHash>>;
+
+fn files_v1(raw: &TorrentInfoRaw) -> Result<InfoFiles, TorrentInfoError> {
+    if let Some(length) = raw.length {
+        Ok(vec![info_file(
+            slice::from_ref(&raw.name),
+            length,
+            raw.attr.as_deref(),
+            None,
+            raw.sha1.as_deref(),
+        )?]
+        .into_iter()
+        .unzip())
+    } else if let Some(files) = &raw.files {
+        Ok(files
+            .iter()
+            .map(|file| {
+                info_file(
+                    &file.path,
+                    file.length,
+                    file.attr.as_deref(),
+                    file.symlink_path.as_deref(),
+                    file.sha1.as_deref(),
+                )
+            })
+            .collect::<Result<Vec<_>, TorrentInfoError>>()?
+            .into_iter()
+            .unzip())
+    } else {
+        Err(TorrentInfoError::NoFiles)
+    }
+}
+
+fn pieces_v1(raw: &TorrentInfoRaw) -> Result<Vec<Piece>, TorrentInfoError> {
+    let pieces = raw.pieces.as_ref().ok_or(TorrentInfoError::NoPieces)?;
+    if pieces.len() % SHA1_SIZE != 0 {
+        return Err(TorrentInfoError::InvalidPieces);
+    }
+    pieces
+        .chunks_exact(SHA1_SIZE)
+        .map(|x| {
+            x.try_into()
+                .map(Piece::Sha1)
+                .map_err(|_| TorrentInfoError::InvalidPieces)
+        })
+        .collect()
+}
+
+/// Flattens v2 file tree into files and pieces.
+///
+/// Every file starts at piece boundary, so pad files are inserted after files, which are
+/// not aligned, except the last one. Pieces of files larger than piece are taken from
+/// piece layers, which must match pieces root of file, smaller files have single piece
+/// verified against pieces root.
+fn files_v2(
+    file_tree: BencodeBlob,
+    piece_length: usize,
+    mut piece_layers: PieceLayers,
+) -> Result<(InfoFiles, Vec<Piece>, PieceLayers), TorrentInfoError> {
+    let mut entries = vec![];
+    parse_file_tree(file_tree, &mut vec![], &mut entries)?;
+    if entries.is_empty() {
+        return Err(TorrentInfoError::NoFiles);
+    }
+
+    let piece_blocks = piece_length / BLOCK_SIZE;
+    let layer_pad = pad_hash(piece_blocks.trailing_zeros() as usize);
+
+    let mut files = vec![];
+    let mut attributes = vec![];
+    let mut pieces = vec![];
+    let mut used_layers = HashMap::new();
+    let last_entry = entries.len() - 1;
+    for (i, (path, entry)) in entries.into_iter().enumerate() {
+        let (file, file_attributes) = info_file(
+            &path,
+            entry.length,
+            entry.attr.as_deref(),
+            entry.symlink_path.as_deref(),
+            None,
+        )?;
+        let length = file.length;
+
+        if length > 0 && file_attributes.symlink.is_none() {
+            let pieces_root: MerkleHash = entry
+                .pieces_root
+                .as_deref()
+                .and_then(|x| x.try_into().ok())
+                .ok_or_else(|| TorrentInfoError::InvalidFileTree(path.clone()))?;
+            if length <= piece_length {
+                pieces.push(Piece::Sha256 {
+                    hash: pieces_root,
+                    length,
+                    leaves: count_parts(length, BLOCK_SIZE),
+                });
+            } else {
+                let layer = match piece_layers.remove(&pieces_root) {
+                    Some(layer) => layer,
+                    None => used_layers
+                        .get(&pieces_root)
+                        .cloned()
+                        .ok_or_else(|| TorrentInfoError::PieceLayerMismatch(file.path.clone()))?,
+                };
+                let count = count_parts(length, piece_length);
+                if layer.len() != count || merkle_root(&layer, count, layer_pad) != pieces_root {
+                    return Err(TorrentInfoError::PieceLayerMismatch(file.path));
+                }
+                pieces.extend(layer.iter().enumerate().map(|(index, hash)| Piece::Sha256 {
+                    hash: *hash,
+                    length: piece_length.min(length - index * piece_length),
+                    leaves: piece_blocks,
+                }));
+                used_layers.insert(pieces_root, layer);
+            }
+        }
+
+        let pad_length = (piece_length - length % piece_length) % piece_length;
+        files.push(file);
+        attributes.push(file_attributes);
+        if i != last_entry && pad_length > 0 {
+            files.push(TorrentInfoFile {
+                path: PathBuf::from(".pad").join(pad_length.to_string()),
+                length: pad_length,
+                pad: true,
+            });
+            attributes.push(TorrentInfoFileAttributes::default());
         }
     }
+
+    Ok(((files, attributes), pieces, used_layers))
 }
 
-#[derive(Debug, PartialEq, Clone, Deserialize)]
-pub struct Piece([u8; SHA1_SIZE]);
+/// Collects files of v2 file tree in order with their paths.
+///
+/// Directories are dictionaries by name, file is a dictionary with empty key.
+fn parse_file_tree(
+    blob: BencodeBlob,
+    path: &mut Vec<String>,
+    entries: &mut Vec<(Vec<String>, TorrentInfoFileTreeRaw)>,
+) -> Result<(), TorrentInfoError> {
+    let dictionary: Vec<(String, BencodeBlob)> = blob
+        .try_into()
+        .map_err(|_| TorrentInfoError::InvalidFileTree(path.clone()))?;
+    for (name, value) in dictionary {
+        if name.is_empty() {
+            if path.is_empty() {
+                return Err(TorrentInfoError::InvalidFileTree(vec![]));
+            }
+            let entry = value
+                .try_into()
+                .map_err(|_| TorrentInfoError::InvalidFileTree(path.clone()))?;
+            entries.push((path.clone(), entry));
+        } else {
+            path.push(name);
+            parse_file_tree(value, path, entries)?;
+            path.pop();
+        }
+    }
+    Ok(())
+}
+
+/// Splits piece layers into hashes by pieces root.
+fn parse_piece_layers(blob: BencodeBlob) -> Result<PieceLayers, TorrentInfoError> {
+    let dictionary: Vec<(Vec<u8>, BencodeBlob)> = blob
+        .try_into()
+        .map_err(|_| TorrentInfoError::InvalidPieceLayers)?;
+    dictionary
+        .into_iter()
+        .map(|(root, layer)| {
+            let root = root
+                .as_slice()
+                .try_into()
+                .map_err(|_| TorrentInfoError::InvalidPieceLayers)?;
+            let layer: Vec<u8> = layer
+                .try_into()
+                .map_err(|_| TorrentInfoError::InvalidPieceLayers)?;
+            if layer.len() % SHA256_SIZE != 0 {
+                return Err(TorrentInfoError::InvalidPieceLayers);
+            }
+            let layer = layer
+                .chunks_exact(SHA256_SIZE)
+                .map(|x| {
+                    x.try_into()
+                        .map_err(|_| TorrentInfoError::InvalidPieceLayers)
+                })
+                .collect::<Result<_, _>>()?;
+            Ok((root, layer))
+        })
+        .collect()
+}
+
+/// Names of devices, which cannot be used as file names on Windows, with any extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters, which cannot be used in file names on Windows.
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Builds file and its attributes from file entry of torrent.
+///
+/// Attributes are parsed from `attr` string: `p` is pad file, `l` is symlink to
+/// `symlink_path`, `x` is executable and `h` is hidden file, unknown attributes are ignored.
+fn info_file(
+    path: &[String],
+    length: i64,
+    attr: Option<&str>,
+    symlink_path: Option<&[String]>,
+    sha1: Option<&[u8]>,
+) -> Result<(TorrentInfoFile, TorrentInfoFileAttributes), TorrentInfoError> {
+    let attr = attr.unwrap_or_default();
+    let file = TorrentInfoFile {
+        path: sanitize_path(path)?,
+        length: file_length(length)?,
+        pad: attr.contains('p'),
+    };
+    let symlink = if attr.contains('l') {
+        let target = symlink_path
+            .ok_or_else(|| TorrentInfoError::InvalidSymlink(path.to_vec()))
+            .and_then(sanitize_path)?;
+        let depth = file.path.components().count() - 1;
+        Some(
+            iter::repeat(Component::ParentDir)
+                .take(depth)
+                .collect::<PathBuf>()
+                .join(target),
+        )
+    } else {
+        None
+    };
+    let attributes = TorrentInfoFileAttributes {
+        executable: attr.contains('x'),
+        hidden: attr.contains('h'),
+        symlink,
+        sha1: sha1.and_then(|x| x.try_into().ok()),
+    };
+    Ok((file, attributes))
+}
+
+fn file_length(length: i64) -> Result<usize, TorrentInfoError> {
+    length
+        .try_into()
+        .map_err(|_| TorrentInfoError::InvalidFileLength(length))
+}
+
+/// Builds file path relative to download directory from path list of torrent.
+///
+/// Torrent is rejected if any element can escape download directory: `.`, `..`, path
+/// separators or absolute paths. Empty elements are skipped, and names, which cannot be
+/// created on disk, are sanitized with [`sanitize_name`].
+fn sanitize_path(elements: &[String]) -> Result<PathBuf, TorrentInfoError> {
+    let mut path = PathBuf::new();
+    for element in elements.iter().filter(|x| !x.is_empty()) {
+        if element == "." || element == ".." || element.contains(&['/', '\\'][..]) {
+            return Err(TorrentInfoError::UnsafePath(elements.to_vec()));
+        }
+        let name = sanitize_name(element, cfg!(windows));
+        let mut components = Path::new(&name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => path.push(name),
+            _ => return Err(TorrentInfoError::UnsafePath(elements.to_vec())),
+        }
+    }
+    if path.as_os_str().is_empty() {
+        Err(TorrentInfoError::EmptyPath)
+    } else {
+        Ok(path)
+    }
+}
+
+/// Replaces control characters with `_`.
+///
+/// With `windows` also replaces reserved characters, trims trailing dots and spaces,
+/// and appends `_` to reserved device names, e.g. `con.txt` becomes `con_.txt`.
+fn sanitize_name(name: &str, windows: bool) -> String {
+    let mut name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || (windows && RESERVED_CHARS.contains(&c)) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if windows {
+        name.truncate(name.trim_end_matches(&['.', ' '][..]).len());
+        if name.is_empty() {
+            name.push('_');
+        }
+        let stem_length = name.find('.').unwrap_or(name.len());
+        if RESERVED_NAMES
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case(&name[..stem_length]))
+        {
+            name.insert(stem_length, '_');
+        }
+    }
+    name
+}
+
+/// Checks that every file has own path, and no file path is a directory of other file.
+///
+/// Pad files are not created on disk, so they can share paths.
+fn check_unique_paths(files: &[TorrentInfoFile]) -> Result<(), TorrentInfoError> {
+    let mut file_paths = HashSet::new();
+    let mut dir_paths = HashSet::new();
+    for file in files.iter().filter(|x| !x.pad) {
+        if dir_paths.contains(&file.path) || !file_paths.insert(file.path.as_path()) {
+            return Err(TorrentInfoError::DuplicatePath(file.path.clone()));
+        }
+        for dir in file.path.ancestors().skip(1) {
+            if file_paths.contains(dir) {
+                return Err(TorrentInfoError::DuplicatePath(dir.to_path_buf()));
+            }
+            dir_paths.insert(dir.to_path_buf());
+        }
+    }
+    Ok(())
+}
 
-impl TryFrom<&[u8]> for Piece {
-    type Error = std::array::TryFromSliceError;
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub enum Piece {
+    /// SHA-1 hash of piece data (v1)
+    Sha1([u8; SHA1_SIZE]),
+    /// Merkle root of piece blocks (v2), piece has `length` bytes of file and the rest
+    /// is padding, tree has `leaves` blocks padded with zero hashes.
+    Sha256 {
+        hash: MerkleHash,
+        length: usize,
+        leaves: usize,
+    },
+}
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        Ok(Piece(value.try_into()?))
+impl Piece {
+    /// Checks downloaded data of piece against its hash.
+    pub fn verify(&self, data: &[u8]) -> bool {
+        match self {
+            Piece::Sha1(hash) => Sha1::digest(data)[..] == hash[..],
+            Piece::Sha256 {
+                hash,
+                length,
+                leaves,
+            } => match data.get(..*length) {
+                Some(data) => merkle_root(&block_hashes(data), *leaves, [0; SHA256_SIZE]) == *hash,
+                None => false,
+            },
+        }
     }
 }
 
@@ -109,15 +539,22 @@ impl TryFrom<&[u8]> for Piece {
 pub struct TorrentInfoRaw {
     pub name: String,
     pub piece_length: i64,
-    pub pieces: Vec<u8>,
+    pub pieces: Option<Vec<u8>>,
     pub length: Option<i64>,
     pub files: Option<Vec<TorrentInfoFileRaw>>,
+    pub attr: Option<String>,
+    pub sha1: Option<Vec<u8>>,
+    pub meta_version: Option<i64>,
+    pub file_tree: Option<BencodeBlob>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct TorrentInfoFileRaw {
     pub length: i64,
     pub path: Vec<String>,
+    pub attr: Option<String>,
+    pub symlink_path: Option<Vec<String>>,
+    pub sha1: Option<Vec<u8>>,
 }
 
 impl TorrentInfoRaw {
@@ -125,41 +562,52 @@ impl TorrentInfoRaw {
     ///
     /// For single file torrent it is the size of this file.
     /// For multi files torrent it is the sum of all file sizes.
+    /// Info block without length and files is empty.
     pub fn len(&self) -> usize {
-        if let Some(len) = self.length {
-            len as usize
+        let len = if let Some(len) = self.length {
+            len
         } else if let Some(files) = &self.files {
-            files.iter().map(|x| x.length).sum::<i64>() as usize
+            files
+                .iter()
+                .fold(0i64, |len, x| len.saturating_add(x.length.max(0)))
         } else {
-            panic!("Wrong torrent info block");
-        }
+            0
+        };
+        len.max(0) as usize
     }
 
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
 
-    /// Count of pieces in torrent.
+    /// Count of v1 pieces in torrent.
     pub fn pieces_count(&self) -> usize {
-        self.pieces.len() / SHA1_SIZE
+        self.pieces
+            .as_ref()
+            .map(|x| x.len() / SHA1_SIZE)
+            .unwrap_or_default()
     }
 
-    /// Piece by index.
+    /// v1 piece by index.
     pub fn piece(&self, index: usize) -> Option<&[u8]> {
         let index = index * SHA1_SIZE;
-        self.pieces.get(index..index + SHA1_SIZE)
+        self.pieces.as_ref()?.get(index..index + SHA1_SIZE)
     }
 }
 
 try_from_bencode!(TorrentInfoRaw,
     normal: (
         "name" => name,
-        "piece length" => piece_length,
-        "pieces" => pieces
+        "piece length" => piece_length
     ),
     optional: (
+        "pieces" => pieces,
         "length" => length,
-        "files" => files
+        "files" => files,
+        "attr" => attr,
+        "sha1" => sha1,
+        "meta version" => meta_version,
+        "file tree" => file_tree
     ),
 );
 
@@ -168,15 +616,40 @@ try_from_bencode!(TorrentInfoFileRaw,
         "length" => length,
         "path" => path
     ),
+    optional: (
+        "attr" => attr,
+        "symlink path" => symlink_path,
+        "sha1" => sha1
+    ),
+);
+
+/// File of v2 file tree.
+#[derive(Debug, PartialEq)]
+pub struct TorrentInfoFileTreeRaw {
+    pub length: i64,
+    pub pieces_root: Option<Vec<u8>>,
+    pub attr: Option<String>,
+    pub symlink_path: Option<Vec<String>>,
+}
+
+try_from_bencode!(TorrentInfoFileTreeRaw,
+    normal: (
+        "length" => length
+    ),
+    optional: (
+        "pieces root" => pieces_root,
+        "attr" => attr,
+        "symlink path" => symlink_path
+    ),
 );
 
 impl TryFrom<BencodeBlob> for Vec<TorrentInfoFileRaw> {
-    type Error = TryFromBencode;
+    type Error = RsbtError;
 
     fn try_from(blob: BencodeBlob) -> Result<Self, Self::Error> {
         match blob.value {
-            BencodeValue::List(l) => Ok(l.into_iter().map(|x| x.try_into().unwrap()).collect()),
-            _ => Err(TryFromBencode::NotList),
+            BencodeValue::List(l) => l.into_iter().map(TryInto::try_into).collect(),
+            _ => Err(TryFromBencode::NotList.into()),
         }
     }
 }
@@ -190,9 +663,13 @@ mod tests {
         let torrent_info = TorrentInfoRaw {
             name: "torrent_info".into(),
             piece_length: 10,
-            pieces: b"a123456789b123456789c123456789d123456789".to_vec(),
+            pieces: Some(b"a123456789b123456789c123456789d123456789".to_vec()),
             length: Some(100),
             files: None,
+            attr: None,
+            sha1: None,
+            meta_version: None,
+            file_tree: None,
         };
         assert_eq!(torrent_info.pieces_count(), 2);
         assert_eq!(
@@ -205,4 +682,289 @@ mod tests {
         );
         assert_eq!(torrent_info.piece(2), None);
     }
+
+    fn crafted_torrent(name: &str, info: &str) -> Vec<u8> {
+        format!(
+            "d8:announce17:http://localhost/4:infod{}4:name{}:{}12:piece lengthi16384e6:pieces20:{}ee",
+            info,
+            name.len(),
+            name,
+            "0".repeat(SHA1_SIZE)
+        )
+        .into_bytes()
+    }
+
+    fn crafted_files(paths: &[&[&str]]) -> Vec<u8> {
+        let files: String = paths
+            .iter()
+            .map(|path| {
+                let path: String = path.iter().map(|x| format!("{}:{}", x.len(), x)).collect();
+                format!("d6:lengthi1e4:pathl{}ee", path)
+            })
+            .collect();
+        crafted_torrent("test", &format!("5:filesl{}e", files))
+    }
+
+    fn info(torrent: Vec<u8>) -> Result<TorrentInfo, RsbtError> {
+        crate::types::torrent::parse_torrent(&torrent)?.info()
+    }
+
+    #[test]
+    fn accept_safe_paths() {
+        let info = info(crafted_files(&[
+            &["dir", "a"],
+            &["dir", "", "b"],
+            &["c\n"],
+            &["..hidden"],
+        ]))
+        .unwrap();
+        let paths: Vec<_> = info.files.iter().map(|x| x.path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("dir").join("a"),
+                PathBuf::from("dir").join("b"),
+                PathBuf::from("c_"),
+                PathBuf::from("..hidden"),
+            ]
+        );
+        assert_eq!(info.len(), 4);
+    }
+
+    #[test]
+    fn reject_unsafe_paths() {
+        for path in &[
+            &["..", "etc", "passwd"][..],
+            &["dir", "..", "..", "passwd"],
+            &["."],
+            &["/etc", "passwd"],
+            &["dir/../../passwd"],
+            &["..\\passwd"],
+        ] {
+            match info(crafted_files(&[path])) {
+                Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::UnsafePath(_))) => (),
+                res => panic!("unexpected result for {:?}: {:?}", path, res),
+            }
+        }
+
+        match info(crafted_torrent("../passwd", "6:lengthi1e")) {
+            Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::UnsafePath(_))) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn reject_invalid_files() {
+        match info(crafted_files(&[&[""]])) {
+            Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::EmptyPath)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match info(crafted_files(&[&["a"], &["b"], &["a"]])) {
+            Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::DuplicatePath(_))) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match info(crafted_files(&[&["a", "b"], &["a"]])) {
+            Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::DuplicatePath(_))) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match info(crafted_files(&[&["a"], &["a", "b"]])) {
+            Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::DuplicatePath(_))) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match info(crafted_torrent("test", "6:lengthi-1e")) {
+            Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::InvalidFileLength(-1))) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match info(crafted_torrent("test", "")) {
+            Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::NoFiles)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match info(crafted_torrent("test", "6:lengthi16385e")) {
+            Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::InvalidPieces)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match info(crafted_torrent("test", "5:filesli1ee")) {
+            Err(RsbtError::TryFromBencode(TryFromBencode::NotDictionary)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let torrent = String::from_utf8(crafted_torrent("", "6:lengthi1e")).unwrap();
+        match info(torrent.replace("4:name0:", "").into_bytes()) {
+            Err(RsbtError::TryFromBencode(TryFromBencode::MissingField("name"))) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn file_attributes() {
+        let torrent_info = info(crafted_torrent(
+            "test",
+            &format!(
+                "5:filesl{}{}{}{}e",
+                "d6:lengthi1e4:pathl3:bin3:rune4:attr1:x4:sha120:01234567890123456789e",
+                "d6:lengthi2e4:pathl4:.pad1:2e4:attr1:pe",
+                "d6:lengthi0e4:pathl3:bin4:linke4:attr1:l12:symlink pathl3:bin3:runee",
+                "d6:lengthi1e4:pathl6:hiddene4:attr2:hqe",
+            ),
+        ))
+        .unwrap();
+        assert_eq!(
+            torrent_info.files.iter().map(|x| x.pad).collect::<Vec<_>>(),
+            vec![false, true, false, false]
+        );
+        assert_eq!(
+            torrent_info.attributes,
+            vec![
+                TorrentInfoFileAttributes {
+                    executable: true,
+                    sha1: Some(*b"01234567890123456789"),
+                    ..Default::default()
+                },
+                TorrentInfoFileAttributes::default(),
+                TorrentInfoFileAttributes {
+                    symlink: Some(PathBuf::from("..").join("bin").join("run")),
+                    ..Default::default()
+                },
+                TorrentInfoFileAttributes {
+                    hidden: true,
+                    ..Default::default()
+                },
+            ]
+        );
+
+        for symlink in &["", "12:symlink pathl2:..6:passwde"] {
+            match info(crafted_torrent(
+                "test",
+                &format!("5:filesld6:lengthi0e4:pathl4:linke4:attr1:l{}ee", symlink),
+            )) {
+                Err(RsbtError::InvalidTorrentInfo(_)) => (),
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn sanitize_windows_names() {
+        assert_eq!(sanitize_name("con", true), "con_");
+        assert_eq!(sanitize_name("LPT1.tar.gz", true), "LPT1_.tar.gz");
+        assert_eq!(sanitize_name("console.txt", true), "console.txt");
+        assert_eq!(sanitize_name("a:b?.", true), "a_b_");
+        assert_eq!(sanitize_name("...", true), "_");
+        assert_eq!(sanitize_name("con", false), "con");
+        assert_eq!(sanitize_name("a:b\u{0}", false), "a:b_");
+    }
+
+    fn crafted_v2_torrent(
+        files: &[(&str, usize, MerkleHash)],
+        v1_info: &str,
+        piece_layers: &[(MerkleHash, Vec<MerkleHash>)],
+    ) -> Vec<u8> {
+        let mut torrent = b"d8:announce17:http://localhost/4:infod".to_vec();
+        torrent.extend_from_slice(v1_info.as_bytes());
+        torrent.extend_from_slice(b"9:file treed");
+        for (name, length, root) in files {
+            torrent.extend_from_slice(
+                format!(
+                    "{}:{}d0:d6:lengthi{}e11:pieces root32:",
+                    name.len(),
+                    name,
+                    length
+                )
+                .as_bytes(),
+            );
+            torrent.extend_from_slice(root);
+            torrent.extend_from_slice(b"ee");
+        }
+        torrent.extend_from_slice(b"e12:meta versioni2e4:name4:test12:piece lengthi32768ee");
+        torrent.extend_from_slice(b"12:piece layersd");
+        for (root, layer) in piece_layers {
+            torrent.extend_from_slice(b"32:");
+            torrent.extend_from_slice(root);
+            torrent.extend_from_slice(format!("{}:", layer.len() * SHA256_SIZE).as_bytes());
+            torrent.extend_from_slice(&layer.concat());
+        }
+        torrent.extend_from_slice(b"ee");
+        torrent
+    }
+
+    #[test]
+    fn v2_files() {
+        let piece_length = 2 * BLOCK_SIZE;
+        let data_a = vec![1; 2 * piece_length + 100];
+        let data_b = vec![2; 10];
+        let layer: Vec<_> = data_a
+            .chunks(piece_length)
+            .map(|x| merkle_root(&block_hashes(x), 2, [0; SHA256_SIZE]))
+            .collect();
+        let root_a = merkle_root(&layer, layer.len(), pad_hash(1));
+        let root_b = merkle_root(&block_hashes(&data_b), 1, [0; SHA256_SIZE]);
+        let files = [("a", data_a.len(), root_a), ("b", data_b.len(), root_b)];
+
+        let torrent_info =
+            info(crafted_v2_torrent(&files, "", &[(root_a, layer.clone())])).unwrap();
+        assert_eq!(torrent_info.version, TorrentVersion::V2);
+        assert_eq!(
+            torrent_info
+                .files
+                .iter()
+                .map(|x| (x.path.clone(), x.length, x.pad))
+                .collect::<Vec<_>>(),
+            vec![
+                (PathBuf::from("a"), data_a.len(), false),
+                (PathBuf::from(".pad").join("32668"), 32668, true),
+                (PathBuf::from("b"), data_b.len(), false),
+            ]
+        );
+        assert_eq!(torrent_info.pieces.len(), 4);
+        assert_eq!(torrent_info.len(), 3 * piece_length + data_b.len());
+        assert_eq!(torrent_info.piece_layers.get(&root_a), Some(&layer));
+
+        let mut last_piece = data_a[2 * piece_length..].to_vec();
+        last_piece.resize(piece_length, 0);
+        assert!(torrent_info.pieces[0].verify(&data_a[..piece_length]));
+        assert!(torrent_info.pieces[2].verify(&last_piece));
+        assert!(torrent_info.pieces[3].verify(&data_b));
+        assert!(!torrent_info.pieces[1].verify(&last_piece));
+        assert!(!torrent_info.pieces[3].verify(&data_b[1..]));
+
+        let mut wrong_layer = layer.clone();
+        wrong_layer.swap(0, 2);
+        for piece_layers in &[vec![], vec![(root_a, wrong_layer)]] {
+            match info(crafted_v2_torrent(&files, "", piece_layers)) {
+                Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::PieceLayerMismatch(_))) => (),
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn hybrid_files() {
+        let root = merkle_root(&block_hashes(&[1; 10]), 1, [0; SHA256_SIZE]);
+        let v1_info = |length: usize| {
+            format!(
+                "5:filesld6:lengthi{}e4:pathl1:aeee6:pieces20:{}",
+                length,
+                "0".repeat(SHA1_SIZE)
+            )
+        };
+
+        let torrent = crafted_v2_torrent(&[("a", 10, root)], &v1_info(10), &[]);
+        let torrent_info = info(torrent.clone()).unwrap();
+        assert_eq!(torrent_info.version, TorrentVersion::Hybrid);
+        assert_eq!(torrent_info.pieces, vec![Piece::Sha1([b'0'; SHA1_SIZE])]);
+
+        let torrent = crate::types::torrent::parse_torrent(&torrent).unwrap();
+        assert_eq!(
+            torrent.info_hash(TorrentVersion::Hybrid),
+            torrent.info_sha1_hash()
+        );
+        assert_eq!(
+            torrent.info_hash(TorrentVersion::V2)[..],
+            torrent.info_sha256_hash()[..SHA1_SIZE]
+        );
+
+        match info(crafted_v2_torrent(&[("a", 10, root)], &v1_info(11), &[])) {
+            Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::HybridMismatch)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
 }
@@ -0,0 +1,140 @@
+//! Merkle hash trees of BitTorrent v2 (BEP 52).
+//!
+//! Leaves are SHA-256 hashes of 16 KiB blocks, the last block of file can be shorter.
+//! Layers are padded up to power of two with hashes of zero filled subtrees.
+use crate::{BLOCK_SIZE, SHA256_SIZE};
+use sha2::{Digest, Sha256};
+
+pub type MerkleHash = [u8; SHA256_SIZE];
+
+pub(crate) fn sha256(data: &[u8]) -> MerkleHash {
+    let mut hash = [0; SHA256_SIZE];
+    hash.copy_from_slice(&Sha256::digest(data));
+    hash
+}
+
+fn sha256_pair(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.input(left);
+    hasher.input(right);
+    let mut hash = [0; SHA256_SIZE];
+    hash.copy_from_slice(&hasher.result());
+    hash
+}
+
+/// Hash of subtree with zero leaves, `layer` 0 is a leaf.
+pub(crate) fn pad_hash(layer: usize) -> MerkleHash {
+    (0..layer).fold([0; SHA256_SIZE], |hash, _| sha256_pair(&hash, &hash))
+}
+
+/// Hashes of blocks of data.
+pub(crate) fn block_hashes(data: &[u8]) -> Vec<MerkleHash> {
+    data.chunks(BLOCK_SIZE).map(sha256).collect()
+}
+
+/// Computes root of tree with `count` leaves, missing leaves are `pad`.
+///
+/// `count` is rounded up to power of two.
+pub(crate) fn merkle_root(leaves: &[MerkleHash], count: usize, pad: MerkleHash) -> MerkleHash {
+    let mut layer = leaves.to_vec();
+    let mut pad = pad;
+    let mut count = count.max(leaves.len()).next_power_of_two();
+    while count > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| sha256_pair(&pair[0], pair.get(1).unwrap_or(&pad)))
+            .collect();
+        pad = sha256_pair(&pad, &pad);
+        count /= 2;
+    }
+    layer.first().copied().unwrap_or(pad)
+}
+
+/// Returns `length` hashes of `layer` from `index` followed by up to `proof_layers` uncle
+/// hashes, which allow to verify them against root. `pad` is the hash of missing nodes of
+/// `layer`.
+///
+/// `length` must be a power of two and `index` must be a multiple of `length`.
+pub(crate) fn layer_proof(
+    layer: &[MerkleHash],
+    pad: MerkleHash,
+    index: usize,
+    length: usize,
+    proof_layers: usize,
+) -> Option<Vec<MerkleHash>> {
+    let count = layer.len().next_power_of_two();
+    if !length.is_power_of_two() || index % length != 0 || index.checked_add(length)? > count {
+        return None;
+    }
+    let mut hashes: Vec<_> = (index..index + length)
+        .map(|x| layer.get(x).copied().unwrap_or(pad))
+        .collect();
+
+    let subtree_height = length.trailing_zeros();
+    let mut nodes = layer.to_vec();
+    let mut pad = pad;
+    let mut height = 0;
+    while nodes.len() > 1 {
+        if height >= subtree_height && ((height - subtree_height) as usize) < proof_layers {
+            let uncle = (index >> height) ^ 1;
+            hashes.push(nodes.get(uncle).copied().unwrap_or(pad));
+        }
+        nodes = nodes
+            .chunks(2)
+            .map(|pair| sha256_pair(&pair[0], pair.get(1).unwrap_or(&pad)))
+            .collect();
+        pad = sha256_pair(&pad, &pad);
+        height += 1;
+    }
+    Some(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_merkle_root() {
+        assert_eq!(merkle_root(&[], 1, [0; SHA256_SIZE]), [0; SHA256_SIZE]);
+
+        let leaves = block_hashes(&[1; BLOCK_SIZE * 2 + 1]);
+        assert_eq!(leaves.len(), 3);
+        assert_eq!(merkle_root(&leaves[..1], 1, [0; SHA256_SIZE]), leaves[0]);
+
+        let root = sha256_pair(
+            &sha256_pair(&leaves[0], &leaves[1]),
+            &sha256_pair(&leaves[2], &[0; SHA256_SIZE]),
+        );
+        assert_eq!(merkle_root(&leaves, 3, [0; SHA256_SIZE]), root);
+        assert_eq!(
+            merkle_root(&leaves, 8, [0; SHA256_SIZE]),
+            sha256_pair(&root, &pad_hash(2))
+        );
+        assert_eq!(merkle_root(&leaves[2..], 4, [0; SHA256_SIZE]), {
+            let padded = merkle_root(&leaves[2..], 2, [0; SHA256_SIZE]);
+            sha256_pair(&padded, &pad_hash(1))
+        });
+    }
+
+    #[test]
+    fn check_layer_proof() {
+        let layer: Vec<_> = (0..5u8).map(|x| sha256(&[x])).collect();
+        let pad = pad_hash(1);
+        let root = merkle_root(&layer, layer.len(), pad);
+
+        let proof = layer_proof(&layer, pad, 4, 2, 10).unwrap();
+        assert_eq!(proof.len(), 4);
+        assert_eq!(&proof[..2], &[layer[4], pad]);
+        let subtree = sha256_pair(&proof[0], &proof[1]);
+        let node = sha256_pair(&subtree, &proof[2]);
+        assert_eq!(sha256_pair(&proof[3], &node), root);
+
+        assert_eq!(layer_proof(&layer, pad, 0, 8, 1).unwrap().len(), 8);
+        assert_eq!(layer_proof(&layer, pad, 0, 2, 1).unwrap()[2], {
+            sha256_pair(&layer[2], &layer[3])
+        });
+        assert_eq!(layer_proof(&layer, pad, 2, 4, 0), None);
+        assert_eq!(layer_proof(&layer, pad, 0, 3, 0), None);
+        assert_eq!(layer_proof(&layer, pad, 8, 2, 0), None);
+    }
+}
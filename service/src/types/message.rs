@@ -1,4 +1,4 @@
-use crate::parser::parser_message;
+use crate::{parser::parser_message, types::merkle::MerkleHash, SHA256_SIZE};
 use bytes::{Buf, BufMut, BytesMut};
 use failure::Fail;
 use nom::Offset;
@@ -84,6 +84,48 @@ pub enum Message {
     ///
     /// The port message is sent by newer versions of the Mainline that implements a DHT tracker. The listen port is the port this peer's DHT node is listening on. This peer should be inserted in the local routing table (if DHT tracker is supported).
     Port(u16),
+    /// hash request: <len=0049><id=21><pieces root><base layer><index><length><proof layers>
+    ///
+    /// Requests merkle tree hashes of file (BEP 52).
+    HashRequest(HashRequest),
+    /// hashes: <len=0049+X><id=22><pieces root><base layer><index><length><proof layers><hashes>
+    ///
+    /// Requested hashes followed by uncle hashes of proof layers.
+    Hashes {
+        request: HashRequest,
+        hashes: Vec<MerkleHash>,
+    },
+    /// hash reject: <len=0049><id=23><pieces root><base layer><index><length><proof layers>
+    ///
+    /// Peer cannot answer hash request.
+    HashReject(HashRequest),
+}
+
+/// Range of merkle tree hashes of file (BEP 52).
+#[derive(Debug, PartialEq, Clone)]
+pub struct HashRequest {
+    /// Root of merkle tree of file
+    pub pieces_root: MerkleHash,
+    /// Layer of requested hashes, 0 is the layer of 16 KiB blocks
+    pub base_layer: u32,
+    /// Index of first hash in base layer, multiple of `length`
+    pub index: u32,
+    /// Count of hashes, power of two
+    pub length: u32,
+    /// Count of layers with uncle hashes, which are required to verify hashes
+    pub proof_layers: u32,
+}
+
+impl HashRequest {
+    pub(crate) const LENGTH: usize = 48;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_slice(&self.pieces_root);
+        buf.put_u32(self.base_layer);
+        buf.put_u32(self.index);
+        buf.put_u32(self.length);
+        buf.put_u32(self.proof_layers);
+    }
 }
 
 impl Display for Message {
@@ -94,6 +136,9 @@ impl Display for Message {
                 begin,
                 block,
             } => write!(f, "Piece({}, {}, [{}])", index, begin, block.len()),
+            Message::Hashes { request, hashes } => {
+                write!(f, "Hashes({:?}, [{}])", request, hashes.len())
+            }
             _ => write!(f, "{:?}", self),
         }
     }
@@ -107,6 +152,8 @@ pub enum MessageCodecError {
     IoError(std::io::Error),
     #[fail(display = "Couldn't parse incoming frame: {}", _0)]
     ParseError(String),
+    #[fail(display = "Frame of {} bytes exceeds limit of 1 MiB", _0)]
+    FrameTooLarge(u32),
 }
 
 impl From<std::io::Error> for MessageCodecError {
@@ -121,6 +168,10 @@ impl From<std::io::Error> for MessageCodecError {
 //     }
 // }
 
+/// Max length of incoming frame, large enough for bitfield of 8M pieces, peers announcing
+/// longer frames are rejected before anything is buffered.
+pub const MAX_FRAME_LENGTH: u32 = 1 << 20;
+
 #[derive(Default)]
 pub struct MessageCodec;
 
@@ -129,6 +180,13 @@ impl Decoder for MessageCodec {
     type Error = MessageCodecError;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if buf.len() >= 4 {
+            let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+            if len > MAX_FRAME_LENGTH {
+                return Err(MessageCodecError::FrameTooLarge(len));
+            }
+        }
+
         let (consumed, f) = match parser_message(buf) {
             Err(e) => {
                 if e.is_incomplete() {
@@ -229,6 +287,28 @@ impl Encoder<Message> for MessageCodec {
                 buf.put_u8(9);
                 buf.put_u16(port);
             }
+            Message::HashRequest(request) => {
+                buf.reserve(5 + HashRequest::LENGTH);
+                buf.put_u32(1 + HashRequest::LENGTH as u32);
+                buf.put_u8(21);
+                request.encode(buf);
+            }
+            Message::Hashes { request, hashes } => {
+                let length = 1 + HashRequest::LENGTH + hashes.len() * SHA256_SIZE;
+                buf.reserve(4 + length);
+                buf.put_u32(length as u32);
+                buf.put_u8(22);
+                request.encode(buf);
+                for hash in hashes {
+                    buf.put_slice(&hash);
+                }
+            }
+            Message::HashReject(request) => {
+                buf.reserve(5 + HashRequest::LENGTH);
+                buf.put_u32(1 + HashRequest::LENGTH as u32);
+                buf.put_u8(23);
+                request.encode(buf);
+            }
         }
         Ok(())
     }
@@ -326,4 +406,65 @@ mod tests {
     fn encode_port() {
         encode_message(&[0, 0, 0, 3, 9, 0, 101], Message::Port(101));
     }
+
+    fn hash_request_bytes(length: u8, id: u8) -> Vec<u8> {
+        let mut bytes = vec![0, 0, 0, length, id];
+        bytes.extend_from_slice(&[7; SHA256_SIZE]);
+        bytes.extend_from_slice(&[0, 0, 0, 2, 0, 0, 0, 4, 0, 0, 0, 2, 0, 0, 0, 1]);
+        bytes
+    }
+
+    fn hash_request() -> HashRequest {
+        HashRequest {
+            pieces_root: [7; SHA256_SIZE],
+            base_layer: 2,
+            index: 4,
+            length: 2,
+            proof_layers: 1,
+        }
+    }
+
+    #[test]
+    fn encode_hash_messages() {
+        encode_message(
+            &hash_request_bytes(49, 21),
+            Message::HashRequest(hash_request()),
+        );
+        encode_message(
+            &hash_request_bytes(49, 23),
+            Message::HashReject(hash_request()),
+        );
+
+        let mut hashes = hash_request_bytes(113, 22);
+        hashes.extend_from_slice(&[1; SHA256_SIZE]);
+        hashes.extend_from_slice(&[2; SHA256_SIZE]);
+        encode_message(
+            &hashes,
+            Message::Hashes {
+                request: hash_request(),
+                hashes: vec![[1; SHA256_SIZE], [2; SHA256_SIZE]],
+            },
+        );
+    }
+
+    #[test]
+    fn decode_frame_limit() {
+        let mut message_codec = MessageCodec;
+
+        let mut buf = BytesMut::from(&[0, 0, 0, 5, 4, 0, 0][..]);
+        assert_eq!(message_codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(&[0, 10]);
+        assert_eq!(
+            message_codec.decode(&mut buf).unwrap(),
+            Some(Message::Have { piece_index: 10 })
+        );
+
+        let mut buf = BytesMut::new();
+        buf.put_u32(MAX_FRAME_LENGTH + 1);
+        buf.put_u8(5);
+        assert!(matches!(
+            message_codec.decode(&mut buf),
+            Err(MessageCodecError::FrameTooLarge(len)) if len == MAX_FRAME_LENGTH + 1
+        ));
+    }
 }
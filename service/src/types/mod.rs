@@ -4,17 +4,24 @@ use crate::errors::{RsbtError, TryFromBencode};
 
 mod config;
 #[macro_use]
-mod bencode;
+pub(crate) mod bencode;
 pub mod info;
+pub mod merkle;
 pub mod message;
 pub mod peer;
 pub mod properties;
 pub mod torrent;
 pub mod udp_tracker;
 
-pub use bencode::{BencodeBlob, BencodeValue};
-pub use config::{Config, Settings};
+pub use bencode::{
+    bytes as bencode_bytes, from_bencode, from_bencode_blob, to_bencode, BencodeBlob, BencodeValue,
+};
+pub use config::{AlternativeLimits, Config, Limits, Preallocation, Settings, StorageBackend};
 pub use properties::Properties;
 
 pub(crate) const HANDSHAKE_PREFIX: [u8; 28] =
     *b"\x13BitTorrent protocol\x00\x00\x00\x00\x00\x00\x00\x00";
+
+/// Reserved bit of handshake, which tells that v2 protocol is supported (BEP 52).
+pub(crate) const HANDSHAKE_V2_RESERVED_BYTE: usize = 27;
+pub(crate) const HANDSHAKE_V2_RESERVED_BIT: u8 = 0x10;
@@ -2,42 +2,71 @@ use super::*;
 use crate::parser::parse_handshake;
 use crate::SHA1_SIZE;
 
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use std::fmt;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Peer {
     pub ip: IpAddr,
+    #[serde(rename = "peer id")]
     pub peer_id: Option<String>,
     pub port: u16,
 }
 
-try_from_bencode!(Peer,
-    normal: (
-        "ip" => ip,
-        "port" => port
-    ),
-    optional: (
-        "peer id" => peer_id
-    )
-);
-
-impl TryFrom<BencodeBlob> for Vec<Peer> {
-    type Error = TryFromBencode;
-
-    fn try_from(blob: BencodeBlob) -> Result<Self, Self::Error> {
-        match blob.value {
-            BencodeValue::String(s) => Ok(s
-                .chunks_exact(6)
+impl TryFrom<&[u8]> for Peer {
+    type Error = RsbtError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Ok(from_bencode(value)?)
+    }
+}
+
+impl TryFrom<Vec<u8>> for Peer {
+    type Error = RsbtError;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        value.as_slice().try_into()
+    }
+}
+
+/// Peers of tracker response, either compact string of IPv4 addresses with ports (BEP 23) or
+/// list of dictionaries.
+pub(crate) fn deserialize_peers<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Peer>, D::Error> {
+    struct PeersVisitor;
+
+    impl<'de> Visitor<'de> for PeersVisitor {
+        type Value = Vec<Peer>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("compact peers or list of peers")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+            Ok(v.chunks_exact(6)
                 .map(|peer| Peer {
                     ip: IpAddr::V4(Ipv4Addr::new(peer[0], peer[1], peer[2], peer[3])),
                     port: u16::from(peer[4]) * 256u16 + u16::from(peer[5]),
                     peer_id: None,
                 })
-                .collect()),
-            BencodeValue::List(l) => Ok(l.into_iter().map(|x| x.try_into().unwrap()).collect()),
-            _ => Err(TryFromBencode::NotDictionary),
+                .collect())
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut peers = vec![];
+            while let Some(peer) = seq.next_element()? {
+                peers.push(peer);
+            }
+            Ok(peers)
         }
     }
+
+    deserializer.deserialize_any(PeersVisitor)
 }
 
 impl From<SocketAddr> for Peer {
@@ -1,10 +1,19 @@
 use super::*;
+use crate::{Bandwidth, DiskIo, ReadCache, DEFAULT_DISK_JOB_CAPACITY};
+use flat_storage_file::FileHandles;
 
 use std::{
     net::{IpAddr, Ipv4Addr},
     path::PathBuf,
+    sync::Arc,
 };
 
+/// Default size of read cache in MiB
+const DEFAULT_READ_CACHE_SIZE: usize = 64;
+
+/// Default count of files kept open by file storage backend
+const DEFAULT_MAX_OPEN_FILES: usize = 64;
+
 #[derive(Debug)]
 pub struct Properties {
     pub compact: Option<bool>,
@@ -20,13 +29,32 @@ pub struct Properties {
     pub save_to: PathBuf,
     /// Storage path
     pub storage: PathBuf,
+    /// Directory for files in progress, download path if not set
+    pub incomplete_dir: Option<PathBuf>,
+    /// Files in progress are named with `.part` suffix
+    pub part_suffix: bool,
+    /// Directory, which files may be shared by creating torrents besides download path
+    pub share_dir: Option<PathBuf>,
     /// Config path
     pub config_dir: PathBuf,
+    /// Default storage backend for torrent data
+    pub storage_backend: StorageBackend,
+    /// Default preallocation of torrent files
+    pub preallocation: Preallocation,
+    /// Bandwidth limits
+    pub bandwidth: Arc<Bandwidth>,
+    /// Disk I/O pool shared by all torrents
+    pub disk_io: Arc<DiskIo>,
+    /// Cache of pieces read for peers and streaming
+    pub read_cache: Arc<ReadCache>,
+    /// Files kept open by file storage backend, shared by all torrents
+    pub file_handles: Arc<FileHandles>,
 }
 
 impl From<(Settings, PathBuf)> for Properties {
     fn from(value: (Settings, PathBuf)) -> Self {
         let config = value.0.config;
+        let limits = value.0.limits;
         let config_dir = value.1;
         let (save_to, storage) = match (
             config.save_to.map(PathBuf::from),
@@ -46,7 +74,32 @@ impl From<(Settings, PathBuf)> for Properties {
             port_max: config.port_max,
             save_to,
             storage,
+            incomplete_dir: config.incomplete_dir.map(PathBuf::from),
+            part_suffix: config.part_suffix.unwrap_or_default(),
+            share_dir: config.share_dir.map(PathBuf::from),
             config_dir,
+            storage_backend: config.storage_backend.unwrap_or_default(),
+            preallocation: config.preallocation.unwrap_or_default(),
+            bandwidth: Arc::new(Bandwidth::new(limits)),
+            disk_io: Arc::new(DiskIo::with_capacity(
+                config.disk_io_threads.unwrap_or_else(num_cpus::get),
+                config.disk_io_queue.unwrap_or(DEFAULT_DISK_JOB_CAPACITY),
+            )),
+            read_cache: Arc::new(ReadCache::new(
+                config.read_cache_size.unwrap_or(DEFAULT_READ_CACHE_SIZE) * 1024 * 1024,
+            )),
+            file_handles: Arc::new(FileHandles::new(
+                config.max_open_files.unwrap_or(DEFAULT_MAX_OPEN_FILES),
+            )),
         }
     }
 }
+
+impl Properties {
+    /// Directories, which files may be shared by creating torrents: download path first.
+    pub fn share_roots(&self) -> Vec<PathBuf> {
+        std::iter::once(self.save_to.clone())
+            .chain(self.share_dir.clone())
+            .collect()
+    }
+}
@@ -1,27 +1,44 @@
+use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
 
 use super::*;
 
-use crate::types::info::{TorrentInfo, TorrentInfoRaw};
-use crate::types::peer::Peer;
-use crate::SHA1_SIZE;
+use crate::types::info::{TorrentInfo, TorrentInfoRaw, TorrentVersion};
+use crate::types::peer::{deserialize_peers, Peer};
+use crate::{SHA1_SIZE, SHA256_SIZE};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Torrent {
+    /// Source of `.torrent` file
+    #[serde(skip)]
     pub raw: Vec<u8>,
+    #[serde(rename = "announce")]
     pub announce_url: String,
+    #[serde(rename = "announce-list")]
     pub announce_list: Option<Vec<Vec<String>>>,
+    #[serde(rename = "creation date")]
     pub creation_date: Option<i64>,
     pub info: BencodeBlob,
+    #[serde(rename = "piece layers")]
+    pub piece_layers: Option<BencodeBlob>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Deserialize)]
 pub struct TrackerAnnounce {
     /// Interval to reannounce in seconds
     pub interval: i64,
+    #[serde(deserialize_with = "deserialize_peers")]
     pub peers: Vec<Peer>,
 }
 
+/// Response of tracker, which rejected announce.
+#[derive(Deserialize)]
+struct TrackerFailure {
+    #[serde(rename = "failure reason")]
+    failure_reason: Option<String>,
+}
+
 impl Torrent {
     pub fn info_sha1_hash(&self) -> [u8; SHA1_SIZE] {
         Sha1::digest(self.info.source.as_slice())[..]
@@ -29,31 +46,77 @@ impl Torrent {
             .expect("20 bytes array expected from Sha1 calculation")
     }
 
-    pub fn info(&self) -> Result<TorrentInfo, RsbtError> {
-        self.info
-            .clone()
+    pub fn info_sha256_hash(&self) -> [u8; SHA256_SIZE] {
+        Sha256::digest(self.info.source.as_slice())[..]
+            .try_into()
+            .expect("32 bytes array expected from Sha256 calculation")
+    }
+
+    /// Info hash, which identifies torrent in handshakes and trackers.
+    ///
+    /// It is SHA-1 hash for v1 and hybrid torrents, so hybrid torrents join v1 swarm,
+    /// and SHA-256 hash truncated to 20 bytes for v2 torrents. Hybrid torrents are announced
+    /// with [`Torrent::info_hash_v2`] too, to join v2 swarm as well.
+    pub fn info_hash(&self, version: TorrentVersion) -> [u8; SHA1_SIZE] {
+        match version {
+            TorrentVersion::V1 | TorrentVersion::Hybrid => self.info_sha1_hash(),
+            TorrentVersion::V2 => self.info_hash_v2(),
+        }
+    }
+
+    /// SHA-256 info hash truncated to 20 bytes, which is used by v2 peers.
+    pub fn info_hash_v2(&self) -> [u8; SHA1_SIZE] {
+        self.info_sha256_hash()[..SHA1_SIZE]
             .try_into()
-            .map(|x: TorrentInfoRaw| x.into())
+            .expect("20 bytes array expected from truncated Sha256")
+    }
+
+    pub fn info(&self) -> Result<TorrentInfo, RsbtError> {
+        let raw: TorrentInfoRaw = self.info.clone().try_into()?;
+        Ok(TorrentInfo::from_raw(raw, self.piece_layers.clone())?)
+    }
+}
+
+impl TryFrom<&[u8]> for Torrent {
+    type Error = RsbtError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let torrent = from_bencode(value)?;
+        Ok(Torrent {
+            raw: value.to_vec(),
+            ..torrent
+        })
+    }
+}
+
+impl TryFrom<Vec<u8>> for Torrent {
+    type Error = RsbtError;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        value.as_slice().try_into()
+    }
+}
+
+impl TryFrom<&[u8]> for TrackerAnnounce {
+    type Error = RsbtError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let blob: BencodeBlob = value.try_into()?;
+        let failure: TrackerFailure = from_bencode_blob(blob.clone())?;
+        if let Some(failure_reason) = failure.failure_reason {
+            return Err(RsbtError::FailureReason(failure_reason));
+        }
+        Ok(from_bencode_blob(blob)?)
     }
 }
 
-try_from_bencode!(Torrent,
-    normal: ("announce" => announce_url),
-    optional: (
-        "announce-list" => announce_list,
-        "creation date" => creation_date
-    ),
-    bencode: ("info" => info),
-    raw: (raw)
-);
-
-try_from_bencode!(TrackerAnnounce,
-    normal: (
-        "interval" => interval,
-        "peers" => peers
-    ),
-    failure: "failure reason"
-);
+impl TryFrom<Vec<u8>> for TrackerAnnounce {
+    type Error = RsbtError;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        value.as_slice().try_into()
+    }
+}
 
 pub fn parse_torrent(buf: &[u8]) -> Result<Torrent, RsbtError> {
     let torrent = buf.try_into()?;
@@ -1,5 +1,5 @@
 use super::*;
-use crate::{app::TorrentProcess, parser::parser_udp_tracker};
+use crate::{app::TorrentProcess, parser::parser_udp_tracker, SHA1_SIZE};
 use bytes::{Buf, BufMut, BytesMut};
 use failure::Fail;
 use nom::Offset;
@@ -45,6 +45,7 @@ impl UdpTrackerRequest {
         connection_id: i64,
         properties: Arc<Properties>,
         torrent_process: Arc<TorrentProcess>,
+        info_hash: [u8; SHA1_SIZE],
     ) -> Self {
         let left = torrent_process.info.len() as i64;
 
@@ -52,7 +53,7 @@ impl UdpTrackerRequest {
             connection_id,
             transaction_id: random(),
             data: UdpTrackerRequestData::Announce {
-                info_hash: torrent_process.hash_id,
+                info_hash,
                 peer_id: crate::PEER_ID,
                 downloaded: 0,
                 uploaded: 0,
@@ -1,6 +1,6 @@
 use flat_storage::FlatStorageFile as TorrentInfoFile;
 use rsbt_service::types::torrent::Torrent;
-use rsbt_service::RsbtError;
+use rsbt_service::{RsbtError, TorrentInfoError};
 
 use std::convert::TryInto;
 use std::path::PathBuf;
@@ -18,42 +18,52 @@ fn parse_plan_9_torrent() -> Result<(), RsbtError> {
         &vec![
             TorrentInfoFile {
                 length: 383_971,
+                pad: false,
                 path: "Plan_9_from_Outer_Space_1959.asr.js".into(),
             },
             TorrentInfoFile {
                 length: 51637,
+                pad: false,
                 path: "Plan_9_from_Outer_Space_1959.asr.srt".into(),
             },
             TorrentInfoFile {
                 length: 346_429,
+                pad: false,
                 path: "Plan_9_from_Outer_Space_1959.gif".into(),
             },
             TorrentInfoFile {
                 length: 56_478_797,
+                pad: false,
                 path: "Plan_9_from_Outer_Space_1959.mp3".into(),
             },
             TorrentInfoFile {
                 length: 758_756_235,
+                pad: false,
                 path: "Plan_9_from_Outer_Space_1959.mp4".into(),
             },
             TorrentInfoFile {
                 length: 390_383_680,
+                pad: false,
                 path: "Plan_9_from_Outer_Space_1959.ogv".into(),
             },
             TorrentInfoFile {
                 length: 11287,
+                pad: false,
                 path: "Plan_9_from_Outer_Space_1959.png".into(),
             },
             TorrentInfoFile {
                 length: 293_299_508,
+                pad: false,
                 path: "Plan_9_from_Outer_Space_1959_512kb.mp4".into(),
             },
             TorrentInfoFile {
                 length: 4675,
+                pad: false,
                 path: "Plan_9_from_Outer_Space_1959_meta.xml".into(),
             },
             TorrentInfoFile {
                 length: 3209,
+                pad: false,
                 path: "__ia_thumb.jpg".into(),
             },
         ]
@@ -92,3 +102,33 @@ fn parse_ferris_torrent() -> Result<(), RsbtError> {
 
     Ok(())
 }
+
+#[test]
+fn reject_path_traversal_torrent() -> Result<(), RsbtError> {
+    let torrent_bytes = include_bytes!("path-traversal.torrent");
+    let torrent: Torrent = torrent_bytes.to_vec().try_into()?;
+
+    match torrent.info() {
+        Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::UnsafePath(path))) => {
+            assert_eq!(path, vec!["..", "..", ".bashrc"])
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn reject_absolute_path_torrent() -> Result<(), RsbtError> {
+    let torrent_bytes = include_bytes!("absolute-path.torrent");
+    let torrent: Torrent = torrent_bytes.to_vec().try_into()?;
+
+    match torrent.info() {
+        Err(RsbtError::InvalidTorrentInfo(TorrentInfoError::UnsafePath(path))) => {
+            assert_eq!(path, vec!["/etc/passwd"])
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    Ok(())
+}